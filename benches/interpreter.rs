@@ -0,0 +1,64 @@
+// Run with `cargo bench`. Criterion keeps the last run's results in target/criterion and reports
+// how much each benchmark changed since then
+use std::fs;
+use std::hint::black_box;
+use criterion::{criterion_group, criterion_main, Criterion};
+use chip8::chip::{Chip, ChipType};
+use chip8::display::{Display, DisplayType};
+
+fn chip() -> Chip {
+    let mut chip = Chip::headless(1200, ChipType::CHIP8);
+    chip.i = 0x300;
+    chip.registers = [0x12, 0x34, 0x56, 0x78, 0x9A, 0xBC, 0xDE, 0xF0, 1, 2, 3, 4, 5, 6, 7, 8];
+    chip
+}
+
+fn decode(criterion: &mut Criterion) {
+    let chip = chip();
+    criterion.bench_function("decode", |bencher| bencher.iter(|| chip.decode(black_box(0xD125))));
+}
+
+// One instruction from each of the busier opcode classes
+fn execute(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("execute");
+    let instructions = [
+        ("6XNN LD", 0x6A42),
+        ("7XNN ADD", 0x7A01),
+        ("8XY4 ADD", 0x8124),
+        ("ANNN LD I", 0xA300),
+        ("DXYN DRW", 0xD125),
+        ("FX33 LD B", 0xF233),
+        ("FX65 LD [I]", 0xF765)
+    ];
+    for (name, instruction) in instructions {
+        let mut chip = chip();
+        group.bench_function(name, |bencher| bencher.iter(|| {
+            // FX65 moves I on, so it's put back each time
+            chip.i = 0x300;
+            let decoded_instruction = chip.decode(black_box(instruction));
+            chip.execute(decoded_instruction);
+        }));
+    }
+    group.finish();
+}
+
+fn draw_sprite(criterion: &mut Criterion) {
+    let mut group = criterion.benchmark_group("draw_sprite");
+    let sprite = vec![0xF0, 0x90, 0xF0, 0x90, 0xF0, 0xFF, 0x81, 0x81, 0x81, 0xFF, 0xAA, 0x55, 0xAA, 0x55, 0xAA];
+    for (name, x, height) in [("8x5 byte aligned", 8, 5), ("8x15 unaligned", 13, 15)] {
+        let mut display = Display::new(DisplayType::Terminal);
+        group.bench_function(name, |bencher| bencher.iter(|| display.draw_sprite(black_box(x), black_box(10), &sprite[..height])));
+    }
+    group.finish();
+}
+
+// Whole frames of a real ROM, at the default speed
+fn run_frame(criterion: &mut Criterion) {
+    let rom = fs::read("test_roms/particle.ch8").unwrap();
+    let mut chip = Chip::headless(1200, ChipType::CHIP8);
+    chip.load_rom(&rom).unwrap();
+    criterion.bench_function("run_frame particle", |bencher| bencher.iter(|| chip.run_frame()));
+}
+
+criterion_group!(benches, decode, execute, draw_sprite, run_frame);
+criterion_main!(benches);
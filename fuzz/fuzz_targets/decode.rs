@@ -0,0 +1,20 @@
+// Decodes every address of arbitrary memory, checking the decode cache and the disassembler agree
+// with decoding each opcode directly
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use chip8::disassembler::disassemble;
+use chip8::instruction::{DecodeCache, Instruction};
+
+fuzz_target!(|memory: &[u8]| {
+    if memory.is_empty() {
+        return;
+    }
+
+    let mut cache = DecodeCache::new(memory.len());
+    for address in 0..memory.len() {
+        let opcode = u16::from_be_bytes([memory[address], memory[(address + 1) % memory.len()]]);
+        let instruction = Instruction::decode(opcode);
+        assert_eq!(cache.get(memory, address), instruction);
+        assert_eq!(disassemble(opcode).starts_with("DW"), instruction == Instruction::Unknown);
+    }
+});
@@ -0,0 +1,140 @@
+use std::fmt;
+use std::time::{Duration, Instant};
+use crate::chip::Chip;
+
+// Instructions grouped by their first nibble, with their mnemonics from the disassembler
+const OPCODE_CLASSES: [&str; 16] = [
+    "0NNN CLS, RET, SYS",
+    "1NNN JP",
+    "2NNN CALL",
+    "3XNN SE",
+    "4XNN SNE",
+    "5XY0 SE",
+    "6XNN LD",
+    "7XNN ADD",
+    "8XYN arithmetic and logic",
+    "9XY0 SNE",
+    "ANNN LD I",
+    "BNNN JP V0",
+    "CXNN RND",
+    "DXYN DRW",
+    "EXNN SKP, SKNP",
+    "FXNN timers, keys and memory"
+];
+
+pub struct BenchReport {
+    pub instructions: u64,
+    pub frames: u64,
+    pub elapsed: Duration,
+    pub opcode_classes: [u64; 16], // How many instructions of each class ran
+    pub stopped: Option<String> // Why the ROM stopped before all the instructions ran
+}
+
+// Runs frames back to back, without reading input, drawing or pacing them, until at least
+// `instructions` have run. Stops early if the ROM ends or waits for a key, since nothing will press one
+pub fn run(chip: &mut Chip, instructions: u64) -> BenchReport {
+    let mut opcode_classes = [0; 16];
+    let mut frames = 0;
+    let mut stopped = None;
+    let first_instruction = chip.instructions;
+
+    // Frames are timed all together, since reading the clock every frame would slow them down
+    let start = Instant::now();
+    while chip.instructions - first_instruction < instructions {
+        let summary = chip.run_frame_with(&mut |chip| opcode_classes[(chip.memory[chip.pc] >> 4) as usize] += 1);
+        frames += 1;
+
+        if let Some(halted) = summary.halted {
+            stopped = Some(halted.to_string());
+            break;
+        }
+        if summary.waiting_for_key {
+            stopped = Some(String::from("The ROM is waiting for a key"));
+            break;
+        }
+    }
+
+    BenchReport {
+        instructions: chip.instructions - first_instruction,
+        frames,
+        elapsed: start.elapsed(),
+        opcode_classes,
+        stopped
+    }
+}
+
+// Counts can be written with underscores, like 50_000_000
+pub fn parse_count(count: &str) -> Result<u64, String> {
+    count
+        .replace('_', "")
+        .parse()
+        .map_err(|_| format!("`{}` isn't a count, like 50_000_000", count))
+}
+
+impl BenchReport {
+    pub fn instructions_per_second(&self) -> f64 {
+        self.instructions as f64 / self.elapsed.as_secs_f64()
+    }
+
+    pub fn time_per_frame(&self) -> Duration {
+        self.elapsed.div_f64(self.frames.max(1) as f64)
+    }
+}
+
+impl fmt::Display for BenchReport {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(stopped) = &self.stopped {
+            writeln!(formatter, "Stopped early: {}", stopped)?;
+        }
+        writeln!(formatter, "Ran {} instructions in {} frames, taking {:.3?}", self.instructions, self.frames, self.elapsed)?;
+        writeln!(formatter, "{:.0} instructions per second", self.instructions_per_second())?;
+        writeln!(formatter, "{:?} per frame", self.time_per_frame())?;
+        writeln!(formatter)?;
+
+        writeln!(formatter, "Instructions by opcode class:")?;
+        let mut classes: Vec<(&str, u64)> = OPCODE_CLASSES.iter().copied().zip(self.opcode_classes).collect();
+        classes.sort_by_key(|(_, count)| std::cmp::Reverse(*count));
+        for (class, count) in classes.into_iter().filter(|(_, count)| *count > 0) {
+            let share = count as f64 / self.instructions.max(1) as f64 * 100.;
+            writeln!(formatter, "  {:<30} {:>12} {:>6.2}%", class, count, share)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip::ChipType;
+
+    #[test]
+    fn counts_instructions_by_opcode_class() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        // Adds to V0 and draws, forever
+        chip.load_rom(&[0x70, 0x01, 0xD0, 0x01, 0x12, 0x00]).unwrap();
+
+        let report = run(&mut chip, 95);
+        assert_eq!(report.frames, 10);
+        assert_eq!(report.instructions, 100);
+        assert_eq!(report.opcode_classes[0x7], 34);
+        assert_eq!(report.opcode_classes[0xD], 33);
+        assert_eq!(report.opcode_classes[0x1], 33);
+        assert_eq!(report.stopped, None);
+    }
+
+    #[test]
+    fn stops_when_the_rom_waits_for_a_key() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        chip.load_rom(&[0xF0, 0x0A]).unwrap();
+
+        let report = run(&mut chip, 1_000);
+        assert_eq!(report.instructions, 1);
+        assert_eq!(report.stopped.as_deref(), Some("The ROM is waiting for a key"));
+    }
+
+    #[test]
+    fn counts_can_have_underscores() {
+        assert_eq!(parse_count("50_000_000"), Ok(50_000_000));
+        assert!(parse_count("lots").is_err());
+    }
+}
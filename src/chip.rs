@@ -1,13 +1,25 @@
-use rand::Rng;
-use std::time::{Duration, SystemTime};
+use std::collections::BTreeMap;
 use clap::ValueEnum;
 use crate::font;
-use crate::display::{Display, DisplayType};
+use crate::display::{DISPLAY_SIZE, Display, DisplayType};
+use crate::expression::Expression;
+use crate::hooks::{Hooks, SpriteDraw, TimerKind};
+use crate::input::Input;
+use crate::instruction::{DecodeCache, Instruction, nibbles};
+use crate::quirks::{QuirkOverrides, Quirks};
+use crate::rom::{RomError, RomWarning};
 use crate::timer::Timer;
-use device_query::{DeviceQuery, DeviceState, Keycode};
+use crate::scheduler::FRAMES_PER_SECOND;
+use crate::snapshot::Snapshot;
+use crate::summary::{FrameSummary, Halt, SoundChange, StepResult};
+use crate::timing::{TimingMode, VipClock, instruction_cycles};
+use crate::watch::{AccessKind, Condition, MemoryAccess, StopReason, Watchpoint};
+use serde::Deserialize;
 use std::process;
 
-#[derive(ValueEnum, Clone, Debug)]
+#[allow(clippy::upper_case_acronyms)]
+#[derive(ValueEnum, Deserialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
 pub enum ChipType {
     CHIP8,
     SCHIP
@@ -15,50 +27,174 @@ pub enum ChipType {
 
 const FONT_ADDR: usize = 0x050;
 const ROM_ADDR: usize = 0x200;
-const TIMER_HZ: u8 = 60;
 
-pub struct DecodedInstruction {
-    nibbles: [u8; 4],
-    nn: u8,
-    nnn: usize
-}
+const STATE_VERSION: u8 = 2;
+// The version, memory, registers, stack, stack level, I, PC, timers, the keys FX0A is waiting on,
+// the instruction count, VIP cycles, CXNN's generator and the display
+pub const STATE_SIZE: usize = 1 + 4096 + 16 + 32 * 2 + 1 + 2 + 2 + 2 + 3 + 8 + 8 + 8 + DISPLAY_SIZE;
 
+// Cloning gives a chip that runs on its own from the same state, for trying things out. The copy
+// is detached from the keyboard and starts without hooks, but keeps exit_on_halt, so turn that
+// off before running a copy of a chip that exits. For going back and forth on one chip, snapshot
+// and restore are cheaper
+#[derive(Clone)]
 pub struct Chip {
-    pub memory: [u8; 4096],
+    pub memory: [u8; 4096], // Written through write_memory, so the decode cache sees the change
+    decode_cache: DecodeCache,
     pub registers: [u8; 16],
     pub stack: [usize; 32],
     pub stack_level: usize,
     pub display: Display,
     pub delay_timer: Timer,
     pub sound_timer: Timer,
+    pub input: Input,
+    keys_down_while_waiting: Option<Vec<u8>>, // Set while FX0A waits for a key to be released
     pub i: usize,
     pub pc: usize,
     pub target_ips: u128,
-    pub chip_type: ChipType
+    pub timing: TimingMode,
+    pub instructions: u64, // Executed since the chip was created, for measuring speed
+    vip_clock: VipClock,
+    random: u64, // The state of CXNN's generator, so it can be snapshotted and seeded
+    pub chip_type: ChipType,
+    pub quirk_overrides: QuirkOverrides,
+    pub breakpoints: BTreeMap<usize, Option<Expression>>, // Breakpoints with a condition only stop when it's true
+    pub watchpoints: Vec<Watchpoint>,
+    pub conditions: Vec<Condition>,
+    pub stop_reason: Option<StopReason>, // Why the last frame stopped early, until execution carries on
+    skip_breakpoint_at: Option<usize>,
+    accesses: Vec<MemoryAccess>, // Made by the last instruction, if there are watchpoints
+    pub exit_on_halt: bool, // Turned off when the process isn't ours to exit, like in a libretro core
+    pub halted: Option<Halt>, // Why the ROM ended, if it did without exiting. Nothing runs after that
+    pub hooks: Hooks
+}
+
+// A frame part way through, for callers that need the chip back between its instructions
+pub(crate) struct FrameProgress {
+    summary: FrameSummary,
+    sounding: bool,
+    instructions_left: u128,
+    started: bool,
+    stopped: bool
 }
 
 impl Chip {
     pub fn new(target_ips: u128, chip_type: ChipType, display_type: DisplayType) -> Self {
         let mut chip = Chip {
             memory: [0; 4096],
+            decode_cache: DecodeCache::new(4096),
             stack: [0; 32],
             stack_level: 0,
+            input: Input::new(&display_type),
+            keys_down_while_waiting: None,
             display: Display::new(display_type),
             registers: [0; 16],
-            delay_timer: Timer::new(TIMER_HZ),
-            sound_timer: Timer::new(TIMER_HZ),
+            delay_timer: Timer::new(),
+            sound_timer: Timer::new(),
             i: 0,
             pc: 0,
             target_ips,
-            chip_type
+            timing: TimingMode::Ips,
+            instructions: 0,
+            vip_clock: VipClock::default(),
+            random: random_seed(),
+            chip_type,
+            quirk_overrides: QuirkOverrides::default(),
+            breakpoints: BTreeMap::new(),
+            watchpoints: vec![],
+            conditions: vec![],
+            stop_reason: None,
+            skip_breakpoint_at: None,
+            accesses: vec![],
+            exit_on_halt: true,
+            halted: None,
+            hooks: Hooks::default()
         };
 
+        chip.reset();
+        chip
+    }
+
+    // Reads no keyboard and draws nowhere, and stops instead of exiting when the ROM ends. For
+    // running inside something else, like a libretro frontend or a benchmark
+    pub fn headless(target_ips: u128, chip_type: ChipType) -> Self {
+        let mut chip = Chip::new(target_ips, chip_type, DisplayType::Terminal);
+        chip.input = Input::detached();
+        chip.exit_on_halt = false;
+        chip
+    }
+
+    // Clears everything a ROM could have changed, ready to load one again
+    pub fn reset(&mut self) {
+        self.memory = [0; 4096];
         let font = font::get_font();
         for (offset, byte) in font.iter().enumerate() {
-            chip.memory[FONT_ADDR + offset] = *byte;
+            self.memory[FONT_ADDR + offset] = *byte;
         }
+        self.decode_cache.clear();
+
+        self.registers = [0; 16];
+        self.stack = [0; 32];
+        self.stack_level = 0;
+        self.i = 0;
+        self.pc = 0;
+        self.delay_timer.set(0);
+        self.sound_timer.set(0);
+        self.keys_down_while_waiting = None;
+        self.stop_reason = None;
+        self.skip_breakpoint_at = None;
+        self.halted = None;
+        self.vip_clock = VipClock::default();
+        self.display.clear();
+    }
 
-        chip
+    pub fn quirks(&self) -> Quirks {
+        self.quirk_overrides.apply(Quirks::from(&self.chip_type))
+    }
+
+    // Gives back the terminal if we took it over, since process::exit skips destructors
+    pub fn exit(&mut self, code: i32) -> ! {
+        self.input.restore();
+        self.display.restore_terminal();
+        process::exit(code);
+    }
+
+    pub fn exit_with_message(&mut self, message: &str) -> ! {
+        self.input.restore();
+        self.display.restore_terminal();
+        println!("{}", message);
+        process::exit(1);
+    }
+
+    // The ROM can't carry on, so the emulator exits unless that's turned off
+    fn halt(&mut self, halt: Halt) {
+        if self.exit_on_halt {
+            match &halt {
+                Halt::Error(message) => self.exit_with_message(message),
+                Halt::Exited => self.exit(1)
+            }
+        }
+        self.halted = Some(halt);
+    }
+
+    // Addresses past the end of memory wrap around to the start, so I and PC can never index past it
+    fn read_byte(&self, address: usize) -> u8 {
+        self.memory[address % self.memory.len()]
+    }
+
+    fn write_byte(&mut self, address: usize, value: u8) {
+        let address = address % self.memory.len();
+        self.memory[address] = value;
+        self.decode_cache.invalidate(address, 1);
+    }
+
+    fn advance_pc(&mut self) {
+        self.pc = (self.pc + 2) % self.memory.len();
+    }
+
+    // Where the running instruction starts, since PC has already moved past it
+    fn instruction_address(&self) -> usize {
+        (self.pc + self.memory.len() - 2) % self.memory.len()
     }
 
     fn clear_display(&mut self) {
@@ -67,12 +203,14 @@ impl Chip {
 
     fn handle_return(&mut self) {
         if self.stack_level == 0 {
-            println!("Can't return from empty stack");
-            process::exit(1);
+            self.halt(Halt::Error(String::from("Can't return from empty stack")));
+            return;
         }
-        self.stack_level = self.stack_level - 1; // stack_level is set to next empty slot in stack,
-                                                 // so go back one level to get the last used slot
+        self.stack_level -= 1; // stack_level is set to next empty slot in stack,
+                               // so go back one level to get the last used slot
+        let address = self.instruction_address();
         self.pc = self.stack[self.stack_level];
+        self.hooks.return_(address, self.pc);
     }
 
     fn jump(&mut self, address: usize) {
@@ -80,201 +218,129 @@ impl Chip {
     }
 
     fn call_at(&mut self, address: usize) {
+        if self.stack_level == self.stack.len() {
+            self.halt(Halt::Error(String::from("Can't call with a full stack")));
+            return;
+        }
         self.stack[self.stack_level] = self.pc;
-        self.stack_level = self.stack_level + 1;
+        self.stack_level += 1;
+        self.hooks.call(self.instruction_address(), address);
         self.pc = address;
     }
 
     fn set_vx_rand(&mut self, x: u8, seed: u8) {
-        let rand_number = rand::thread_rng().gen_range(0..255);
+        let rand_number = self.random_byte();
         self.registers[x as usize] = rand_number & seed;
     }
 
+    // Xorshift, which is plenty for games and small enough to copy with every snapshot
+    fn random_byte(&mut self) -> u8 {
+        self.random ^= self.random << 13;
+        self.random ^= self.random >> 7;
+        self.random ^= self.random << 17;
+        (self.random >> 56) as u8
+    }
+
     //Draw sprite at coord (x, y) that is 8 pixels wide and the height arg tall
-    async fn draw(&mut self, x: u8, y:u8, height: u8) {
+    fn draw(&mut self, x: u8, y:u8, height: u8) {
         let x_index = self.registers[x as usize] as usize;
         let y_index = self.registers[y as usize] as usize;
 
         self.registers[0xF] = 0; // Clear pixel_flip flag
 
-        // Collect sprite to draw on screen
-        let mut sprite: Vec<u8> = vec![];
-        for row in 0..height {
-            // Each row is a byte in memory, so to get the next row, go to the next memory addr
-            sprite.push(self.memory[self.i + row as usize]);
+        // Each row of the sprite is a byte in memory, starting at I
+        self.record_access(self.i, height as usize, AccessKind::Read);
+        let mut sprite = [0; 15];
+        for (row, byte) in sprite.iter_mut().enumerate().take(height as usize) {
+            *byte = self.read_byte(self.i + row);
         }
 
         // Let display actually draw the sprite
-        let did_flip_pixel_to_off = self.display.draw_sprite(x_index, y_index, height, sprite).await;
+        let did_flip_pixel_to_off = self.display.draw_sprite(x_index, y_index, &sprite[..height as usize]);
         self.registers[0xF] = if did_flip_pixel_to_off { 1 } else { 0 };
+        self.hooks.draw(SpriteDraw { x: x_index as u8, y: y_index as u8, height, collision: did_flip_pixel_to_off });
     }
 
     fn skip_if_key_press(&mut self, x: u8) {
-        let device_state = DeviceState::new();
-        let keys: Vec<Keycode> = device_state.get_keys();
-        let vx_keycode = self.hex_to_keycode(self.registers[x as usize]);
+        let keys = self.input.pressed_keys();
 
-        if keys.contains(&vx_keycode) {
-            self.pc = self.pc + 2;
+        if keys.contains(&self.registers[x as usize]) {
+            self.advance_pc();
         }
     }
 
     fn skip_if_not_key_press(&mut self, x: u8) {
-        let device_state = DeviceState::new();
-        let keys: Vec<Keycode> = device_state.get_keys();
-        let vx_keycode = self.hex_to_keycode(self.registers[x as usize]);
+        let keys = self.input.pressed_keys();
 
-        if !keys.contains(&vx_keycode) {
-            self.pc = self.pc + 2;
-        }
-    }
-
-    fn hex_to_keycode(&self, keypress: u8) -> Keycode {
-        match keypress {
-            0x1 => Keycode::Key1,
-            0x2 => Keycode::Key2,
-            0x3 => Keycode::Key3,
-            0x4 => Keycode::Q,
-            0x5 => Keycode::W,
-            0x6 => Keycode::E,
-            0x7 => Keycode::A,
-            0x8 => Keycode::S,
-            0x9 => Keycode::D,
-            0xA => Keycode::Z,
-            0x0 => Keycode::X,
-            0xB => Keycode::C,
-            0xC => Keycode::Key4,
-            0xD => Keycode::R,
-            0xE => Keycode::F,
-            0xF => Keycode::V,
-            _ => {
-                Keycode::D
-            }
-        }
-    }
-
-    fn keycode_to_hex(&self, keypress: &Keycode) -> Option<u8> {
-        match keypress {
-            Keycode::Key1 => Some(0x1),
-            Keycode::Key2 => Some(0x2),
-            Keycode::Key3 => Some(0x3),
-            Keycode::Q => Some(0x4),
-            Keycode::W => Some(0x5),
-            Keycode::E => Some(0x6),
-            Keycode::A => Some(0x7),
-            Keycode::S => Some(0x8),
-            Keycode::D => Some(0x9),
-            Keycode::Z => Some(0xA),
-            Keycode::X => Some(0x0),
-            Keycode::C => Some(0xB),
-            Keycode::Key4 => Some(0xC),
-            Keycode::R => Some(0xD),
-            Keycode::F => Some(0xE),
-            Keycode::V => Some(0xF),
-            _ => {
-                None
-            }
+        if !keys.contains(&self.registers[x as usize]) {
+            self.advance_pc();
         }
     }
 
+    // Waits for a key to be pressed and released. Rather than blocking the frame, the instruction
+    // runs again until a key that was down is let go
     fn await_then_store_keypress(&mut self, x: u8) {
-        let device_state = DeviceState::new();
-        let mut keys_already_down = device_state.get_keys();
-        loop {
-            let keys_down_now = device_state.get_keys();
+        let keys_down_now = self.input.pressed_keys();
+        if let Some(keys_already_down) = self.keys_down_while_waiting.take() {
             for key_already_down in &keys_already_down {
-                if keys_down_now.contains(&key_already_down) {
-                    continue;
-                } else if let Some(pressed_key) = self.keycode_to_hex(&key_already_down) {
-                    self.registers[x as usize] = pressed_key;
+                if !keys_down_now.contains(key_already_down) {
+                    self.registers[x as usize] = *key_already_down;
                     return;
                 }
-
             }
-            keys_already_down = keys_down_now;
         }
-    }
-
-    fn fetch(&mut self) -> u16 {
-        let first_byte = self.memory[self.pc] as u16;
-        let second_byte = self.memory[1 + self.pc] as u16;
-
-        let shifted_first_byte = first_byte << 8; // 0xAB becomes 0xAB00
-        let combined_bytes = shifted_first_byte + second_byte;
-
-        self.pc = self.pc + 2;
 
-        combined_bytes
+        self.keys_down_while_waiting = Some(keys_down_now);
+        self.pc = (self.pc + self.memory.len() - 2) % self.memory.len();
     }
 
-    fn decode(&self, instruction: u16) -> DecodedInstruction {
-        let first_nibble = (instruction >> 12) as u8;
-        let second_nibble = ((instruction & 0x0F00) >> 8) as u8;
-        let third_nibble = ((instruction & 0x00F0) >> 4) as u8;
-        let fourth_nibble = (instruction & 0x000F) as u8;
-        let nibbles = [first_nibble, second_nibble, third_nibble, fourth_nibble];
-
-        let nn = (instruction & 0x00FF) as u8;
-        let nnn = (instruction & 0x0FFF) as usize;
-
-        DecodedInstruction { nibbles, nn, nnn }
+    pub fn decode(&self, instruction: u16) -> Instruction {
+        Instruction::decode(instruction)
     }
 
-    async fn execute(&mut self, decoded_instruction: DecodedInstruction) {
-        match decoded_instruction.nibbles {
-            [0, 0, 0x0, 0x0] => process::exit(1),
-            [0, 0, 0xE, 0x0] => self.clear_display(),
-            [0, 0, 0xE, 0xE] => self.handle_return(),
-            [0, _, _, _] => { },
-            [1, _, _, _] => self.jump(decoded_instruction.nnn),
-            [2, _, _, _] => self.call_at(decoded_instruction.nnn as usize),
-            [3, x, _, _] => {
-                if self.registers[x as usize] == decoded_instruction.nn {
-                    self.pc += 2
-                }
-            },
-            [4, x, _, _] => {
-                if self.registers[x as usize] != decoded_instruction.nn {
-                    self.pc += 2
-                }
-            },
-            [5, x, y, 0x0] => {
-                if self.registers[x as usize] == self.registers[y as usize] {
-                    self.pc += 2
-                }
-            },
-            [6, x, _, _] => self.registers[x as usize] = decoded_instruction.nn,
-            [7, x, _, _] => self.registers[x as usize] = {
-                let sum = self.registers[x as usize] as u16 + decoded_instruction.nn as u16;
+    pub fn execute(&mut self, instruction: Instruction) {
+        match instruction {
+            Instruction::Exit => self.halt(Halt::Exited),
+            Instruction::ClearScreen => self.clear_display(),
+            Instruction::Return => self.handle_return(),
+            Instruction::MachineCall => (),
+            Instruction::Jump(address) => self.jump(address as usize),
+            Instruction::Call(address) => self.call_at(address as usize),
+            Instruction::SkipIfEqual(x, nn) if self.registers[x as usize] == nn => self.advance_pc(),
+            Instruction::SkipIfNotEqual(x, nn) if self.registers[x as usize] != nn => self.advance_pc(),
+            Instruction::SkipIfRegistersEqual(x, y) if self.registers[x as usize] == self.registers[y as usize] => self.advance_pc(),
+            Instruction::Load(x, nn) => self.registers[x as usize] = nn,
+            Instruction::Add(x, nn) => self.registers[x as usize] = {
+                let sum = self.registers[x as usize] as u16 + nn as u16;
                 if sum > 255 {
                     (sum - 255 - 1) as u8
                 } else {
                     sum as u8
                 }
             },
-            [8, x, y, 0] => self.registers[x as usize] = self.registers[y as usize],
-            [8, x, y, 1] => {
-                self.registers[x as usize] = self.registers[x as usize] | self.registers[y as usize];
+            Instruction::Copy(x, y) => self.registers[x as usize] = self.registers[y as usize],
+            Instruction::Or(x, y) => {
+                self.registers[x as usize] |= self.registers[y as usize];
 
-                if matches!(self.chip_type, ChipType::CHIP8) {
+                if self.quirks().vf_reset {
                     self.registers[0xF] = 0;
                 }
             },
-            [8, x, y, 2] => {
-                self.registers[x as usize] = self.registers[x as usize] & self.registers[y as usize];
+            Instruction::And(x, y) => {
+                self.registers[x as usize] &= self.registers[y as usize];
 
-                if matches!(self.chip_type, ChipType::CHIP8) {
+                if self.quirks().vf_reset {
                     self.registers[0xF] = 0;
                 }
             },
-            [8, x, y, 3] => {
-                self.registers[x as usize] = self.registers[x as usize] ^ self.registers[y as usize];
+            Instruction::Xor(x, y) => {
+                self.registers[x as usize] ^= self.registers[y as usize];
 
-                if matches!(self.chip_type, ChipType::CHIP8) {
+                if self.quirks().vf_reset {
                     self.registers[0xF] = 0;
                 }
             },
-            [8, x, y, 4] => self.registers[x as usize] = {
+            Instruction::AddRegisters(x, y) => self.registers[x as usize] = {
                 let mut sum = self.registers[x as usize] as u16 + self.registers[y as usize] as u16;
                 if sum > 255 {
                     sum = sum - 255 - 1;
@@ -285,7 +351,7 @@ impl Chip {
                     sum as u8
                 }
             },
-            [8, x, y, 5] => self.registers[x as usize] = {
+            Instruction::Subtract(x, y) => self.registers[x as usize] = {
                 if self.registers[x as usize] >= self.registers[y as usize] {
                     let diff = self.registers[x as usize] - self.registers[y as usize];
                     self.registers[0xF] = 1;
@@ -299,8 +365,8 @@ impl Chip {
                     diff
                 }
             },
-            [8, x, y, 6] => {
-                if matches!(self.chip_type, ChipType::CHIP8) {
+            Instruction::ShiftRight(x, y) => {
+                if !self.quirks().shifting {
                     self.registers[x as usize] = self.registers[y as usize];
                 }
                 let vx = self.registers[x as usize];
@@ -309,7 +375,7 @@ impl Chip {
                 self.registers[x as usize] = vx >> 1;
                 self.registers[0xF] = lsb;
             },
-            [8, x, y, 7] => self.registers[x as usize] = {
+            Instruction::SubtractReversed(x, y) => self.registers[x as usize] = {
                 if self.registers[y as usize] >= self.registers[x as usize] {
                     let diff = self.registers[y as usize] - self.registers[x as usize];
                     self.registers[0xF] = 1;
@@ -320,8 +386,8 @@ impl Chip {
                     0xFF - positive_diff + 1
                 }
             },
-            [8, x, y, 0xE] => {
-                if matches!(self.chip_type, ChipType::CHIP8) {
+            Instruction::ShiftLeft(x, y) => {
+                if !self.quirks().shifting {
                     self.registers[x as usize] = self.registers[y as usize];
                 }
                 let vx = self.registers[x as usize];
@@ -330,93 +396,396 @@ impl Chip {
                 self.registers[x as usize] = vx << 1;
                 self.registers[0xF] = msb;
             },
-            [9, x, y, 0] => {
-                if self.registers[x as usize] != self.registers[y as usize] {
-                    self.pc += 2
-                }
+            Instruction::SkipIfRegistersNotEqual(x, y) if self.registers[x as usize] != self.registers[y as usize] => self.advance_pc(),
+            Instruction::LoadI(address) => self.i = address as usize,
+            Instruction::JumpWithOffset(x, address) => {
+                let register_index = if self.quirks().jumping { x as usize } else { 0 };
+                self.pc = (self.registers[register_index] as usize + address as usize) % self.memory.len()
+            },
+            Instruction::Random(x, nn) => self.set_vx_rand(x, nn),
+            Instruction::Draw(x, y, n) => self.draw(x, y, n),
+            Instruction::SkipIfKey(x) => self.skip_if_key_press(x),
+            Instruction::SkipIfNotKey(x) => self.skip_if_not_key_press(x),
+            Instruction::LoadDelayTimer(x) => self.registers[x as usize] = self.delay_timer.get(),
+            Instruction::WaitForKey(x) => self.await_then_store_keypress(x),
+            Instruction::SetDelayTimer(x) => {
+                self.delay_timer.set(self.registers[x as usize]);
+                self.hooks.timer_set(TimerKind::Delay, self.registers[x as usize]);
             },
-            [0xA, _, _, _] => self.i = decoded_instruction.nnn,
-            [0xB, x, _, _] => {
-                let register_index = if matches!(self.chip_type, ChipType::SCHIP) { x as usize } else { 0 };
-                self.pc = self.registers[register_index] as usize + decoded_instruction.nnn
+            Instruction::SetSoundTimer(x) => {
+                self.sound_timer.set(self.registers[x as usize]);
+                self.hooks.timer_set(TimerKind::Sound, self.registers[x as usize]);
             },
-            [0xC, x, _, _] => self.set_vx_rand(x, decoded_instruction.nn),
-            [0xD, x, y, n] => self.draw(x, y, n).await,
-            [0xE, x, 0x9, 0xE] => self.skip_if_key_press(x),
-            [0xE, x, 0xA, 0x1] => self.skip_if_not_key_press(x),
-            [0xF, x, 0x0, 0x7] => self.registers[x as usize] = self.delay_timer.get(),
-            [0xF, x, 0x0, 0xA] => self.await_then_store_keypress(x),
-            [0xF, x, 0x1, 0x5] => self.delay_timer.set(self.registers[x as usize]),
-            [0xF, x, 0x1, 0x8] => self.sound_timer.set(self.registers[x as usize]),
-            [0xF, x, 0x1, 0xE] => self.i += self.registers[x as usize] as usize,
-            [0xF, x, 0x2, 0x9] => self.i = FONT_ADDR + (self.registers[x as usize] as usize * font::FONT_SIZE),
-            [0xF, x, 0x3, 0x3] => {
+            // I is a 16 bit register, so it wraps instead of growing past what save states and GDB hold
+            Instruction::AddToI(x) => self.i = (self.i + self.registers[x as usize] as usize) & 0xFFFF,
+            Instruction::LoadFontCharacter(x) => self.i = FONT_ADDR + (self.registers[x as usize] as usize * font::FONT_SIZE),
+            Instruction::StoreDecimal(x) => {
                 let number = self.registers[x as usize];
                 let ones = number % 10;
                 let tens = number / 10 % 10;
                 let hundreds = number / 10 / 10 % 10;
 
-                self.memory[self.i] = hundreds;
-                self.memory[1 + self.i] = tens;
-                self.memory[2 + self.i] = ones;
+                self.record_access(self.i, 3, AccessKind::Write);
+                self.write_byte(self.i, hundreds);
+                self.write_byte(1 + self.i, tens);
+                self.write_byte(2 + self.i, ones);
             },
-            [0xF, x, 0x5, 0x5] => {
+            Instruction::StoreRegisters(x) => {
+                self.record_access(self.i, x as usize + 1, AccessKind::Write);
                 for register in 0..=x as usize {
-                    let address = self.i + register;
-                    self.memory[address] = self.registers[register];
+                    self.write_byte(self.i + register, self.registers[register]);
                 }
 
                 // CHIP-8 updates I to the end of the stored registers
-                if matches!(self.chip_type, ChipType::CHIP8) {
-                    self.i = self.i + x as usize + 1;
+                if self.quirks().memory {
+                    self.i = (self.i + x as usize + 1) & 0xFFFF;
                 }
             },
-            [0xF, x, 0x6, 0x5] => {
+            Instruction::LoadRegisters(x) => {
+                self.record_access(self.i, x as usize + 1, AccessKind::Read);
                 for register in 0..=x as usize {
-                    let address = self.i + register;
-                    self.registers[register] = self.memory[address]
+                    self.registers[register] = self.read_byte(self.i + register);
                 }
 
-                if matches!(self.chip_type, ChipType::CHIP8) {
-                    self.i = self.i + x as usize + 1;
+                if self.quirks().memory {
+                    self.i = (self.i + x as usize + 1) & 0xFFFF;
                 }
             },
             _ => ()
         }
     }
 
-    pub async fn step(&mut self) {
-        let start_time = SystemTime::now();
+    // Runs one frame's worth of instructions, then ticks the timers. The caller reads input, paces
+    // frames and draws the display between them, going by what the summary says happened
+    pub fn run_frame(&mut self) -> FrameSummary {
+        self.run_frame_with(&mut |_| ())
+    }
 
-        // Run loop
-        let instruction = self.fetch();
-        let decoded_instruction = self.decode(instruction);
-        self.execute(decoded_instruction).await;
+    // Like run_frame, calling `before_instruction` before each instruction runs
+    pub fn run_frame_with(&mut self, before_instruction: &mut dyn FnMut(&mut Chip)) -> FrameSummary {
+        let mut frame = self.start_frame();
+        while self.next_instruction(&mut frame) {
+            before_instruction(self);
+            self.run_instruction(&mut frame);
+        }
+        self.finish_frame(frame)
+    }
 
-        // Decrement timers if needed
-        self.delay_timer.check_decrement();
-        self.sound_timer.check_decrement();
+    pub(crate) fn start_frame(&mut self) -> FrameProgress {
+        if self.timing == TimingMode::Vip {
+            self.vip_clock.start_frame();
+        }
+        FrameProgress {
+            summary: FrameSummary::default(),
+            sounding: self.sound_timer.get() > 0,
+            instructions_left: self.instructions_per_frame(),
+            started: false,
+            stopped: self.halted.is_some()
+        }
+    }
+
+    // Whether the frame runs another instruction. A breakpoint stops the frame before it
+    pub(crate) fn next_instruction(&mut self, frame: &mut FrameProgress) -> bool {
+        if frame.stopped {
+            return false;
+        }
+        let more = match self.timing {
+            // FX0A ends the frame, but only once it's run, so it can see the keys
+            TimingMode::Ips => frame.instructions_left > 0 && (!frame.started || self.keys_down_while_waiting.is_none()),
+            // DXYN waits for the display interrupt, so only the first instruction of a frame can draw
+            TimingMode::Vip => !self.vip_clock.frame_done()
+                && self.keys_down_while_waiting.is_none()
+                && (self.memory[self.pc] >> 4 != 0xD || self.vip_clock.in_vblank())
+        };
+        if more && self.check_breakpoint() {
+            frame.stopped = true;
+            return false;
+        }
+        more
+    }
+
+    // Runs the instruction next_instruction said was next. A watch stops the frame after it
+    pub(crate) fn run_instruction(&mut self, frame: &mut FrameProgress) {
+        frame.summary.add(self.step());
+        frame.instructions_left = frame.instructions_left.saturating_sub(1);
+        frame.started = true;
+        frame.stopped = self.check_watches() || self.halted.is_some();
+    }
 
-        // Sleep timer if executing too fast to hit maximum instructions per second
-        let duration_time = Duration::from_micros(1000000 / self.target_ips as u64);
-        let minimum_time = start_time.checked_add(duration_time).expect("Cant get step time");
-        while minimum_time.max(SystemTime::now()) == minimum_time{ }
+    // Ticks the timers, unless the frame was stopped part way
+    pub(crate) fn finish_frame(&mut self, frame: FrameProgress) -> FrameSummary {
+        let mut summary = frame.summary;
+        if self.halted.is_some() && !frame.started {
+            return summary;
+        }
+        if !frame.stopped {
+            self.delay_timer.tick();
+            self.sound_timer.tick();
+            self.display.end_frame();
+            self.hooks.frame_end();
+        }
+        summary.sound = SoundChange::between(frame.sounding, self.sound_timer.get() > 0);
+        summary.stop_reason = self.stop_reason.clone();
+        summary
     }
 
-    pub fn load_rom(&mut self, rom: &Vec<u8>) {
+    // Carrying on from a breakpoint runs the instruction it stopped at
+    fn check_breakpoint(&mut self) -> bool {
+        if self.breakpoints.is_empty() || self.skip_breakpoint_at.take() == Some(self.pc) {
+            return false;
+        }
+
+        let hit = match self.breakpoints.get(&self.pc) {
+            Some(Some(condition)) => condition.is_true(self),
+            Some(None) => true,
+            None => false
+        };
+        if hit {
+            self.skip_breakpoint_at = Some(self.pc);
+            self.stop_reason = Some(StopReason::Breakpoint(self.pc));
+        }
+        hit
+    }
+
+    fn check_watches(&mut self) -> bool {
+        for access in &self.accesses {
+            if let Some(address) = self.watchpoints.iter().find_map(|watchpoint| watchpoint.hit(access)) {
+                self.stop_reason = Some(StopReason::Watchpoint { address, kind: access.kind });
+            }
+        }
+
+        if !self.conditions.is_empty() {
+            let now_true: Vec<bool> = self.conditions.iter().map(|condition| condition.expression.is_true(self)).collect();
+            for (condition, now_true) in self.conditions.iter_mut().zip(now_true) {
+                if now_true && !condition.was_true {
+                    self.stop_reason = Some(StopReason::Condition(condition.expression.to_string()));
+                }
+                condition.was_true = now_true;
+            }
+        }
+
+        self.stop_reason.is_some()
+    }
+
+    fn record_access(&mut self, start: usize, length: usize, kind: AccessKind) {
+        if !self.watchpoints.is_empty() {
+            self.accesses.push(MemoryAccess { start, length, kind });
+        }
+        self.hooks.memory_access(MemoryAccess { start, length, kind });
+    }
+
+    // FX0A stops the frame while it waits, and runs again each frame until a key is pressed and released
+    pub fn waiting_for_key(&self) -> bool {
+        self.keys_down_while_waiting.is_some()
+    }
+
+    pub fn instructions_per_frame(&self) -> u128 {
+        (self.target_ips / FRAMES_PER_SECOND as u128).max(1)
+    }
+
+    pub fn step(&mut self) -> StepResult {
+        if self.halted.is_some() {
+            return StepResult::default();
+        }
+        let sounding = self.sound_timer.get() > 0;
+        self.stop_reason = None;
+        self.accesses.clear();
+        let address = self.pc;
+        let instruction = self.decode_cache.get(&self.memory, address);
+        self.hooks.before_instruction(address, instruction);
+        if self.timing == TimingMode::Vip {
+            // Charged before executing, since the cost depends on the registers the instruction reads
+            let opcode = u16::from_be_bytes([self.memory[self.pc], self.read_byte(self.pc + 1)]);
+            self.vip_clock.charge(instruction_cycles(nibbles(opcode), &self.registers, self.i));
+        }
+        self.advance_pc();
+        self.execute(instruction);
+        self.hooks.after_instruction(address, instruction);
+        self.instructions += 1;
+
+        StepResult {
+            screen_changed: self.display.take_changed(),
+            sound: SoundChange::between(sounding, self.sound_timer.get() > 0),
+            waiting_for_key: self.waiting_for_key(),
+            halted: self.halted.clone()
+        }
+    }
+
+    // ROMs are loaded at 0x200, so they can fill the rest of memory
+    pub fn max_rom_size(&self) -> usize {
+        self.memory.len() - ROM_ADDR
+    }
+
+    pub fn load_rom(&mut self, rom: &[u8]) -> Result<Vec<RomWarning>, RomError> {
+        if rom.is_empty() {
+            return Err(RomError::Empty);
+        }
+        if rom.len() > self.max_rom_size() {
+            return Err(RomError::TooLarge { size: rom.len(), max_size: self.max_rom_size(), chip_type: self.chip_type.clone() });
+        }
+
         self.pc = ROM_ADDR;
-        for (offset, byte) in rom.iter().enumerate() {
-            self.memory[self.pc + offset] = *byte;
+        self.write_memory(ROM_ADDR, rom);
+
+        let mut warnings = vec![];
+        if !rom.len().is_multiple_of(2) {
+            warnings.push(RomWarning::OddLength(rom.len()));
+        }
+        Ok(warnings)
+    }
+
+    // For anything outside the ROM that changes memory, like a debugger poking bytes
+    pub fn write_memory(&mut self, address: usize, bytes: &[u8]) {
+        self.memory[address..address + bytes.len()].copy_from_slice(bytes);
+        self.decode_cache.invalidate(address, bytes.len());
+    }
+
+    // For when memory may have changed behind the chip's back, like through a pointer to it
+    pub fn forget_decoded(&mut self) {
+        self.decode_cache.clear();
+    }
+
+    // Everything a running ROM can change, for save states. Settings, breakpoints and the like
+    // aren't included
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut state = Vec::with_capacity(STATE_SIZE);
+        state.push(STATE_VERSION);
+        state.extend_from_slice(&self.memory);
+        state.extend_from_slice(&self.registers);
+        for address in self.stack {
+            state.extend_from_slice(&(address as u16).to_le_bytes());
+        }
+        state.push(self.stack_level as u8);
+        state.extend_from_slice(&(self.i as u16).to_le_bytes());
+        state.extend_from_slice(&(self.pc as u16).to_le_bytes());
+        state.push(self.delay_timer.get());
+        state.push(self.sound_timer.get());
+        let waiting_keys = self.keys_down_while_waiting.as_ref().map(|keys| keys.iter().fold(0u16, |mask, key| mask | 1 << key));
+        state.push(waiting_keys.is_some() as u8);
+        state.extend_from_slice(&waiting_keys.unwrap_or(0).to_le_bytes());
+        state.extend_from_slice(&self.instructions.to_le_bytes());
+        state.extend_from_slice(&self.vip_clock.cycles.to_le_bytes());
+        state.extend_from_slice(&self.random.to_le_bytes());
+        state.extend(self.display.pixels().iter().map(|pixel| *pixel as u8));
+        state
+    }
+
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), String> {
+        if state.len() != STATE_SIZE {
+            return Err(format!("Save states are {} bytes, not {}", STATE_SIZE, state.len()));
+        }
+        if state[0] != STATE_VERSION {
+            return Err(format!("Save state version {} isn't supported", state[0]));
+        }
+
+        let mut rest = &state[1..];
+        let mut take = |length: usize| {
+            let (taken, remaining) = rest.split_at(length);
+            rest = remaining;
+            taken
+        };
+        let word = |bytes: &[u8]| u16::from_le_bytes([bytes[0], bytes[1]]) as usize;
+        let long = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
+
+        self.memory.copy_from_slice(take(4096));
+        self.decode_cache.clear();
+        self.registers.copy_from_slice(take(16));
+        for address in self.stack.iter_mut() {
+            *address = word(take(2)) % self.memory.len();
+        }
+        self.stack_level = (take(1)[0] as usize).min(self.stack.len());
+        self.i = word(take(2));
+        self.pc = word(take(2)) % self.memory.len();
+        self.delay_timer.set(take(1)[0]);
+        self.sound_timer.set(take(1)[0]);
+        let waiting = take(1)[0] != 0;
+        let waiting_keys = word(take(2));
+        self.keys_down_while_waiting = waiting.then(|| (0..16).filter(|key| waiting_keys & 1 << key != 0).collect());
+        self.instructions = long(take(8));
+        self.vip_clock = VipClock::default();
+        self.vip_clock.cycles = long(take(8));
+        self.random = long(take(8)).max(1);
+        for (pixel_index, value) in take(DISPLAY_SIZE).iter().enumerate() {
+            self.display.set_pixel(pixel_index, *value != 0);
+        }
+
+        self.stop_reason = None;
+        self.skip_breakpoint_at = None;
+        self.halted = None;
+        Ok(())
+    }
+
+    // Makes CXNN give the same numbers each run, for reproducible runs and tests
+    pub fn seed_random(&mut self, seed: u64) {
+        self.random = seed.max(1);
+    }
+
+    // The state to come back to with restore, for trying different keys from the same point.
+    // Like save_state it leaves out settings, breakpoints and whatever's attached to the chip
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            memory: self.memory,
+            registers: self.registers,
+            stack: self.stack,
+            stack_level: self.stack_level,
+            plane: self.display.plane,
+            delay_timer: self.delay_timer.get(),
+            sound_timer: self.sound_timer.get(),
+            keys_down_while_waiting: self.keys_down_while_waiting.clone(),
+            i: self.i,
+            pc: self.pc,
+            instructions: self.instructions,
+            vip_clock: self.vip_clock.clone(),
+            random: self.random,
+            halted: self.halted.clone()
         }
     }
+
+    // Keys held with press_key stay held, since they're input rather than state
+    pub fn restore(&mut self, snapshot: &Snapshot) {
+        // Only instructions over memory that differs need decoding again
+        for address in 0..self.memory.len() {
+            if self.memory[address] != snapshot.memory[address] {
+                self.decode_cache.invalidate(address, 1);
+            }
+        }
+        self.memory = snapshot.memory;
+        self.registers = snapshot.registers;
+        self.stack = snapshot.stack;
+        self.stack_level = snapshot.stack_level;
+        self.display.set_plane(snapshot.plane);
+        self.delay_timer.set(snapshot.delay_timer);
+        self.sound_timer.set(snapshot.sound_timer);
+        self.keys_down_while_waiting = snapshot.keys_down_while_waiting.clone();
+        self.i = snapshot.i;
+        self.pc = snapshot.pc;
+        self.instructions = snapshot.instructions;
+        self.vip_clock = snapshot.vip_clock.clone();
+        self.random = snapshot.random;
+        self.halted = snapshot.halted.clone();
+        self.stop_reason = None;
+        self.skip_breakpoint_at = None;
+    }
 }
 
-#[cfg(test)]
-use crate::display::DISPLAY_SIZE;
+// Xorshift gets stuck at 0, so that's never a seed
+#[cfg(not(target_arch = "wasm32"))]
+fn random_seed() -> u64 {
+    rand::random::<u64>().max(1)
+}
+
+// rand gets its entropy through wasm-bindgen in the browser, which macroquad's page loader doesn't
+// provide, so macroquad's own generator is used there. The web build seeds it at startup
+#[cfg(target_arch = "wasm32")]
+fn random_seed() -> u64 {
+    (macroquad::rand::rand() as u64) << 32 | macroquad::rand::rand() as u64 | 1
+}
 
 #[cfg(test)]
 mod tests {
+    use std::sync::{Arc, Mutex};
     use super::*;
+    use crate::display::DISPLAY_HEIGHT;
 
     #[test]
     fn initial_memory_has_font_at_0x050() {
@@ -447,16 +816,16 @@ mod tests {
     }
 
     #[test]
-    fn fetch_gets_two_byte_instruction_and_increments_pc() {
+    fn step_runs_two_byte_instruction_and_increments_pc() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 30;
 
         chip.memory[30] = 0xAB;
         chip.memory[31] = 0xCD;
 
-        let instruction = chip.fetch();
+        chip.step();
 
-        assert_eq!(instruction, 0xABCD);
+        assert_eq!(chip.i, 0xBCD);
         assert_eq!(chip.pc, 32);
     }
 
@@ -467,12 +836,26 @@ mod tests {
 
         let decoded_instruction = chip.decode(instruction);
 
-        assert_eq!(decoded_instruction.nibbles[0], 0xA);
-        assert_eq!(decoded_instruction.nibbles[1], 0xB);
-        assert_eq!(decoded_instruction.nibbles[2], 0xC);
-        assert_eq!(decoded_instruction.nibbles[3], 0xD);
-        assert_eq!(decoded_instruction.nn, 0xCD);
-        assert_eq!(decoded_instruction.nnn, 0xBCD);
+        assert_eq!(decoded_instruction, Instruction::LoadI(0xBCD));
+    }
+
+    #[test]
+    fn self_modifying_code_runs_what_it_wrote() {
+        let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
+        // Runs 6000 once, then overwrites it with 6005 through FX55 and jumps back to it
+        chip.load_rom(&[0x60, 0x00, 0x60, 0x60, 0x61, 0x05, 0xA2, 0x00, 0xF1, 0x55, 0x12, 0x00]).unwrap();
+        for _ in 0..6 {
+            chip.step();
+        }
+        assert_eq!(chip.registers[0], 0x60);
+        chip.step();
+        assert_eq!(chip.registers[0], 5);
+
+        // Pokes from outside the ROM are seen too
+        chip.write_memory(0x201, &[0x09]);
+        chip.pc = 0x200;
+        chip.step();
+        assert_eq!(chip.registers[0], 9);
     }
 
     #[test]
@@ -506,14 +889,123 @@ mod tests {
         assert_eq!(chip.stack_level, 0);
     }
 
+    #[test]
+    fn vip_timing_waits_for_vblank_before_drawing() {
+        let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
+        chip.timing = TimingMode::Vip;
+        chip.load_rom(&[0x60, 0x01, 0xD0, 0x15, 0x12, 0x04]).unwrap();
+
+        chip.run_frame();
+        assert_eq!(chip.pc, ROM_ADDR + 2); // The draw waits for the next frame
+
+        chip.run_frame();
+        assert_eq!(chip.pc, ROM_ADDR + 4);
+    }
+
+    #[test]
+    fn ips_timing_runs_a_frame_of_instructions_then_ticks_timers() {
+        let mut chip = Chip::new(600, ChipType::CHIP8, DisplayType::Terminal);
+        chip.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap(); // Adds 1 to V0 forever
+        chip.delay_timer.set(5);
+
+        chip.run_frame();
+
+        assert_eq!(chip.registers[0], 5);
+        assert_eq!(chip.delay_timer.get(), 4);
+    }
+
+    #[test]
+    fn run_frame_stops_at_breakpoint_then_carries_on_past_it() {
+        let mut chip = Chip::new(600, ChipType::CHIP8, DisplayType::Terminal);
+        chip.load_rom(&[0x70, 0x01, 0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip.breakpoints.insert(0x202, None);
+        chip.delay_timer.set(5);
+
+        chip.run_frame();
+        assert_eq!(chip.stop_reason, Some(StopReason::Breakpoint(0x202)));
+        assert_eq!(chip.registers[0], 1);
+        assert_eq!(chip.delay_timer.get(), 5); // The frame didn't finish
+
+        chip.run_frame();
+        assert_eq!(chip.stop_reason, Some(StopReason::Breakpoint(0x202)));
+        assert_eq!(chip.registers[0], 3);
+    }
+
+    #[test]
+    fn conditional_breakpoint_only_stops_when_true() {
+        let mut chip = Chip::new(600, ChipType::CHIP8, DisplayType::Terminal);
+        chip.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip.breakpoints.insert(0x200, Some(Expression::parse("v0 == 3").unwrap()));
+
+        chip.run_frame();
+
+        assert_eq!(chip.stop_reason, Some(StopReason::Breakpoint(0x200)));
+        assert_eq!(chip.registers[0], 3);
+    }
+
+    #[test]
+    fn watchpoint_stops_after_fx33_writes_to_it() {
+        let mut chip = Chip::new(600, ChipType::CHIP8, DisplayType::Terminal);
+        chip.load_rom(&[0xA3, 0x00, 0xF0, 0x33, 0x12, 0x04]).unwrap();
+        chip.watchpoints.push(crate::watch::parse_watchpoint("302:w").unwrap());
+
+        chip.run_frame();
+
+        assert_eq!(chip.stop_reason, Some(StopReason::Watchpoint { address: 0x302, kind: AccessKind::Write }));
+        assert_eq!(chip.pc, 0x204);
+    }
+
+    #[test]
+    fn watchpoint_stops_after_sprite_is_read() {
+        let mut chip = Chip::new(600, ChipType::CHIP8, DisplayType::Terminal);
+        chip.load_rom(&[0xA3, 0x00, 0xD0, 0x05, 0x12, 0x04]).unwrap();
+        chip.watchpoints.push(crate::watch::parse_watchpoint("304:r").unwrap());
+
+        chip.run_frame();
+
+        assert_eq!(chip.stop_reason, Some(StopReason::Watchpoint { address: 0x304, kind: AccessKind::Read }));
+    }
+
+    #[test]
+    fn condition_stops_when_it_becomes_true() {
+        let mut chip = Chip::new(600, ChipType::CHIP8, DisplayType::Terminal);
+        chip.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip.conditions.push(crate::watch::parse_condition("v0 >= 2").unwrap());
+
+        chip.run_frame();
+        assert_eq!(chip.stop_reason, Some(StopReason::Condition(String::from("v0 >= 2"))));
+        assert_eq!(chip.registers[0], 2);
+
+        // Stays true, so it doesn't stop again
+        chip.run_frame();
+        assert_eq!(chip.stop_reason, None);
+    }
+
+    #[test]
+    fn reset_clears_state_but_keeps_font() {
+        let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
+        chip.load_rom(&[0x60, 0x01, 0x12, 0x02]).unwrap();
+        chip.run_frame();
+        chip.display.set_pixel(0, true);
+        chip.delay_timer.set(10);
+
+        chip.reset();
+
+        assert_eq!(chip.registers[0], 0);
+        assert_eq!(chip.memory[ROM_ADDR], 0);
+        assert_eq!(chip.memory[FONT_ADDR], font::get_font()[0]);
+        assert_eq!(chip.delay_timer.get(), 0);
+        assert!(!chip.display.get_pixel(0));
+    }
+
     #[test]
     fn load_rom() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         let rom: Vec<u8> = vec![0xD, 0xE, 0xA, 0xD, 0xB, 0xE, 0xE, 0xF];
 
-        chip.load_rom(&rom);
+        chip.load_rom(&rom).unwrap();
 
-        assert_eq!(chip.memory[ROM_ADDR + 0], 0xD);
+        assert_eq!(chip.memory[ROM_ADDR], 0xD);
         assert_eq!(chip.memory[ROM_ADDR + 1], 0xE);
         assert_eq!(chip.memory[ROM_ADDR + 2], 0xA);
         assert_eq!(chip.memory[ROM_ADDR + 3], 0xD);
@@ -523,152 +1015,189 @@ mod tests {
         assert_eq!(chip.memory[ROM_ADDR + 7], 0xF);
     }
 
-    #[tokio::test]
-    async fn test_00e0_clear_display() {
+    #[test]
+    fn load_rom_fills_rest_of_memory() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
-        chip.display.display = [true; DISPLAY_SIZE];
+        let rom: Vec<u8> = vec![0xAB; 4096 - ROM_ADDR];
+
+        assert_eq!(chip.load_rom(&rom), Ok(vec![]));
+        assert_eq!(chip.memory[4095], 0xAB);
+        assert_eq!(chip.pc, ROM_ADDR);
+    }
+
+    #[test]
+    fn load_rom_rejects_rom_too_large_for_memory() {
+        let mut chip = Chip::new(1200, ChipType::SCHIP, DisplayType::Terminal);
+        let rom: Vec<u8> = vec![0xAB; 4096 - ROM_ADDR + 1];
+
+        let error = chip.load_rom(&rom).unwrap_err();
+
+        assert_eq!(error, RomError::TooLarge { size: 3585, max_size: 3584, chip_type: ChipType::SCHIP });
+        assert_eq!(error.to_string(), "The ROM is 3585 bytes, but SCHIP ROMs can be at most 3584 bytes");
+        assert_eq!(chip.memory[ROM_ADDR], 0); // Nothing was loaded
+    }
+
+    #[test]
+    fn load_rom_rejects_empty_rom() {
+        let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
+
+        assert_eq!(chip.load_rom(&[]), Err(RomError::Empty));
+    }
+
+    #[test]
+    fn load_rom_warns_about_odd_length() {
+        let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
+
+        assert_eq!(chip.load_rom(&[0x00, 0xE0, 0x12]), Ok(vec![RomWarning::OddLength(3)]));
+        assert_eq!(chip.memory[ROM_ADDR + 2], 0x12);
+    }
+
+    #[test]
+    fn test_00e0_clear_display() {
+        let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
+        chip.display.plane = [u64::MAX; DISPLAY_HEIGHT];
 
         let decoded_instruction = chip.decode(0x00E0);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
-        assert_eq!(chip.display.display, [false; DISPLAY_SIZE]);
+        assert_eq!(chip.display.pixels(), [false; DISPLAY_SIZE]);
     }
 
-    #[tokio::test]
-    async fn test_00ee_return_from_subroutine() {
+    #[test]
+    fn test_00ee_return_from_subroutine() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 0x500;
         chip.stack[0] = 0x250;
         chip.stack_level = 1;
 
         let decoded_instruction = chip.decode(0x00EE);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0x250);
         assert_eq!(chip.stack_level, 0);
     }
 
-    #[tokio::test]
-    async fn test_1nnn_jump() {
+    #[test]
+    fn test_1nnn_jump() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 0x250;
 
         let decoded_instruction = chip.decode(0x1ABC);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0xABC);
     }
 
-    #[tokio::test]
-    async fn test_2nnn_call_subroutine_at_nnn() {
+    #[test]
+    fn test_2nnn_call_subroutine_at_nnn() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 0x250;
 
         let decoded_instruction = chip.decode(0x2ABC);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0xABC);
         assert_eq!(chip.stack[0], 0x250);
     }
 
-    #[tokio::test]
-    async fn test_3xnn_skip_if_vx_equal_nn_dont_skip() {
+    #[test]
+    fn test_3xnn_skip_if_vx_equal_nn_dont_skip() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 0x250;
         chip.registers[0xA] = 0xAA;
 
         let decoded_instruction = chip.decode(0x3A00);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0x250);
     }
 
-    #[tokio::test]
-    async fn test_3xnn_skip_if_vx_equal_nn_skip() {
+    #[test]
+    fn test_3xnn_skip_if_vx_equal_nn_skip() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 0x250;
         chip.registers[0xA] = 0xAA;
 
         let decoded_instruction = chip.decode(0x3AAA);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0x252);
     }
 
-    #[tokio::test]
-    async fn test_4xnn_skip_if_vx_not_equal_nn_dont_skip() {
+    #[test]
+    fn test_4xnn_skip_if_vx_not_equal_nn_dont_skip() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 0x250;
         chip.registers[0xA] = 0xAA;
 
         let decoded_instruction = chip.decode(0x4AAA);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0x250);
     }
 
-    #[tokio::test]
-    async fn test_4xnn_skip_if_vx_not_equal_nn_skip() {
+    #[test]
+    fn test_4xnn_skip_if_vx_not_equal_nn_skip() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 0x250;
         chip.registers[0xA] = 0xAA;
 
         let decoded_instruction = chip.decode(0x4A00);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0x252);
     }
 
-    #[tokio::test]
-    async fn test_5xy0_skip_if_vx_equal_vy_dont_skip() {
+    #[test]
+    fn test_5xy0_skip_if_vx_equal_vy_dont_skip() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 0x250;
         chip.registers[0xA] = 0xAA;
         chip.registers[0xB] = 0xBB;
 
         let decoded_instruction = chip.decode(0x5AB0);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0x250);
     }
 
-    #[tokio::test]
-    async fn test_5xy0_skip_if_vx_equal_vy_skip() {
+    #[test]
+    fn test_5xy0_skip_if_vx_equal_vy_skip() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 0x250;
         chip.registers[0xA] = 0xAA;
         chip.registers[0xB] = 0xAA;
 
         let decoded_instruction = chip.decode(0x5AB0);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0x252);
     }
 
-    #[tokio::test]
-    async fn test_6xnn_set_vx_to_nn_00() {
+    #[test]
+    fn test_6xnn_set_vx_to_nn_00() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.registers[0xA] = 0x0;
 
         let decoded_instruction = chip.decode(0x6A00);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[0xA], 0x00);
     }
 
-    #[tokio::test]
-    async fn test_6xnn_set_vx_to_nn_ff() {
+    #[test]
+    fn test_6xnn_set_vx_to_nn_ff() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.registers[0xA] = 0x0;
 
         let decoded_instruction = chip.decode(0x6AFF);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[0xA], 0xFF);
     }
 
-    #[tokio::test]
-    async fn test_7xnn_add_vx_and_nn() {
+    #[test]
+    fn test_7xnn_add_vx_and_nn() {
         let vx: usize = 0x3;
         let vf: usize = 0xF;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
@@ -676,15 +1205,15 @@ mod tests {
         chip.registers[vf] = 0x01;
 
         let decoded_instruction = chip.decode(0x730F); // n = 0x0F
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         // 0x10 + 0x0F = 0x1F
         assert_eq!(chip.registers[vx], 0x1F);
         assert_eq!(chip.registers[vf], 0x1); // Carry flag is unchanged
     }
 
-    #[tokio::test]
-    async fn test_7xnn_add_vx_and_nn_overflow() {
+    #[test]
+    fn test_7xnn_add_vx_and_nn_overflow() {
         let vx: usize = 0x3;
         let vf: usize = 0xF;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
@@ -692,15 +1221,15 @@ mod tests {
         chip.registers[vf] = 0x00;
 
         let decoded_instruction = chip.decode(0x73F0); // nn = 0xF0
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         // 0xF0 + 0xF0 = 0x1E0 --> only 8 bits, so its just 0xE0
         assert_eq!(chip.registers[vx], 0xE0);
         assert_eq!(chip.registers[vf], 0x0); // Carry flag is unchanged
     }
 
-    #[tokio::test]
-    async fn test_8xy0_set_vx_to_value_of_vy() {
+    #[test]
+    fn test_8xy0_set_vx_to_value_of_vy() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
@@ -708,14 +1237,14 @@ mod tests {
         chip.registers[vy] = 0x0F;
 
         let decoded_instruction = chip.decode(0x8AB0);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0x0F);
         assert_eq!(chip.registers[vy], 0x0F); // VY is unchanged
     }
 
-    #[tokio::test]
-    async fn test_8xy1_set_vx_to_vx_bitwise_or_vy_none() {
+    #[test]
+    fn test_8xy1_set_vx_to_vx_bitwise_or_vy_none() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
@@ -723,14 +1252,14 @@ mod tests {
         chip.registers[vy] = 0x00;
 
         let decoded_instruction = chip.decode(0x8AB1);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0x00);
         assert_eq!(chip.registers[vy], 0x00); // VY is unchanged
     }
 
-    #[tokio::test]
-    async fn test_8xy1_set_vx_to_vx_bitwise_or_vy_all() {
+    #[test]
+    fn test_8xy1_set_vx_to_vx_bitwise_or_vy_all() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
@@ -738,14 +1267,14 @@ mod tests {
         chip.registers[vy] = 0x0F;
 
         let decoded_instruction = chip.decode(0x8AB1);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0xFF);
         assert_eq!(chip.registers[vy], 0x0F); // VY is unchanged
     }
 
-    #[tokio::test]
-    async fn test_8xy2_set_vx_to_vx_bitwise_and_vy_none() {
+    #[test]
+    fn test_8xy2_set_vx_to_vx_bitwise_and_vy_none() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
@@ -753,14 +1282,14 @@ mod tests {
         chip.registers[vy] = 0x0F;
 
         let decoded_instruction = chip.decode(0x8AB2);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0x00);
         assert_eq!(chip.registers[vy], 0x0F); // VY is unchanged
     }
 
-    #[tokio::test]
-    async fn test_8xy2_set_vx_to_vx_bitwise_and_vy_some() {
+    #[test]
+    fn test_8xy2_set_vx_to_vx_bitwise_and_vy_some() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
@@ -768,14 +1297,14 @@ mod tests {
         chip.registers[vy] = 0x18;
 
         let decoded_instruction = chip.decode(0x8AB2);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0x10);
         assert_eq!(chip.registers[vy], 0x18); // VY is unchanged
     }
 
-    #[tokio::test]
-    async fn test_8xy2_set_vx_to_vx_bitwise_and_vy_all() {
+    #[test]
+    fn test_8xy2_set_vx_to_vx_bitwise_and_vy_all() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
@@ -783,14 +1312,14 @@ mod tests {
         chip.registers[vy] = 0xFF;
 
         let decoded_instruction = chip.decode(0x8AB2);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0xFF);
         assert_eq!(chip.registers[vy], 0xFF); // VY is unchanged
     }
 
-    #[tokio::test]
-    async fn test_8xy3_set_vx_to_vx_bitwise_xor_vy_none() {
+    #[test]
+    fn test_8xy3_set_vx_to_vx_bitwise_xor_vy_none() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
@@ -798,14 +1327,14 @@ mod tests {
         chip.registers[vy] = 0xFF;
 
         let decoded_instruction = chip.decode(0x8AB3);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0x00);
         assert_eq!(chip.registers[vy], 0xFF); // VY is unchanged
     }
 
-    #[tokio::test]
-    async fn test_8xy3_set_vx_to_vx_bitwise_xor_vy_some() {
+    #[test]
+    fn test_8xy3_set_vx_to_vx_bitwise_xor_vy_some() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
@@ -813,14 +1342,14 @@ mod tests {
         chip.registers[vy] = 0x18;
 
         let decoded_instruction = chip.decode(0x8AB3);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0xEA);
         assert_eq!(chip.registers[vy], 0x18); // VY is unchanged
     }
 
-    #[tokio::test]
-    async fn test_8xy3_set_vx_to_vx_bitwise_xor_vy_all() {
+    #[test]
+    fn test_8xy3_set_vx_to_vx_bitwise_xor_vy_all() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
@@ -828,14 +1357,14 @@ mod tests {
         chip.registers[vy] = 0x0F;
 
         let decoded_instruction = chip.decode(0x8AB3);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0xFF);
         assert_eq!(chip.registers[vy], 0x0F); // VY is unchanged
     }
 
-    #[tokio::test]
-    async fn test_8xy4_add_vx_and_vy() {
+    #[test]
+    fn test_8xy4_add_vx_and_vy() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let vf: usize = 0xF;
@@ -844,15 +1373,15 @@ mod tests {
         chip.registers[vy] = 0x0F;
 
         let decoded_instruction = chip.decode(0x8AB4);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0xFF);
         assert_eq!(chip.registers[vy], 0x0F); // VY is unchanged
         assert_eq!(chip.registers[vf], 0x0); // Carry flag is not set
     }
 
-    #[tokio::test]
-    async fn test_8xy4_add_vx_and_vy_overflow() {
+    #[test]
+    fn test_8xy4_add_vx_and_vy_overflow() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let vf: usize = 0xF;
@@ -861,7 +1390,7 @@ mod tests {
         chip.registers[vy] = 0x0F;
 
         let decoded_instruction = chip.decode(0x8AB4);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         // 0xFF + 0x0F = 0x10E --> u8 only has 8 bits, so it's 0xOE
         assert_eq!(chip.registers[vx], 0x0E);
@@ -869,8 +1398,8 @@ mod tests {
         assert_eq!(chip.registers[vf], 0x1); // Carry flag is set
     }
 
-    #[tokio::test]
-    async fn test_8xy5_subtract_vy_from_vx() {
+    #[test]
+    fn test_8xy5_subtract_vy_from_vx() {
         let vx = 0xA;
         let vy = 0xB;
         let vf = 0xF;
@@ -879,15 +1408,15 @@ mod tests {
         chip.registers[vy as usize] = 0x0F;
 
         let decoded_instruction = chip.decode(0x8AB5);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx as usize], 0xF0);
         assert_eq!(chip.registers[vy as usize], 0x0F); // VY is unchanged
         assert_eq!(chip.registers[vf as usize], 0x1); // Carry flag is set since no borrow
     }
 
-    #[tokio::test]
-    async fn test_8xy5_subtract_vy_from_vx_underflow() {
+    #[test]
+    fn test_8xy5_subtract_vy_from_vx_underflow() {
         let vx = 0xA;
         let vy = 0xB;
         let vf = 0xF;
@@ -896,7 +1425,7 @@ mod tests {
         chip.registers[vy as usize] = 0xFF;
 
         let decoded_instruction = chip.decode(0x8AB5);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         // 0x10F - 0xFF = 0x010 ---> 0x0F - 0xFF is the same, but have to carry.
         assert_eq!(chip.registers[vx as usize], 0x10);
@@ -904,8 +1433,8 @@ mod tests {
         assert_eq!(chip.registers[vf as usize], 0x0); // Carry flag no longer set due to the borrow
     }
 
-    #[tokio::test]
-    async fn test_8xy6_store_vy_least_sig_bit_into_vx_1_chip8() {
+    #[test]
+    fn test_8xy6_store_vy_least_sig_bit_into_vx_1_chip8() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let vf: usize = 0xF;
@@ -915,15 +1444,15 @@ mod tests {
         chip.registers[vf] = 0x00;
 
         let decoded_instruction = chip.decode(0x8AB6);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0b01111110);
         assert_eq!(chip.registers[vy], 0b11111101);
         assert_eq!(chip.registers[vf], 1);
     }
 
-    #[tokio::test]
-    async fn test_8xy6_store_vy_least_sig_bit_into_vf_0_chip8() {
+    #[test]
+    fn test_8xy6_store_vy_least_sig_bit_into_vf_0_chip8() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let vf: usize = 0xF;
@@ -933,15 +1462,15 @@ mod tests {
         chip.registers[vf] = 0x00;
 
         let decoded_instruction = chip.decode(0x8AB6);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0b01000001);
         assert_eq!(chip.registers[vy], 0b10000010);
         assert_eq!(chip.registers[vf], 0);
     }
 
-    #[tokio::test]
-    async fn test_8xy6_store_vx_least_sig_bit_into_vf_1_schip() {
+    #[test]
+    fn test_8xy6_store_vx_least_sig_bit_into_vf_1_schip() {
         // VY is completely ignored here
         let vx: usize = 0xA;
         let vy: usize = 0xB;
@@ -952,15 +1481,15 @@ mod tests {
         chip.registers[vf] = 0x00;
 
         let decoded_instruction = chip.decode(0x8AB6);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0b01111110);
         assert_eq!(chip.registers[vy], 0b00000000);
         assert_eq!(chip.registers[vf], 1);
     }
 
-    #[tokio::test]
-    async fn test_8xy6_store_vx_least_sig_bit_into_vf_0_schip() {
+    #[test]
+    fn test_8xy6_store_vx_least_sig_bit_into_vf_0_schip() {
         let vx: usize = 0xA;
         let vf: usize = 0xF;
         let mut chip = Chip::new(1200, ChipType::SCHIP, DisplayType::Terminal);
@@ -968,14 +1497,14 @@ mod tests {
         chip.registers[vf] = 0x00;
 
         let decoded_instruction = chip.decode(0x8AB6);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0b01000001);
         assert_eq!(chip.registers[vf], 0);
     }
 
-    #[tokio::test]
-    async fn test_8xy7_set_vx_to_vy_minux_vx() {
+    #[test]
+    fn test_8xy7_set_vx_to_vy_minux_vx() {
         let vx = 0xA;
         let vy = 0xB;
         let vf = 0xF;
@@ -984,15 +1513,15 @@ mod tests {
         chip.registers[vy as usize] = 0xFF;
 
         let decoded_instruction = chip.decode(0x8AB7);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx as usize], 0xF0);
         assert_eq!(chip.registers[vy as usize], 0xFF); // VY is unchanged
         assert_eq!(chip.registers[vf as usize], 0x1); // Carry flag set due to no borrow
     }
 
-    #[tokio::test]
-    async fn test_8xy7_set_vx_to_vy_minux_vx_underflow() {
+    #[test]
+    fn test_8xy7_set_vx_to_vy_minux_vx_underflow() {
         let vx = 0xA;
         let vy = 0xB;
         let vf = 0xF;
@@ -1001,15 +1530,15 @@ mod tests {
         chip.registers[vy as usize] = 0x0F;
 
         let decoded_instruction = chip.decode(0x8AB7);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx as usize], 0x10);
         assert_eq!(chip.registers[vy as usize], 0x0F); // VY is unchanged
         assert_eq!(chip.registers[vf as usize], 0x0); // Carry flag not set due to the borrow
     }
 
-    #[tokio::test]
-    async fn test_8xye_store_vx_most_sig_bit_into_vf_1_chip8() {
+    #[test]
+    fn test_8xye_store_vx_most_sig_bit_into_vf_1_chip8() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let vf: usize = 0xF;
@@ -1019,7 +1548,7 @@ mod tests {
         chip.registers[vf] = 0x00;
 
         let decoded_instruction = chip.decode(0x8ABE);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0b00000010);
         assert_eq!(chip.registers[vy], 0b10000001);
@@ -1028,8 +1557,8 @@ mod tests {
                                              //chip-8 impl only
     }
 
-    #[tokio::test]
-    async fn test_8xye_store_vx_most_sig_bit_into_vf_0_chip8() {
+    #[test]
+    fn test_8xye_store_vx_most_sig_bit_into_vf_0_chip8() {
         let vx: usize = 0xA;
         let vy: usize = 0xB;
         let vf: usize = 0xF;
@@ -1039,15 +1568,15 @@ mod tests {
         chip.registers[vf] = 0x00;
 
         let decoded_instruction = chip.decode(0x8ABE);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0b11111110);
         assert_eq!(chip.registers[vy], 0b01111111);
         assert_eq!(chip.registers[vf], 0x0);
     }
     
-    #[tokio::test]
-    async fn test_8xye_store_vx_most_sig_bit_into_vf_1_schip() {
+    #[test]
+    fn test_8xye_store_vx_most_sig_bit_into_vf_1_schip() {
         let vx: usize = 0xA;
         let vf: usize = 0xF;
         let mut chip = Chip::new(1200, ChipType::SCHIP, DisplayType::Terminal);
@@ -1055,7 +1584,7 @@ mod tests {
         chip.registers[vf] = 0x00;
 
         let decoded_instruction = chip.decode(0x8ABE);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0b00000010);
         assert_eq!(chip.registers[vf], 0x1); // Carry flag is unchanged
@@ -1063,8 +1592,8 @@ mod tests {
                                              //chip-8 impl only
     }
 
-    #[tokio::test]
-    async fn test_8xye_store_vx_most_sig_bit_into_vf_0_schip() {
+    #[test]
+    fn test_8xye_store_vx_most_sig_bit_into_vf_0_schip() {
         let vx: usize = 0xA;
         let vf: usize = 0xF;
         let mut chip = Chip::new(1200, ChipType::SCHIP, DisplayType::Terminal);
@@ -1072,51 +1601,51 @@ mod tests {
         chip.registers[vf] = 0x00;
 
         let decoded_instruction = chip.decode(0x8ABE);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 0b11111110);
         assert_eq!(chip.registers[vf], 0x0);
     }
 
-    #[tokio::test]
-    async fn test_9xy0_skip_if_vx_not_equal_vy_skip() {
+    #[test]
+    fn test_9xy0_skip_if_vx_not_equal_vy_skip() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 0x250;
         chip.registers[0xA] = 0xAA;
         chip.registers[0xB] = 0x00;
 
         let decoded_instruction = chip.decode(0x9AB0);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0x252);
     }
 
-    #[tokio::test]
-    async fn test_annn_set_i_to_nnn() {
+    #[test]
+    fn test_annn_set_i_to_nnn() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.i = 0;
 
         let decoded_instruction = chip.decode(0xABED);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.i, 0xBED);
     }
 
-    #[tokio::test]
-    async fn test_bnnn_jump_to_nnn_plus_v0_chip8() {
+    #[test]
+    fn test_bnnn_jump_to_nnn_plus_v0_chip8() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.chip_type = ChipType::CHIP8;
         chip.pc = 0x200;
         chip.registers[0] = 0xF;
 
         let decoded_instruction = chip.decode(0xBABC);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0xABC + 0xF);
     }
 
-    #[tokio::test]
-    async fn test_bnnn_jump_to_nnn_plus_v0_superchip() {
+    #[test]
+    fn test_bnnn_jump_to_nnn_plus_v0_superchip() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.chip_type = ChipType::SCHIP;
         chip.pc = 0x200;
@@ -1125,32 +1654,46 @@ mod tests {
         //SUPERCHIP uses VX instead of V0 to add to NNN
 
         let decoded_instruction = chip.decode(0xBABC);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
+
+        assert_eq!(chip.pc, 0xABC + 0xF);
+    }
+
+    #[test]
+    fn test_bnnn_jump_to_nnn_plus_vx_with_jumping_quirk_override() {
+        let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
+        chip.quirk_overrides.jumping = Some(true);
+        chip.pc = 0x200;
+        chip.registers[0x0] = 0x0;
+        chip.registers[0xA] = 0xF;
+
+        let decoded_instruction = chip.decode(0xBABC);
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0xABC + 0xF);
     }
 
     #[ignore]
-    #[tokio::test]
-    async fn test_ex9e_skip_if_vx_key_is_pressed() {
+    #[test]
+    fn test_ex9e_skip_if_vx_key_is_pressed() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.pc = 0x200;
 
         let decoded_instruction = chip.decode(0xEA9E);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.pc, 0x200);
     }
 
-    #[tokio::test]
-    async fn test_fx07_set_vx_to_delay_timers_value() {
+    #[test]
+    fn test_fx07_set_vx_to_delay_timers_value() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         let vx = 0xA;
         chip.registers[vx] = 0;
         chip.delay_timer.set(30);
 
         let decoded_instruction = chip.decode(0xFA07);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[vx], 30);
     }
@@ -1163,75 +1706,75 @@ mod tests {
         let vx = 0xA;
 
         let decoded_instruction = chip.decode(0xFA0A);
-        chip.execute(decoded_instruction).await; // Have to press E and enter
+        chip.execute(decoded_instruction); // Have to press E and enter
 
         assert_eq!(chip.registers[vx], 0xE)
     }
     *
     */
 
-    #[tokio::test]
-    async fn test_fx15_set_delay_timer_to_vx() {
+    #[test]
+    fn test_fx15_set_delay_timer_to_vx() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         let vx = 0xA;
         chip.registers[vx] = 30;
         chip.delay_timer.set(0);
 
         let decoded_instruction = chip.decode(0xFA15);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.delay_timer.get(), 30);
     }
 
-    #[tokio::test]
-    async fn test_fx18_set_sound_timer_to_vx() {
+    #[test]
+    fn test_fx18_set_sound_timer_to_vx() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         let vx = 0xA;
         chip.registers[vx] = 30;
         chip.sound_timer.set(0);
 
         let decoded_instruction = chip.decode(0xFA18);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.sound_timer.get(), 30);
     }
 
-    #[tokio::test]
-    async fn test_fx1e_add_vx_to_i() {
+    #[test]
+    fn test_fx1e_add_vx_to_i() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.i = 0x0F;
         chip.registers[0xA] = 0xF0;
         chip.registers[0xF] = 9;
 
         let decoded_instruction = chip.decode(0xFA1E);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.i, 0xFF);
         assert_eq!(chip.registers[0xF], 0x9); // VF unaffected
     }
 
-    #[tokio::test]
-    async fn test_fx1e_add_vx_to_i_overflow() {
+    #[test]
+    fn test_fx1e_add_vx_to_i_overflow() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.i = 4096;
         chip.registers[0xA] = 255;
         chip.registers[0xF] = 9;
 
         let decoded_instruction = chip.decode(0xFA1E);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         // Not an overflow, and we don't throw any error. This is the rom's responsibility to ensure
         assert_eq!(chip.i, 4351);
         assert_eq!(chip.registers[0xF], 0x9); // VF unaffected
     }
 
-    #[tokio::test]
-    async fn test_fx29_set_i_to_sprite_for_vx() {
+    #[test]
+    fn test_fx29_set_i_to_sprite_for_vx() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.registers[0xA] = 0xF;
 
         let decoded_instruction = chip.decode(0xFA29);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(FONT_ADDR, 0x050);
         assert_eq!(chip.i, 0x09B); // FONT_ADDR + skipping 15 5 byte characters(0x4B) to get to 0x9B
@@ -1242,53 +1785,53 @@ mod tests {
         assert_eq!(chip.memory[chip.i + 4], 0x80);
     }
 
-    #[tokio::test]
-    async fn test_fx33_store_binary_at_i_000() {
+    #[test]
+    fn test_fx33_store_binary_at_i_000() {
         let vx = 0xA;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.registers[vx] = 0;
         chip.i = 0;
 
         let decoded_instruction = chip.decode(0xFA33);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.memory[0], 0x0);
         assert_eq!(chip.memory[1], 0x0);
         assert_eq!(chip.memory[2], 0x0);
     }
 
-    #[tokio::test]
-    async fn test_fx33_store_binary_at_i_255() {
+    #[test]
+    fn test_fx33_store_binary_at_i_255() {
         let vx = 0xA;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.registers[vx] = 255;
         chip.i = 0;
 
         let decoded_instruction = chip.decode(0xFA33);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.memory[0], 0x2);
         assert_eq!(chip.memory[1], 0x5);
         assert_eq!(chip.memory[2], 0x5);
     }
 
-    #[tokio::test]
-    async fn test_fx33_store_binary_at_i_123() {
+    #[test]
+    fn test_fx33_store_binary_at_i_123() {
         let vx = 0xA;
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.registers[vx] = 123;
         chip.i = 0;
 
         let decoded_instruction = chip.decode(0xFA33);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.memory[0], 0x1);
         assert_eq!(chip.memory[1], 0x2);
         assert_eq!(chip.memory[2], 0x3);
     }
 
-    #[tokio::test]
-    async fn test_fx55_store_registers_at_i() {
+    #[test]
+    fn test_fx55_store_registers_at_i() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.i = 0x500;
         chip.registers[0x0] = 123;
@@ -1297,7 +1840,7 @@ mod tests {
         chip.registers[0xB] = 32;
 
         let decoded_instruction = chip.decode(0xFA55);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.memory[0x500], 123);
         assert_eq!(chip.memory[0x505], 23);
@@ -1305,31 +1848,31 @@ mod tests {
         assert_eq!(chip.memory[0x50B], 0); // Not included, since VX is 0xA
     }
 
-    #[tokio::test]
-    async fn test_fx55_increments_i_chip8() {
+    #[test]
+    fn test_fx55_increments_i_chip8() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.i = 0x500;
         chip.registers[0x0] = 1;
 
         let decoded_instruction = chip.decode(0xFA55);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.i, 0x500 + 10 + 1); // i + 1 + Vx, where Vx is 0xA
     }
 
-    #[tokio::test]
-    async fn test_fx65_load_registers_from_i() {
+    #[test]
+    fn test_fx65_load_registers_from_i() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         let i = 0x500;
         chip.i = i;
-        chip.memory[i + 0] = 123;
+        chip.memory[i] = 123;
         chip.memory[i + 1] = 23;
         chip.memory[i + 2] = 3;
         chip.memory[i + 3] = 32;
         chip.memory[i + 4] = 33;
 
         let decoded_instruction = chip.decode(0xF365);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.registers[0], 123);
         assert_eq!(chip.registers[1], 23);
@@ -1338,15 +1881,215 @@ mod tests {
         assert_eq!(chip.registers[4], 0); // Not included, since VX is 0x3
     }
 
-    #[tokio::test]
-    async fn test_fx65_increments_i_chip8() {
+    #[test]
+    fn test_fx65_increments_i_chip8() {
         let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
         chip.i = 0x500;
         chip.registers[0x0] = 1;
 
         let decoded_instruction = chip.decode(0xFF65);
-        chip.execute(decoded_instruction).await;
+        chip.execute(decoded_instruction);
 
         assert_eq!(chip.i, 0x500 + 15 + 1); // i + 1 + Vx, where Vx is 0xF
     }
+
+    #[test]
+    fn halting_stops_the_chip_when_it_cant_exit() {
+        let mut chip = Chip::new(600, ChipType::CHIP8, DisplayType::Terminal);
+        chip.exit_on_halt = false;
+        chip.load_rom(&[0x60, 0x01, 0x00, 0xEE, 0x61, 0x01]).unwrap();
+
+        chip.run_frame();
+        assert_eq!(chip.halted, Some(Halt::Error(String::from("Can't return from empty stack"))));
+        assert_eq!(chip.registers[0], 1);
+        assert_eq!(chip.registers[1], 0);
+        assert_eq!(chip.pc, 0x204);
+
+        chip.reset();
+        assert_eq!(chip.halted, None);
+    }
+
+    #[test]
+    fn steps_report_what_changed() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        chip.load_rom(&[0x60, 0x02, 0xA0, 0x50, 0xD0, 0x05, 0xF0, 0x18, 0xF1, 0x0A, 0x00, 0x00]).unwrap();
+
+        assert_eq!(chip.step(), StepResult::default());
+        assert_eq!(chip.step(), StepResult::default());
+        assert_eq!(chip.step(), StepResult { screen_changed: true, ..Default::default() });
+        assert_eq!(chip.step(), StepResult { sound: Some(SoundChange::Started), ..Default::default() });
+        assert_eq!(chip.step(), StepResult { waiting_for_key: true, ..Default::default() });
+
+        chip.input.press_key(4);
+        chip.step();
+        chip.input.release_key(4);
+        assert_eq!(chip.step(), StepResult::default());
+        assert_eq!(chip.step(), StepResult { halted: Some(Halt::Exited), ..Default::default() });
+        assert_eq!(chip.step(), StepResult::default());
+    }
+
+    #[test]
+    fn frames_sum_up_their_steps() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        // Beeps for a frame while drawing, then waits for a key
+        chip.load_rom(&[0x60, 0x01, 0xA0, 0x50, 0xF0, 0x18, 0xD0, 0x05, 0xF1, 0x0A, 0x12, 0x0A]).unwrap();
+
+        let summary = chip.run_frame();
+        assert_eq!(summary, FrameSummary { instructions: 5, screen_changed: true, waiting_for_key: true, ..Default::default() });
+
+        let summary = chip.run_frame();
+        assert_eq!(summary, FrameSummary { instructions: 1, waiting_for_key: true, ..Default::default() });
+
+        chip.breakpoints.insert(0x208, None);
+        chip.sound_timer.set(2);
+        let summary = chip.run_frame();
+        assert_eq!(summary.stop_reason, Some(StopReason::Breakpoint(0x208)));
+        assert_eq!(summary.instructions, 0);
+        chip.breakpoints.clear();
+        chip.run_frame();
+        assert_eq!(chip.run_frame().sound, Some(SoundChange::Stopped));
+    }
+
+    #[test]
+    fn calling_with_a_full_stack_halts() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        chip.load_rom(&[0x22, 0x00]).unwrap(); // Calls itself forever
+
+        for _ in 0..33 {
+            chip.step();
+        }
+        assert_eq!(chip.halted, Some(Halt::Error(String::from("Can't call with a full stack"))));
+        assert_eq!(chip.stack_level, 32);
+    }
+
+    #[test]
+    fn memory_wraps_around_instead_of_running_off_the_end() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        chip.load_rom(&[0x12, 0x00]).unwrap();
+        chip.registers = [1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12, 13, 14, 15, 16];
+
+        // FX55 and FX65 carry on from 0x000
+        chip.i = 0xFFE;
+        chip.execute(Instruction::StoreRegisters(3));
+        assert_eq!(chip.memory[0xFFE..], [1, 2]);
+        assert_eq!(chip.memory[..2], [3, 4]);
+        chip.i = 0xFFF;
+        chip.execute(Instruction::LoadRegisters(1));
+        assert_eq!(chip.registers[..2], [2, 3]);
+
+        // So does PC, from the last address and from BNNN
+        chip.pc = 0xFFF;
+        chip.memory[0xFFF] = 0x60;
+        chip.memory[0] = 0x2A;
+        chip.step();
+        assert_eq!((chip.registers[0], chip.pc), (0x2A, 1));
+        chip.execute(Instruction::JumpWithOffset(0, 0xFFF));
+        assert_eq!(chip.pc, 0x29);
+    }
+
+    #[test]
+    fn save_states_restore_the_chip() {
+        let mut chip = Chip::new(600, ChipType::CHIP8, DisplayType::Terminal);
+        chip.load_rom(&[0x60, 0x05, 0xF0, 0x15, 0xA2, 0x00, 0xD0, 0x05, 0x22, 0x0C, 0xF1, 0x0A, 0x70, 0x01, 0x12, 0x0C]).unwrap();
+        chip.run_frame();
+        let state = chip.save_state();
+        assert_eq!(state.len(), STATE_SIZE);
+
+        chip.run_frame();
+        let mut restored = Chip::new(600, ChipType::CHIP8, DisplayType::Terminal);
+        restored.load_state(&state).unwrap();
+        restored.run_frame();
+        assert_eq!(restored.save_state(), chip.save_state());
+        assert_eq!(restored.registers[0], chip.registers[0]);
+        assert_eq!(restored.stack_level, 1);
+
+        assert!(restored.load_state(&state[1..]).is_err());
+    }
+
+    #[test]
+    fn save_states_keep_the_random_numbers_coming_the_same() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        chip.load_rom(&[0xC0, 0xFF, 0xC1, 0xFF]).unwrap();
+        chip.seed_random(7);
+        let state = chip.save_state();
+        chip.step();
+        chip.step();
+
+        let mut restored = Chip::headless(600, ChipType::CHIP8);
+        restored.seed_random(8);
+        restored.load_state(&state).unwrap();
+        restored.step();
+        restored.step();
+        assert_eq!(restored.registers[..2], chip.registers[..2]);
+    }
+
+    #[test]
+    fn i_wraps_at_16_bits_and_survives_save_states() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        // Adds 0xFF to I forever
+        chip.load_rom(&[0x60, 0xFF, 0xF0, 0x1E, 0x12, 0x02]).unwrap();
+        chip.step();
+        for _ in 0..258 {
+            chip.step();
+            chip.step();
+        }
+        assert_eq!(chip.i, 0xFE); // 258 * 0xFF is 0x100FE
+
+        let mut restored = Chip::headless(600, ChipType::CHIP8);
+        restored.load_state(&chip.save_state()).unwrap();
+        assert_eq!(restored.i, chip.i);
+    }
+
+    #[test]
+    fn snapshots_replay_the_same_way() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        // Takes random numbers until key 0 is down, then sets V2
+        chip.load_rom(&[0xC0, 0xFF, 0xE1, 0x9E, 0x12, 0x00, 0x62, 0x55, 0x12, 0x08]).unwrap();
+        chip.seed_random(7);
+        chip.step();
+        let snapshot = chip.snapshot();
+
+        chip.run_frame();
+        let without_key = chip.snapshot();
+        chip.restore(&snapshot);
+        chip.run_frame();
+        assert_eq!(chip.snapshot(), without_key);
+
+        chip.restore(&snapshot);
+        chip.input.press_key(0);
+        chip.run_frame();
+        assert_eq!(chip.registers[2], 0x55);
+        assert_ne!(chip.snapshot(), without_key);
+    }
+
+    #[test]
+    fn restoring_decodes_the_old_memory_again() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        chip.load_rom(&[0x61, 0x07]).unwrap();
+        let snapshot = chip.snapshot();
+        chip.write_memory(0x200, &[0x61, 0x05]);
+        chip.step();
+        assert_eq!(chip.registers[1], 5);
+
+        chip.restore(&snapshot);
+        chip.step();
+        assert_eq!(chip.registers[1], 7);
+    }
+
+    #[test]
+    fn clones_run_on_their_own() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        chip.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        let hooked = Arc::new(Mutex::new(0));
+        let counter = hooked.clone();
+        chip.hooks.on_before_instruction(move |_| *counter.lock().unwrap() += 1);
+        chip.input.press_key(3);
+
+        let mut copy = chip.clone();
+        copy.run_frame();
+        assert_eq!(copy.registers[0], 5);
+        assert_eq!(copy.input.pressed_keys(), [3]);
+        assert_eq!(chip.registers[0], 0);
+        assert_eq!(*hooked.lock().unwrap(), 0);
+    }
 }
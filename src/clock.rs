@@ -0,0 +1,52 @@
+// std's Instant panics in the browser, so there it's built on the page's clock instead
+#[cfg(not(target_arch = "wasm32"))]
+pub use std::time::Instant;
+
+#[cfg(target_arch = "wasm32")]
+pub use web::Instant;
+
+#[cfg(target_arch = "wasm32")]
+mod web {
+    use std::ops::{Add, AddAssign, Sub};
+    use std::time::Duration;
+    use macroquad::miniquad::date;
+
+    #[derive(PartialEq, PartialOrd, Clone, Copy, Debug)]
+    pub struct Instant(Duration); // Since the Unix epoch
+
+    impl Instant {
+        pub fn now() -> Self {
+            Instant(Duration::from_secs_f64(date::now()))
+        }
+
+        pub fn elapsed(&self) -> Duration {
+            Instant::now().duration_since(*self)
+        }
+
+        pub fn duration_since(&self, earlier: Instant) -> Duration {
+            self.0.saturating_sub(earlier.0)
+        }
+    }
+
+    impl Add<Duration> for Instant {
+        type Output = Instant;
+
+        fn add(self, duration: Duration) -> Instant {
+            Instant(self.0 + duration)
+        }
+    }
+
+    impl AddAssign<Duration> for Instant {
+        fn add_assign(&mut self, duration: Duration) {
+            self.0 += duration;
+        }
+    }
+
+    impl Sub for Instant {
+        type Output = Duration;
+
+        fn sub(self, earlier: Instant) -> Duration {
+            self.duration_since(earlier)
+        }
+    }
+}
@@ -0,0 +1,273 @@
+use std::collections::HashMap;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::chip::ChipType;
+use crate::display::DisplayType;
+use crate::filter::{FilterType, check_decay};
+use crate::input::DEFAULT_KEY_HOLD_MS;
+use crate::keymap::{KeyBindings, Keymap, KeymapFile, KeymapPreset};
+use crate::palette::{Palette, PaletteSetting};
+use crate::quirks::QuirkOverrides;
+use crate::scheduler::{DEFAULT_TURBO_FACTOR, Speed};
+use crate::terminal::TerminalMode;
+use crate::timing::TimingMode;
+
+pub const DEFAULT_TARGET_IPS: u128 = 1200;
+pub const DEFAULT_PHOSPHOR_DECAY: f32 = 0.6;
+
+// Options that can be set by the config file, a ROM's profile or the command line.
+// Anything left unset falls through to the layer below it.
+#[derive(Deserialize, Clone, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Profile {
+    pub name: Option<String>,
+    pub target_instructions_per_second: Option<u64>,
+    pub timing: Option<TimingMode>,
+    pub speed: Option<Speed>,
+    pub turbo_factor: Option<u32>,
+    pub chip_type: Option<ChipType>,
+    pub display_type: Option<DisplayType>,
+    pub filter: Option<FilterType>,
+    pub phosphor_decay: Option<f32>,
+    pub palette: Option<PaletteSetting>,
+    pub terminal_mode: Option<TerminalMode>,
+    pub key_hold_ms: Option<u64>,
+    pub keymap: Option<PathBuf>,
+    pub keymap_preset: Option<KeymapPreset>,
+    #[serde(default)]
+    pub keys: KeyBindings,
+    #[serde(default)]
+    pub quirks: QuirkOverrides
+}
+
+impl Profile {
+    // Options set in `other` win over the ones set here. Key bindings are applied after ours
+    pub fn layer(&mut self, other: &Profile) {
+        self.name = other.name.clone().or(self.name.take());
+        self.target_instructions_per_second = other.target_instructions_per_second.or(self.target_instructions_per_second);
+        self.timing = other.timing.or(self.timing);
+        self.speed = other.speed.or(self.speed);
+        self.turbo_factor = other.turbo_factor.or(self.turbo_factor);
+        self.chip_type = other.chip_type.clone().or(self.chip_type.take());
+        self.display_type = other.display_type.clone().or(self.display_type.take());
+        self.filter = other.filter.or(self.filter);
+        self.phosphor_decay = other.phosphor_decay.or(self.phosphor_decay);
+        self.palette = other.palette.clone().or(self.palette.take());
+        self.terminal_mode = other.terminal_mode.or(self.terminal_mode);
+        self.key_hold_ms = other.key_hold_ms.or(self.key_hold_ms);
+        self.keymap = other.keymap.clone().or(self.keymap.take());
+        self.keymap_preset = other.keymap_preset.or(self.keymap_preset);
+        self.keys.0.extend(other.keys.0.iter().cloned());
+        self.quirks.layer(&other.quirks);
+    }
+}
+
+// The user's config file, like:
+//
+//   database = "programs.json"
+//
+//   [defaults]
+//   target_instructions_per_second = 1000
+//   palette = "green"
+//
+//   [roms.0df2789f661358d8f7370e6cf93490c5bcd44b01]
+//   name = "Pong"
+//   chip_type = "schip"
+//   palette = { foreground = "#ffffff", background = "#202020" }
+//
+//   [roms.0df2789f661358d8f7370e6cf93490c5bcd44b01.quirks]
+//   vf_reset = true
+//
+// ROM profiles are keyed by the SHA-1 of the ROM, so they follow the ROM if it's renamed or moved.
+#[derive(Deserialize, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct Config {
+    pub database: Option<PathBuf>,
+    #[serde(default)]
+    pub defaults: Profile,
+    #[serde(default)]
+    pub roms: HashMap<String, Profile>
+}
+
+impl Config {
+    // Loads the given config file, or the default one if it exists
+    pub fn load(path: Option<&Path>) -> Result<Self, String> {
+        let path = match path {
+            Some(path) => path.to_path_buf(),
+            None => match default_config_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default())
+            }
+        };
+
+        let contents = fs::read_to_string(&path)
+            .map_err(|error| format!("Can't read config {}: {}", path.display(), error))?;
+        let mut config = Config::parse(&contents)
+            .map_err(|error| format!("Can't parse config {}: {}", path.display(), error))?;
+
+        // Files are found next to the config, rather than wherever the emulator is run from
+        let config_dir = path.parent().unwrap_or(Path::new(""));
+        config.database = config.database.take().map(|database| config_dir.join(database));
+        for profile in config.roms.values_mut().chain([&mut config.defaults]) {
+            profile.keymap = profile.keymap.take().map(|keymap| config_dir.join(keymap));
+        }
+        Ok(config)
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, toml::de::Error> {
+        let mut config: Config = toml::from_str(contents)?;
+
+        // Hashes are usually printed in lowercase, but allow copying them from anywhere
+        config.roms = config.roms.into_iter().map(|(hash, profile)| (hash.to_lowercase(), profile)).collect();
+        Ok(config)
+    }
+
+    pub fn rom_profile(&self, rom: &[u8]) -> Option<&Profile> {
+        self.roms.get(&rom_hash(rom))
+    }
+}
+
+// $XDG_CONFIG_HOME/chip8, falling back to ~/.config/chip8
+pub fn default_config_dir() -> Option<PathBuf> {
+    let config_dir = env::var_os("XDG_CONFIG_HOME")
+        .map(PathBuf::from)
+        .or_else(|| env::var_os("HOME").map(|home| PathBuf::from(home).join(".config")))
+        .or_else(|| env::var_os("APPDATA").map(PathBuf::from))?;
+    Some(config_dir.join("chip8"))
+}
+
+pub fn default_config_path() -> Option<PathBuf> {
+    Some(default_config_dir()?.join("config.toml"))
+}
+
+pub fn rom_hash(rom: &[u8]) -> String {
+    sha1_smol::Sha1::from(rom).digest().to_string()
+}
+
+// Every option resolved to a value, ready to set up the Chip with
+pub struct Settings {
+    pub target_ips: u128,
+    pub timing: TimingMode,
+    pub speed: Speed,
+    pub turbo_factor: u32,
+    pub chip_type: ChipType,
+    pub display_type: DisplayType,
+    pub filter: FilterType,
+    pub phosphor_decay: f32,
+    pub palette: Palette,
+    pub terminal_mode: TerminalMode,
+    pub key_hold_ms: u64,
+    pub keymap: Keymap,
+    pub quirks: QuirkOverrides
+}
+
+impl Settings {
+    pub fn resolve(profile: Profile, rom_name: &str) -> Result<Self, String> {
+        let keymap_file = match &profile.keymap {
+            Some(path) => KeymapFile::load(path)?,
+            None => KeymapFile::default()
+        };
+        let display_type = profile.display_type.unwrap_or(DisplayType::Macroquad);
+        let mut keymap = keymap_file.keymap_for(profile.keymap_preset, &display_type, rom_name);
+        keymap.apply(&profile.keys);
+
+        let phosphor_decay = match profile.phosphor_decay {
+            Some(decay) => check_decay(decay)?,
+            None => DEFAULT_PHOSPHOR_DECAY
+        };
+
+        Ok(Settings {
+            target_ips: profile.target_instructions_per_second.map(u128::from).unwrap_or(DEFAULT_TARGET_IPS),
+            timing: profile.timing.unwrap_or(TimingMode::Ips),
+            speed: profile.speed.unwrap_or(Speed::Normal),
+            turbo_factor: profile.turbo_factor.unwrap_or(DEFAULT_TURBO_FACTOR).max(1),
+            chip_type: profile.chip_type.unwrap_or(ChipType::CHIP8),
+            display_type,
+            filter: profile.filter.unwrap_or(FilterType::None),
+            phosphor_decay,
+            palette: profile.palette.map(|palette| palette.palette()).transpose()?.unwrap_or_default(),
+            terminal_mode: profile.terminal_mode.unwrap_or(TerminalMode::Auto),
+            key_hold_ms: profile.key_hold_ms.unwrap_or(DEFAULT_KEY_HOLD_MS),
+            keymap,
+            quirks: profile.quirks
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CONFIG: &str = r##"
+        [defaults]
+        target_instructions_per_second = 1000
+        palette = "green"
+        chip_type = "chip8"
+
+        [defaults.keys]
+        5 = ["up"]
+
+        [roms.0DF2789F661358D8F7370E6CF93490C5BCD44B01]
+        name = "Pong"
+        chip_type = "schip"
+        palette = { foreground = "#ffffff", background = "#202020" }
+
+        [roms.0DF2789F661358D8F7370E6CF93490C5BCD44B01.quirks]
+        vf_reset = true
+    "##;
+
+    fn config() -> Config {
+        Config::parse(CONFIG).unwrap()
+    }
+
+    #[test]
+    fn rom_hash_is_sha1_of_rom() {
+        assert_eq!(rom_hash(b"abc"), "a9993e364706816aba3e25717850c26c9cd0d89d");
+    }
+
+    #[test]
+    fn rom_profile_is_layered_over_defaults() {
+        let config = config();
+        let mut profile = config.defaults.clone();
+        profile.layer(&config.roms["0df2789f661358d8f7370e6cf93490c5bcd44b01"]);
+
+        let settings = Settings::resolve(profile, "pong.ch8").unwrap();
+
+        assert_eq!(settings.target_ips, 1000);
+        assert!(matches!(settings.chip_type, ChipType::SCHIP));
+        assert_eq!(settings.palette, Palette { foreground: [255, 255, 255], background: [32, 32, 32] });
+        assert_eq!(settings.quirks.vf_reset, Some(true));
+        assert_eq!(settings.keymap.chip_key("up"), Some(0x5));
+    }
+
+    #[test]
+    fn command_line_is_layered_over_config() {
+        let config = config();
+        let mut profile = config.defaults.clone();
+        profile.layer(&Profile { target_instructions_per_second: Some(500), ..Default::default() });
+
+        let settings = Settings::resolve(profile, "maze.ch8").unwrap();
+
+        assert_eq!(settings.target_ips, 500);
+        assert_eq!(settings.palette, Palette::from(crate::palette::PaletteType::Green));
+    }
+
+    #[test]
+    fn unset_options_use_defaults() {
+        let settings = Settings::resolve(Profile::default(), "maze.ch8").unwrap();
+
+        assert_eq!(settings.target_ips, DEFAULT_TARGET_IPS);
+        assert_eq!(settings.timing, TimingMode::Ips);
+        assert_eq!(settings.display_type, DisplayType::Macroquad);
+        assert_eq!(settings.phosphor_decay, DEFAULT_PHOSPHOR_DECAY);
+        assert_eq!(settings.keymap, Keymap::default());
+    }
+
+    #[test]
+    fn config_rejects_unknown_options() {
+        let config = Config::parse("[defaults]\ntarget_ips = 10");
+        assert!(config.is_err());
+    }
+}
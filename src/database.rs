@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+use crate::chip::ChipType;
+use crate::config::{Profile, default_config_dir};
+use crate::palette::PaletteSetting;
+use crate::quirks::QuirkOverrides;
+
+// The CHIP-8 community program database (https://github.com/chip-8/chip-8-database) describes
+// known ROMs by their SHA-1 hash: what platform they were written for, how fast they should run
+// and what colors and keys they use. Only `programs.json` is needed, since it lists every ROM's hash.
+
+#[derive(Deserialize, Debug)]
+struct Program {
+    title: String,
+    #[serde(default)]
+    authors: Vec<String>,
+    #[serde(default)]
+    roms: HashMap<String, Rom>
+}
+
+#[derive(Deserialize, Debug)]
+#[serde(rename_all = "camelCase")]
+struct Rom {
+    #[serde(default)]
+    platforms: Vec<String>,
+    #[serde(default)]
+    quirky_platforms: HashMap<String, DatabaseQuirks>,
+    tickrate: Option<u64>,
+    #[serde(default)]
+    keys: HashMap<String, u8>,
+    colors: Option<Colors>
+}
+
+// The database has more quirks than this, but these are the ones the emulator supports
+#[derive(Deserialize, Debug, Default)]
+#[serde(rename_all = "camelCase")]
+struct DatabaseQuirks {
+    logic: Option<bool>,
+    shift: Option<bool>,
+    memory_leave_i_unchanged: Option<bool>,
+    jump: Option<bool>
+}
+
+#[derive(Deserialize, Debug)]
+struct Colors {
+    #[serde(default)]
+    pixels: Vec<String>
+}
+
+// What the database knows about a ROM
+#[derive(Debug)]
+pub struct RomInfo {
+    pub title: String,
+    pub authors: Vec<String>,
+    pub platform: Option<String>, // None if none of the ROM's platforms are supported
+    pub key_hints: Vec<(String, u8)>,
+    pub profile: Profile
+}
+
+pub struct Database {
+    programs: Vec<Program>,
+    rom_hashes: HashMap<String, usize>
+}
+
+impl Database {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| format!("Can't read ROM database {}: {}", path.display(), error))?;
+        Database::parse(&contents)
+            .map_err(|error| format!("Can't parse ROM database {}: {}", path.display(), error))
+    }
+
+    pub fn parse(contents: &str) -> Result<Self, serde_json::Error> {
+        let programs: Vec<Program> = serde_json::from_str(contents)?;
+
+        let mut rom_hashes = HashMap::new();
+        for (index, program) in programs.iter().enumerate() {
+            for hash in program.roms.keys() {
+                rom_hashes.insert(hash.to_lowercase(), index);
+            }
+        }
+
+        Ok(Database { programs, rom_hashes })
+    }
+
+    pub fn lookup(&self, hash: &str) -> Option<RomInfo> {
+        let program = &self.programs[*self.rom_hashes.get(hash)?];
+        let rom = program.roms.iter().find(|(rom_hash, _)| rom_hash.to_lowercase() == hash)?.1;
+
+        let mut profile = Profile { name: Some(program.title.clone()), ..Default::default() };
+
+        // Use the first platform the ROM runs on that we can emulate
+        let platform = rom.platforms.iter().find_map(|platform| Some((platform, platform_settings(platform)?)));
+        if let Some((platform, (chip_type, quirks))) = &platform {
+            profile.chip_type = Some(chip_type.clone());
+            profile.quirks = *quirks;
+            if let Some(quirky_platform) = rom.quirky_platforms.get(*platform) {
+                profile.quirks.layer(&quirky_platform.overrides());
+            }
+
+            // The database's tickrate is instructions per frame, at 60 frames a second
+            profile.target_instructions_per_second = rom.tickrate.map(|tickrate| tickrate * 60);
+
+            if let Some([background, foreground, ..]) = rom.colors.as_ref().map(|colors| colors.pixels.as_slice()) {
+                profile.palette = Some(PaletteSetting::Custom {
+                    foreground: foreground.clone(),
+                    background: background.clone()
+                });
+            }
+        }
+
+        let mut key_hints: Vec<(String, u8)> = rom.keys.iter().map(|(name, key)| (name.clone(), *key)).collect();
+        key_hints.sort();
+
+        Some(RomInfo {
+            title: program.title.clone(),
+            authors: program.authors.clone(),
+            platform: platform.map(|(platform, _)| platform.clone()),
+            key_hints,
+            profile
+        })
+    }
+}
+
+impl DatabaseQuirks {
+    fn overrides(&self) -> QuirkOverrides {
+        QuirkOverrides {
+            vf_reset: self.logic,
+            memory: self.memory_leave_i_unchanged.map(|leave_i_unchanged| !leave_i_unchanged),
+            shifting: self.shift,
+            jumping: self.jump
+        }
+    }
+}
+
+// The chip type and quirks for the database's platforms that we can emulate
+fn platform_settings(platform: &str) -> Option<(ChipType, QuirkOverrides)> {
+    match platform {
+        "originalChip8" | "hybridVIP" => Some((ChipType::CHIP8, QuirkOverrides::default())),
+        "modernChip8" => Some((ChipType::CHIP8, QuirkOverrides { vf_reset: Some(false), ..Default::default() })),
+        "superchip1" | "superchip" => Some((ChipType::SCHIP, QuirkOverrides::default())),
+        _ => None
+    }
+}
+
+// ~/.config/chip8/programs.json, next to the config file
+pub fn default_database_path() -> Option<PathBuf> {
+    Some(default_config_dir()?.join("programs.json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROGRAMS: &str = r##"[
+        {
+            "title": "Pong",
+            "authors": ["Paul Vervalin"],
+            "roms": {
+                "0DF2789F661358D8F7370E6CF93490C5BCD44B01": {
+                    "file": "pong.ch8",
+                    "platforms": ["originalChip8"],
+                    "quirkyPlatforms": { "originalChip8": { "logic": false, "vblank": true } },
+                    "tickrate": 15,
+                    "keys": { "up": 1, "down": 4 },
+                    "colors": { "pixels": ["#000000", "#ffffff"] }
+                }
+            }
+        },
+        {
+            "title": "Super Neatboy",
+            "authors": ["JohnEarnest"],
+            "roms": {
+                "a2d27a4b4bb0a4dfb2d1b4c0bd8d6dac5dcf9f6f": { "platforms": ["xochip"], "tickrate": 1000 }
+            }
+        }
+    ]"##;
+
+    #[test]
+    fn lookup_finds_rom_by_hash() {
+        let database = Database::parse(PROGRAMS).unwrap();
+
+        let rom_info = database.lookup("0df2789f661358d8f7370e6cf93490c5bcd44b01").unwrap();
+
+        assert_eq!(rom_info.title, "Pong");
+        assert_eq!(rom_info.authors, vec!["Paul Vervalin"]);
+        assert_eq!(rom_info.platform, Some(String::from("originalChip8")));
+        assert_eq!(rom_info.key_hints, vec![(String::from("down"), 4), (String::from("up"), 1)]);
+        assert!(database.lookup("a9993e364706816aba3e25717850c26c9cd0d89d").is_none());
+    }
+
+    #[test]
+    fn lookup_builds_profile_from_platform_tickrate_and_colors() {
+        let database = Database::parse(PROGRAMS).unwrap();
+
+        let profile = database.lookup("0df2789f661358d8f7370e6cf93490c5bcd44b01").unwrap().profile;
+
+        assert!(matches!(profile.chip_type, Some(ChipType::CHIP8)));
+        assert_eq!(profile.quirks.vf_reset, Some(false));
+        assert_eq!(profile.target_instructions_per_second, Some(900));
+        assert_eq!(profile.palette, Some(PaletteSetting::Custom {
+            foreground: String::from("#ffffff"),
+            background: String::from("#000000")
+        }));
+    }
+
+    #[test]
+    fn lookup_leaves_profile_empty_for_unsupported_platforms() {
+        let database = Database::parse(PROGRAMS).unwrap();
+
+        let rom_info = database.lookup("a2d27a4b4bb0a4dfb2d1b4c0bd8d6dac5dcf9f6f").unwrap();
+
+        assert_eq!(rom_info.platform, None);
+        assert!(rom_info.profile.chip_type.is_none());
+        assert!(rom_info.profile.target_instructions_per_second.is_none());
+    }
+}
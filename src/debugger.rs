@@ -0,0 +1,389 @@
+use macroquad::prelude::*;
+use crate::chip::Chip;
+use crate::disassembler::disassemble;
+use crate::display::{DEBUGGER_PIXEL_DIMENSION, DISPLAY_HEIGHT, DISPLAY_WIDTH};
+
+// The framebuffer shrinks into the top left quarter of the window, with the register panel to
+// its right, the disassembly below it and the memory viewer in the bottom right
+const PANEL_WIDTH: f32 = DEBUGGER_PIXEL_DIMENSION * DISPLAY_WIDTH as f32;
+const PANEL_HEIGHT: f32 = DEBUGGER_PIXEL_DIMENSION * DISPLAY_HEIGHT as f32;
+const FONT_SIZE: f32 = 20.;
+const LINE_HEIGHT: f32 = 22.;
+const PADDING: f32 = 10.;
+const DISASSEMBLY_LINES: usize = 13;
+const MEMORY_ROWS: usize = 13;
+const MEMORY_ROW_BYTES: usize = 16;
+const STACK_LINE_LEVELS: usize = 8;
+
+const TEXT_COLOR: Color = LIGHTGRAY;
+const PC_COLOR: Color = YELLOW;
+const I_COLOR: Color = SKYBLUE;
+const BREAKPOINT_COLOR: Color = RED;
+const EDIT_COLOR: Color = GREEN;
+const WATCH_COLOR: Color = ORANGE;
+
+// Values that can be edited while paused
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Field {
+    Register(usize),
+    I,
+    Pc,
+    DelayTimer,
+    SoundTimer,
+    Memory(usize)
+}
+
+impl Field {
+    fn digits(&self) -> usize {
+        match self {
+            Field::I | Field::Pc => 3,
+            _ => 2
+        }
+    }
+
+    fn get(&self, chip: &Chip) -> usize {
+        match self {
+            Field::Register(register) => chip.registers[*register] as usize,
+            Field::I => chip.i,
+            Field::Pc => chip.pc,
+            Field::DelayTimer => chip.delay_timer.get() as usize,
+            Field::SoundTimer => chip.sound_timer.get() as usize,
+            Field::Memory(address) => chip.memory[*address] as usize
+        }
+    }
+
+    // Values are typed in hex, and can't have more digits than the field shows
+    fn set(&self, chip: &mut Chip, text: &str) -> Result<(), String> {
+        if text.is_empty() || text.len() > self.digits() {
+            return Err(format!("`{}` should be 1 to {} hex digits", text, self.digits()));
+        }
+        let value = usize::from_str_radix(text, 16).map_err(|_| format!("`{}` isn't hex", text))?;
+
+        match self {
+            Field::Register(register) => chip.registers[*register] = value as u8,
+            Field::I => chip.i = value,
+            Field::Pc => chip.pc = value,
+            Field::DelayTimer => chip.delay_timer.set(value as u8),
+            Field::SoundTimer => chip.sound_timer.set(value as u8),
+            Field::Memory(address) => chip.write_memory(*address, &[value as u8])
+        }
+        Ok(())
+    }
+}
+
+enum Click {
+    ToggleBreakpoint(usize),
+    Edit(Field)
+}
+
+struct Edit {
+    field: Field,
+    text: String
+}
+
+pub struct Debugger {
+    pub visible: bool,
+    disassembly_scroll: isize, // In instructions from PC, reset whenever PC moves
+    last_pc: usize,
+    memory_address: usize,
+    edit: Option<Edit>,
+    clickable: Vec<(Rect, Click)>,
+    char_width: f32
+}
+
+impl Debugger {
+    pub fn new() -> Self {
+        Debugger {
+            visible: false,
+            disassembly_scroll: 0,
+            last_pc: 0,
+            memory_address: 0x200,
+            edit: None,
+            clickable: vec![],
+            char_width: FONT_SIZE / 2.
+        }
+    }
+
+    // Hotkeys are ignored while typing a value, so typing can't trigger them
+    pub fn editing(&self) -> bool {
+        self.edit.is_some()
+    }
+
+    // Handles the mouse and keyboard, then draws the panels. Called each frame while visible,
+    // after the framebuffer is drawn
+    pub fn update(&mut self, chip: &mut Chip, paused: bool) {
+        self.char_width = measure_text("0", None, FONT_SIZE as u16, 1.).width;
+        if !paused {
+            self.edit = None;
+        }
+        if chip.pc != self.last_pc {
+            self.disassembly_scroll = 0;
+            self.last_pc = chip.pc;
+        }
+
+        self.handle_typing(chip);
+        self.handle_mouse(chip, paused);
+
+        self.clickable.clear();
+        draw_rectangle(PANEL_WIDTH, 0., screen_width() - PANEL_WIDTH, screen_height(), Color::new(0.05, 0.05, 0.05, 1.));
+        draw_rectangle(0., PANEL_HEIGHT, PANEL_WIDTH, screen_height() - PANEL_HEIGHT, Color::new(0.08, 0.08, 0.08, 1.));
+        self.draw_registers(chip);
+        self.draw_disassembly(chip);
+        self.draw_memory(chip);
+    }
+
+    fn handle_typing(&mut self, chip: &mut Chip) {
+        let Some(edit) = &mut self.edit else {
+            // Throw away what was typed while not editing, so it doesn't turn up when editing starts
+            while get_char_pressed().is_some() { }
+            return;
+        };
+
+        while let Some(character) = get_char_pressed() {
+            if character.is_ascii_hexdigit() && edit.text.len() < edit.field.digits() {
+                edit.text.push(character.to_ascii_uppercase());
+            }
+        }
+        if is_key_pressed(KeyCode::Backspace) {
+            edit.text.pop();
+        }
+        if is_key_pressed(KeyCode::Escape) {
+            self.edit = None;
+        } else if is_key_pressed(KeyCode::Enter) || is_key_pressed(KeyCode::KpEnter) {
+            // A bad value leaves the field as it was
+            let _ = edit.field.set(chip, &edit.text);
+            self.edit = None;
+        }
+    }
+
+    fn handle_mouse(&mut self, chip: &mut Chip, paused: bool) {
+        let (mouse_x, mouse_y) = mouse_position();
+        let mouse = vec2(mouse_x, mouse_y);
+
+        let (_, wheel) = mouse_wheel();
+        if wheel != 0. {
+            let lines = -wheel.signum() as isize;
+            if Rect::new(0., PANEL_HEIGHT, PANEL_WIDTH, PANEL_HEIGHT).contains(mouse) {
+                self.disassembly_scroll += lines;
+            } else if Rect::new(PANEL_WIDTH, PANEL_HEIGHT, PANEL_WIDTH, PANEL_HEIGHT).contains(mouse) {
+                let address = self.memory_address as isize + lines * MEMORY_ROW_BYTES as isize;
+                let last_row = (chip.memory.len() - MEMORY_ROWS * MEMORY_ROW_BYTES) as isize;
+                self.memory_address = address.clamp(0, last_row) as usize;
+            }
+        }
+
+        if !is_mouse_button_pressed(MouseButton::Left) {
+            return;
+        }
+        // Clicking anywhere else finishes editing without changing the value
+        self.edit = None;
+        let Some((_, click)) = self.clickable.iter().find(|(rect, _)| rect.contains(mouse)) else { return };
+        match click {
+            Click::ToggleBreakpoint(address) => {
+                if chip.breakpoints.remove(address).is_none() {
+                    chip.breakpoints.insert(*address, None);
+                }
+            },
+            Click::Edit(field) if paused => {
+                self.edit = Some(Edit { field: *field, text: String::new() });
+            },
+            Click::Edit(_) => ()
+        }
+    }
+
+    fn text(&self, text: &str, column: usize, line: usize, origin: Vec2, color: Color) {
+        let x = origin.x + PADDING + column as f32 * self.char_width;
+        let y = origin.y + PADDING + LINE_HEIGHT * (line as f32 + 0.75);
+        draw_text(text, x, y, FONT_SIZE, color);
+    }
+
+    fn text_rect(&self, chars: usize, column: usize, line: usize, origin: Vec2) -> Rect {
+        let x = origin.x + PADDING + column as f32 * self.char_width;
+        let y = origin.y + PADDING + LINE_HEIGHT * line as f32;
+        Rect::new(x, y, chars as f32 * self.char_width, LINE_HEIGHT)
+    }
+
+    // Draws an editable value, or what's being typed into it
+    fn field(&mut self, chip: &Chip, field: Field, column: usize, line: usize, origin: Vec2, color: Color) {
+        let (text, color) = match &self.edit {
+            Some(edit) if edit.field == field => (format!("{:_<1$}", edit.text, field.digits()), EDIT_COLOR),
+            _ => (format!("{:01$X}", field.get(chip), field.digits()), color)
+        };
+        self.text(&text, column, line, origin, color);
+        self.clickable.push((self.text_rect(field.digits(), column, line, origin), Click::Edit(field)));
+    }
+
+    fn draw_registers(&mut self, chip: &Chip) {
+        let origin = vec2(PANEL_WIDTH, 0.);
+        self.text("Registers (click to edit while paused)", 0, 0, origin, WHITE);
+
+        for register in 0..16 {
+            let (column, line) = (register % 4 * 10, register / 4 + 1);
+            self.text(&format!("V{:X}", register), column, line, origin, TEXT_COLOR);
+            self.field(chip, Field::Register(register), column + 3, line, origin, TEXT_COLOR);
+        }
+
+        self.text("I", 0, 5, origin, I_COLOR);
+        self.field(chip, Field::I, 3, 5, origin, I_COLOR);
+        self.text("PC", 10, 5, origin, PC_COLOR);
+        self.field(chip, Field::Pc, 13, 5, origin, PC_COLOR);
+        self.text("DT", 20, 5, origin, TEXT_COLOR);
+        self.field(chip, Field::DelayTimer, 23, 5, origin, TEXT_COLOR);
+        self.text("ST", 30, 5, origin, TEXT_COLOR);
+        self.field(chip, Field::SoundTimer, 33, 5, origin, TEXT_COLOR);
+
+        self.text("Stack", 0, 7, origin, WHITE);
+        for (index, line) in stack_lines(&chip.stack[..chip.stack_level]).iter().enumerate() {
+            self.text(line, 0, index + 8, origin, TEXT_COLOR);
+        }
+
+        // Below the deepest the stack can go
+        let line = 8 + chip.stack.len().div_ceil(STACK_LINE_LEVELS);
+        if let Some(stop_reason) = &chip.stop_reason {
+            self.text(&format!("Stopped: {}", stop_reason), 0, line, origin, BREAKPOINT_COLOR);
+        }
+        let watches: Vec<String> = chip.watchpoints
+            .iter()
+            .map(|watchpoint| watchpoint.to_string())
+            .chain(chip.conditions.iter().map(|condition| condition.expression.to_string()))
+            .collect();
+        if !watches.is_empty() {
+            self.text(&format!("Watching: {}", watches.join(", ")), 0, line + 1, origin, WATCH_COLOR);
+        }
+    }
+
+    fn draw_disassembly(&mut self, chip: &Chip) {
+        let origin = vec2(0., PANEL_HEIGHT);
+        self.text("Disassembly (click to toggle breakpoints)", 0, 0, origin, WHITE);
+
+        let addresses = disassembly_addresses(chip.pc, self.disassembly_scroll, chip.memory.len());
+        for (index, address) in addresses.into_iter().enumerate() {
+            let instruction = (chip.memory[address] as u16) << 8 | chip.memory[address + 1] as u16;
+            let breakpoint = chip.breakpoints.get(&address);
+            let color = match (address == chip.pc, breakpoint) {
+                (true, _) => PC_COLOR,
+                (false, Some(_)) => BREAKPOINT_COLOR,
+                (false, None) => TEXT_COLOR
+            };
+            let pc_marker = if address == chip.pc { ">" } else { " " };
+            let (breakpoint_marker, condition) = match breakpoint {
+                Some(Some(condition)) => ("?", format!("  if {}", condition)),
+                Some(None) => ("*", String::new()),
+                None => (" ", String::new())
+            };
+            let line = format!(
+                "{}{} {:03X}  {:04X}  {}{}",
+                pc_marker, breakpoint_marker, address, instruction, disassemble(instruction), condition
+            );
+
+            self.text(&line, 0, index + 1, origin, color);
+            let rect = Rect::new(0., origin.y + PADDING + LINE_HEIGHT * (index + 1) as f32, PANEL_WIDTH, LINE_HEIGHT);
+            self.clickable.push((rect, Click::ToggleBreakpoint(address)));
+        }
+    }
+
+    fn draw_memory(&mut self, chip: &Chip) {
+        let origin = vec2(PANEL_WIDTH, PANEL_HEIGHT);
+        self.text("Memory (scroll to move, click to edit while paused)", 0, 0, origin, WHITE);
+
+        for row in 0..MEMORY_ROWS {
+            let row_address = self.memory_address + row * MEMORY_ROW_BYTES;
+            self.text(&format!("{:03X}", row_address), 0, row + 1, origin, WHITE);
+
+            for offset in 0..MEMORY_ROW_BYTES {
+                let address = row_address + offset;
+                let watched = chip.watchpoints.iter().any(|watchpoint| (watchpoint.start..=watchpoint.end).contains(&address));
+                let color = if address == chip.i {
+                    I_COLOR
+                } else if address == chip.pc || address == chip.pc + 1 {
+                    PC_COLOR
+                } else if watched {
+                    WATCH_COLOR
+                } else {
+                    TEXT_COLOR
+                };
+                self.field(chip, Field::Memory(address), 5 + offset * 3, row + 1, origin, color);
+            }
+        }
+    }
+}
+
+impl Default for Debugger {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+// Instructions are 2 bytes, so the lines step by 2 either side of PC
+fn disassembly_addresses(pc: usize, scroll: isize, memory_size: usize) -> Vec<usize> {
+    let centre = pc as isize + scroll * 2;
+    let first = centre - (DISASSEMBLY_LINES / 2) as isize * 2;
+    (0..DISASSEMBLY_LINES as isize)
+        .map(|line| first + line * 2)
+        .filter(|address| *address >= 0 && *address as usize + 1 < memory_size)
+        .map(|address| address as usize)
+        .collect()
+}
+
+// Return addresses, most recent call first, a line at a time after the level of the first on it.
+// That fits the whole stack in the panel
+fn stack_lines(stack: &[usize]) -> Vec<String> {
+    let levels: Vec<usize> = (0..stack.len()).rev().collect();
+    levels
+        .chunks(STACK_LINE_LEVELS)
+        .map(|levels| {
+            let addresses: Vec<String> = levels.iter().map(|level| format!("{:03X}", stack[*level])).collect();
+            format!("{:02}: {}", levels[0], addresses.join(" "))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip::ChipType;
+    use crate::display::DisplayType;
+
+    #[test]
+    fn disassembly_is_centred_on_pc() {
+        let addresses = disassembly_addresses(0x210, 0, 4096);
+
+        assert_eq!(addresses.len(), DISASSEMBLY_LINES);
+        assert_eq!(addresses[DISASSEMBLY_LINES / 2], 0x210);
+        assert_eq!(addresses[0], 0x210 - 12);
+        assert_eq!(disassembly_addresses(0x210, 2, 4096)[DISASSEMBLY_LINES / 2], 0x214);
+    }
+
+    #[test]
+    fn disassembly_stops_at_ends_of_memory() {
+        assert_eq!(disassembly_addresses(0x000, 0, 4096)[0], 0x000);
+        assert_eq!(*disassembly_addresses(0xFFE, 0, 4096).last().unwrap(), 0xFFE);
+    }
+
+    #[test]
+    fn stack_lines_show_every_level() {
+        let stack: Vec<usize> = (0..32).map(|level| 0x200 + level * 2).collect();
+        let lines = stack_lines(&stack);
+
+        assert_eq!(lines.len(), 4);
+        assert_eq!(lines[0], "31: 23E 23C 23A 238 236 234 232 230");
+        assert_eq!(lines[3], "07: 20E 20C 20A 208 206 204 202 200");
+        assert_eq!(stack_lines(&stack[..2]), vec!["01: 202 200"]);
+        assert!(stack_lines(&[]).is_empty());
+    }
+
+    #[test]
+    fn fields_are_edited_in_hex() {
+        let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
+
+        Field::Register(0xA).set(&mut chip, "2F").unwrap();
+        Field::I.set(&mut chip, "3A0").unwrap();
+        Field::Memory(0x300).set(&mut chip, "7").unwrap();
+
+        assert_eq!(chip.registers[0xA], 0x2F);
+        assert_eq!(chip.i, 0x3A0);
+        assert_eq!(chip.memory[0x300], 0x07);
+        assert!(Field::Register(0).set(&mut chip, "100").is_err());
+        assert!(Field::Pc.set(&mut chip, "").is_err());
+    }
+}
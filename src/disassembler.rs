@@ -0,0 +1,69 @@
+use crate::instruction::Instruction;
+
+// Turns instructions into the mnemonics from Cowgod's CHIP-8 technical reference, like `LD V0, 0x01`,
+// through the decoder the chip runs so the two can't disagree about what an opcode is
+pub fn disassemble(instruction: u16) -> String {
+    match Instruction::decode(instruction) {
+        Instruction::ClearScreen => String::from("CLS"),
+        Instruction::Return => String::from("RET"),
+        // 0000 exits here, but it's still a 0NNN to the reference
+        Instruction::Exit | Instruction::MachineCall => format!("SYS 0x{:03X}", instruction & 0xFFF),
+        Instruction::Jump(nnn) => format!("JP 0x{:03X}", nnn),
+        Instruction::Call(nnn) => format!("CALL 0x{:03X}", nnn),
+        Instruction::SkipIfEqual(x, nn) => format!("SE V{:X}, 0x{:02X}", x, nn),
+        Instruction::SkipIfNotEqual(x, nn) => format!("SNE V{:X}, 0x{:02X}", x, nn),
+        Instruction::SkipIfRegistersEqual(x, y) => format!("SE V{:X}, V{:X}", x, y),
+        Instruction::Load(x, nn) => format!("LD V{:X}, 0x{:02X}", x, nn),
+        Instruction::Add(x, nn) => format!("ADD V{:X}, 0x{:02X}", x, nn),
+        Instruction::Copy(x, y) => format!("LD V{:X}, V{:X}", x, y),
+        Instruction::Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+        Instruction::And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+        Instruction::Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+        Instruction::AddRegisters(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+        Instruction::Subtract(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+        Instruction::ShiftRight(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+        Instruction::SubtractReversed(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+        Instruction::ShiftLeft(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+        Instruction::SkipIfRegistersNotEqual(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+        Instruction::LoadI(nnn) => format!("LD I, 0x{:03X}", nnn),
+        // The reference always names V0, even for chips that jump from VX
+        Instruction::JumpWithOffset(_, nnn) => format!("JP V0, 0x{:03X}", nnn),
+        Instruction::Random(x, nn) => format!("RND V{:X}, 0x{:02X}", x, nn),
+        Instruction::Draw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+        Instruction::SkipIfKey(x) => format!("SKP V{:X}", x),
+        Instruction::SkipIfNotKey(x) => format!("SKNP V{:X}", x),
+        Instruction::LoadDelayTimer(x) => format!("LD V{:X}, DT", x),
+        Instruction::WaitForKey(x) => format!("LD V{:X}, K", x),
+        Instruction::SetDelayTimer(x) => format!("LD DT, V{:X}", x),
+        Instruction::SetSoundTimer(x) => format!("LD ST, V{:X}", x),
+        Instruction::AddToI(x) => format!("ADD I, V{:X}", x),
+        Instruction::LoadFontCharacter(x) => format!("LD F, V{:X}", x),
+        Instruction::StoreDecimal(x) => format!("LD B, V{:X}", x),
+        Instruction::StoreRegisters(x) => format!("LD [I], V{:X}", x),
+        Instruction::LoadRegisters(x) => format!("LD V{:X}, [I]", x),
+        // Not an instruction, probably sprite data
+        Instruction::Unknown => format!("DW 0x{:04X}", instruction)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn disassembles_instructions_with_operands() {
+        assert_eq!(disassemble(0x00E0), "CLS");
+        assert_eq!(disassemble(0x0000), "SYS 0x000");
+        assert_eq!(disassemble(0x1234), "JP 0x234");
+        assert_eq!(disassemble(0x6A0F), "LD VA, 0x0F");
+        assert_eq!(disassemble(0x8CDE), "SHL VC, VD");
+        assert_eq!(disassemble(0xD125), "DRW V1, V2, 5");
+        assert_eq!(disassemble(0xF365), "LD V3, [I]");
+    }
+
+    #[test]
+    fn data_is_shown_as_words() {
+        assert_eq!(disassemble(0x5121), "DW 0x5121");
+        assert_eq!(disassemble(0xFFFF), "DW 0xFFFF");
+    }
+}
@@ -1,159 +1,263 @@
+use std::path::Path;
 use clap::ValueEnum;
+use serde::Deserialize;
 use macroquad::prelude::*;
+use crate::filter::{Filter, FilterType};
+use crate::overlay::Overlay;
+use crate::palette::Palette;
+use crate::terminal::{TerminalMode, TerminalRenderer};
 
 pub const DISPLAY_WIDTH: usize = 64;
 pub const DISPLAY_HEIGHT: usize = 32;
 pub const DISPLAY_SIZE: usize = DISPLAY_WIDTH * DISPLAY_HEIGHT;
 pub const PIXEL_DIMENSION: f32 = 20.;
+pub const DEBUGGER_PIXEL_DIMENSION: f32 = 10.; // Leaves room for the debugger panels
+const OVERLAY_FONT_SIZE: f32 = 24.;
 
-pub fn window_conf() -> Conf {
+pub fn window_conf(rom_title: &str) -> Conf {
     Conf {
-        window_title: "RUSTYCHIP-8".to_owned(),
+        window_title: format!("RUSTYCHIP-8 - {}", rom_title),
         window_width: 1280,
         window_height: 640,
         ..Default::default()
     }
 }
 
-#[derive(ValueEnum, PartialEq, Clone, Debug)]
+#[derive(ValueEnum, Deserialize, PartialEq, Clone, Debug)]
+#[serde(rename_all = "lowercase")]
 pub enum DisplayType {
     Macroquad,
     Terminal
 }
 
+// Each row is packed into an integer, with the leftmost pixel in the highest bit, so a sprite row
+// is drawn with one XOR. There's a single plane, since only XO-CHIP has more
+pub type Plane = [u64; DISPLAY_HEIGHT];
+
 pub struct Display {
-    pub display: [bool; DISPLAY_SIZE],
-    display_type: DisplayType
+    pub plane: Plane,
+    changed: bool, // Since the chip last asked, so frontends can skip redrawing
+    frame_ended: bool, // Since the filter last ran, which it only does once per emulated frame
+    display_type: DisplayType,
+    filter: Filter,
+    palette: Palette,
+    terminal: TerminalRenderer,
+    pub overlay: Overlay,
+    pub pixel_dimension: f32
 }
 
-#[async_trait::async_trait]
 trait MacroquadDisplay {
-    async fn render(&self);
+    fn render(&mut self);
 }
 
 trait TerminalDisplay {
-    fn render(&self);
+    fn render(&mut self);
 }
 
-#[async_trait::async_trait]
 impl MacroquadDisplay for Display {
-    async fn render(&self) {
+    fn render(&mut self) {
         clear_background(BLACK);
 
+        let [on_red, on_green, on_blue] = self.palette.foreground;
+        let [off_red, off_green, off_blue] = self.palette.background;
+        let pixel_on_color = Color::from_rgba(on_red, on_green, on_blue, 255);
+        let pixel_off_color = Color::from_rgba(off_red, off_green, off_blue, 255);
+        let brightness = *self.brightness();
+
         for row in 0..DISPLAY_HEIGHT {
             for column in 0..DISPLAY_WIDTH {
                 let pixel = DISPLAY_WIDTH * row + column;
-                let pixel_height = PIXEL_DIMENSION * row as f32;
-                let pixel_width = PIXEL_DIMENSION * column as f32;
+                let pixel_height = self.pixel_dimension * row as f32;
+                let pixel_width = self.pixel_dimension * column as f32;
 
-                let pixel_on_color = Color::new(255., 176., 0., 255.);
-                let pixel_off_color = BLACK;
-                let pixel_color = if self.display[pixel] == true { pixel_on_color } else { pixel_off_color };
+                let pixel_color = Color::new(
+                    pixel_off_color.r + (pixel_on_color.r - pixel_off_color.r) * brightness[pixel],
+                    pixel_off_color.g + (pixel_on_color.g - pixel_off_color.g) * brightness[pixel],
+                    pixel_off_color.b + (pixel_on_color.b - pixel_off_color.b) * brightness[pixel],
+                    1.
+                );
 
-                draw_rectangle(pixel_width, pixel_height, PIXEL_DIMENSION, PIXEL_DIMENSION, pixel_color);
+                draw_rectangle(pixel_width, pixel_height, self.pixel_dimension, self.pixel_dimension, pixel_color);
             }
         }
 
-        next_frame().await;
+        draw_overlay(&self.overlay.lines(), pixel_on_color);
+    }
+}
+
+// Drawn on a dark box in the top left, so it can be read over any pixels
+fn draw_overlay(lines: &[String], color: Color) {
+    if lines.is_empty() {
+        return;
+    }
+
+    let padding = OVERLAY_FONT_SIZE / 2.;
+    let width = lines
+        .iter()
+        .map(|line| measure_text(line, None, OVERLAY_FONT_SIZE as u16, 1.).width)
+        .fold(0., f32::max);
+    let height = OVERLAY_FONT_SIZE * lines.len() as f32;
+    draw_rectangle(0., 0., width + padding * 2., height + padding * 2., Color::new(0., 0., 0., 0.7));
+
+    for (index, line) in lines.iter().enumerate() {
+        let baseline = padding + OVERLAY_FONT_SIZE * (index as f32 + 0.75);
+        draw_text(line, padding, baseline, OVERLAY_FONT_SIZE, color);
     }
 }
 
 impl TerminalDisplay for Display {
-    fn render(&self) {
-        // Clear screen
-        print!("\x1B[2J\x1B[1;1H");
+    fn render(&mut self) {
+        self.terminal.render(&self.pixels());
+    }
+}
 
-        let mut str_to_print = String::new();
-        for _ in 0..DISPLAY_WIDTH {
-            str_to_print.push_str("-");
-        }
-        str_to_print.push_str("\n");
-        for row in 0..DISPLAY_HEIGHT {
-            str_to_print.push_str("|");
-            for column in 0..DISPLAY_WIDTH {
-                let pixel = DISPLAY_WIDTH * row + column;
-                let sprite = if self.display[pixel] == true { "X" } else { " " };
-                str_to_print.push_str(sprite);
-            }
-            str_to_print.push_str("|\n");
-        }
-        for _ in 0..DISPLAY_WIDTH {
-            str_to_print.push_str("-");
+// A copy has the same screen and settings, but its own terminal renderer, which only takes the
+// terminal over if the copy is printed
+impl Clone for Display {
+    fn clone(&self) -> Self {
+        Self {
+            plane: self.plane,
+            changed: self.changed,
+            frame_ended: self.frame_ended,
+            display_type: self.display_type.clone(),
+            filter: self.filter.clone(),
+            palette: self.palette,
+            terminal: self.terminal.detached(),
+            overlay: self.overlay.clone(),
+            pixel_dimension: self.pixel_dimension
         }
-        str_to_print.push_str("\n");
-
-        println!("{}", str_to_print);
     }
 }
 
 impl Display {
     pub fn new(display_type: DisplayType) -> Self {
         Self {
-            display: [false; DISPLAY_SIZE],
-            display_type
+            plane: [0; DISPLAY_HEIGHT],
+            changed: false,
+            frame_ended: true,
+            display_type,
+            filter: Filter::new(FilterType::None, 0.),
+            palette: Palette::default(),
+            terminal: TerminalRenderer::new(TerminalMode::Auto, Palette::default()),
+            overlay: Overlay::default(),
+            pixel_dimension: PIXEL_DIMENSION
         }
     }
 
+    pub fn display_type(&self) -> &DisplayType {
+        &self.display_type
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.terminal.set_palette(palette);
+    }
+
+    pub fn set_terminal_mode(&mut self, mode: TerminalMode) {
+        self.terminal.set_mode(mode);
+    }
+
+    // Gives the terminal back before exiting, since the terminal display takes it over
+    pub fn restore_terminal(&mut self) {
+        self.terminal.restore();
+    }
+
+    pub fn set_filter(&mut self, filter_type: FilterType, decay: f32) {
+        self.filter = Filter::new(filter_type, decay);
+        self.frame_ended = true;
+    }
+
+    // The filter fades and merges whole frames, so it moves on when the emulator finishes one
+    // rather than every time the screen is drawn, however often that is
+    pub fn end_frame(&mut self) {
+        self.frame_ended = true;
+    }
+
+    fn brightness(&mut self) -> &[f32; DISPLAY_SIZE] {
+        if std::mem::take(&mut self.frame_ended) {
+            let pixels = self.pixels();
+            self.filter.apply(&pixels);
+        }
+        self.filter.brightness()
+    }
+
     pub fn clear(&mut self) {
-        self.display = [false; DISPLAY_SIZE];
+        self.changed |= self.plane != [0; DISPLAY_HEIGHT];
+        self.plane = [0; DISPLAY_HEIGHT];
     }
 
-    pub fn set_pixel(&mut self, pixel_index: usize, value: bool) {
+    pub fn set_plane(&mut self, plane: Plane) {
+        self.changed |= self.plane != plane;
+        self.plane = plane;
+    }
+
+    pub fn take_changed(&mut self) -> bool {
+        std::mem::take(&mut self.changed)
+    }
+
+    fn pixel_bit(pixel_index: usize) -> (usize, u64) {
         let actual_index = pixel_index % DISPLAY_SIZE;
-        self.display[actual_index] = value;
+        (actual_index / DISPLAY_WIDTH, 1 << (DISPLAY_WIDTH - 1 - actual_index % DISPLAY_WIDTH))
+    }
+
+    pub fn set_pixel(&mut self, pixel_index: usize, value: bool) {
+        let (row, bit) = Self::pixel_bit(pixel_index);
+        self.changed |= self.get_pixel(pixel_index) != value;
+        if value {
+            self.plane[row] |= bit;
+        } else {
+            self.plane[row] &= !bit;
+        }
     }
 
     pub fn get_pixel(&self, pixel_index: usize) -> bool {
-        let actual_index = pixel_index % DISPLAY_SIZE;
-        self.display[actual_index]
+        let (row, bit) = Self::pixel_bit(pixel_index);
+        self.plane[row] & bit != 0
     }
 
-    pub async fn print(&self) {
+    // One bool per pixel, row by row, for renderers that go pixel by pixel
+    pub fn pixels(&self) -> [bool; DISPLAY_SIZE] {
+        let mut pixels = [false; DISPLAY_SIZE];
+        for (pixel_index, pixel) in pixels.iter_mut().enumerate() {
+            *pixel = self.get_pixel(pixel_index);
+        }
+        pixels
+    }
+
+    // Macroquad only shows what's drawn once the frame ends, which is left to the caller so it
+    // can draw more on top
+    pub fn print(&mut self) {
         if self.display_type == DisplayType::Macroquad {
-            MacroquadDisplay::render(self).await;
+            MacroquadDisplay::render(self);
         } else {
             TerminalDisplay::render(self);
         }
     }
 
-    pub async fn draw_sprite(&mut self, x_index: usize, y_index: usize, height: u8, sprite: Vec<u8>) -> bool {
-        let mut starting_index = x_index + y_index * DISPLAY_WIDTH as usize;
-        let mut flipped_pixel_to_off = false;
-
-        for row in 0..height {
-            // Each row is a byte in memory, so to get the next row, go to the next memory addr
-            let pixel_pattern = sprite[row as usize];
-            if row as usize + y_index >= DISPLAY_HEIGHT {
-                break;
-            }
+    // Saves the screen as a PNG in the palette's colors, one image pixel per CHIP-8 pixel
+    pub fn screenshot(&self, path: &Path) -> Result<(), String> {
+        let pixels: Vec<u8> = self.pixels()
+            .iter()
+            .flat_map(|pixel| if *pixel { self.palette.foreground } else { self.palette.background })
+            .collect();
+        image::save_buffer(path, &pixels, DISPLAY_WIDTH as u32, DISPLAY_HEIGHT as u32, image::ColorType::Rgb8)
+            .map_err(|error| format!("Can't save a screenshot to {}: {}", path.display(), error))
+    }
 
-            let max_x_in_row = (starting_index / DISPLAY_WIDTH) * DISPLAY_WIDTH + (DISPLAY_WIDTH - 1);
-            for offset in 0..8 {
-                let pixel_index = starting_index + offset;
-                if pixel_index >= max_x_in_row {
-                    break;
-                }
-
-                let pixel_bit = (pixel_pattern >> 7 - offset) & 1;
-                let display_bit = self.get_pixel(pixel_index);
-                let new_value = match (pixel_bit, display_bit) {
-                    (0, false) => false,
-                    (1, false) => true,
-                    (0, true) => true,
-                    (1, true) => {
-                        flipped_pixel_to_off = true;
-                        false
-                    },
-                    _ => panic!("This wasn't supposed to happen: {} {}", pixel_bit, display_bit)
-                };
-
-                self.set_pixel(pixel_index, new_value);
-            }
+    // The sprite starts at (x, y) wrapped onto the screen, and is clipped at the right and bottom
+    // edges. Returns whether any pixel was turned off
+    pub fn draw_sprite(&mut self, x: usize, y: usize, sprite: &[u8]) -> bool {
+        let x = x % DISPLAY_WIDTH;
+        let y = y % DISPLAY_HEIGHT;
+        let mut flipped_pixel_to_off = false;
 
-            starting_index += DISPLAY_WIDTH;
+        for (row, pixel_pattern) in self.plane[y..].iter_mut().zip(sprite) {
+            let pixels = (*pixel_pattern as u64) << (DISPLAY_WIDTH - 8) >> x;
+            flipped_pixel_to_off |= *row & pixels != 0;
+            self.changed |= pixels != 0;
+            *row ^= pixels;
         }
 
-        self.print().await;
         flipped_pixel_to_off
     }
 }
@@ -165,7 +269,51 @@ mod tests {
     #[test]
     fn initial_display_is_64_by_32_pixels_all_empty() {
         let display = Display::new(DisplayType::Terminal);
-        assert_eq!(display.display, [false; 64 * 32]);
+        assert_eq!(display.pixels(), [false; 64 * 32]);
     }
-}
 
+    #[test]
+    fn sprites_flip_pixels_and_report_collisions() {
+        let mut display = Display::new(DisplayType::Terminal);
+
+        assert!(!display.draw_sprite(3, 1, &[0b1010_0000]));
+        assert!(display.get_pixel(DISPLAY_WIDTH + 3));
+        assert!(!display.get_pixel(DISPLAY_WIDTH + 4));
+        assert!(display.get_pixel(DISPLAY_WIDTH + 5));
+
+        assert!(display.draw_sprite(4, 1, &[0b1100_0000]));
+        assert!(display.get_pixel(DISPLAY_WIDTH + 4));
+        assert!(!display.get_pixel(DISPLAY_WIDTH + 5));
+    }
+
+    #[test]
+    fn sprites_wrap_their_start_and_clip_at_the_edges() {
+        let mut display = Display::new(DisplayType::Terminal);
+
+        // (68, 31) wraps to (4, 31), and the second row falls off the bottom
+        display.draw_sprite(68, 31, &[0xFF, 0xFF]);
+        assert_eq!(display.plane[31], 0x0FF0_0000_0000_0000);
+        assert_eq!(display.plane[0], 0);
+
+        // Only the first four columns fit at the right edge
+        display.draw_sprite(60, 0, &[0xFF]);
+        assert_eq!(display.plane[0], 0xF);
+        assert!(display.get_pixel(DISPLAY_WIDTH - 1));
+    }
+
+    #[test]
+    fn filter_only_moves_on_when_a_frame_ends() {
+        let mut display = Display::new(DisplayType::Macroquad);
+        display.set_filter(FilterType::Phosphor, 0.5);
+        display.set_pixel(10, true);
+        assert_eq!(display.brightness()[10], 1.);
+
+        display.set_pixel(10, false);
+        assert_eq!(display.brightness()[10], 1.);
+        assert_eq!(display.brightness()[10], 1.);
+
+        display.end_frame();
+        assert_eq!(display.brightness()[10], 0.5);
+        assert_eq!(display.brightness()[10], 0.5);
+    }
+}
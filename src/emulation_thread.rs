@@ -0,0 +1,288 @@
+use std::path::PathBuf;
+use std::sync::mpsc::{self, Receiver, Sender, TryRecvError};
+use std::thread::{self, JoinHandle};
+use crate::chip::{Chip, ChipType};
+use crate::display::Plane;
+use crate::emulator::{change_speed, reload};
+use crate::scheduler::{Scheduler, Speed};
+use crate::summary::Halt;
+use crate::timing::TimingMode;
+use crate::triple_buffer::{Reader, Writer, triple_buffer};
+use crate::watch::StopReason;
+
+// What the UI thread tells the emulation thread. Keys are keypad keys, already through the keymap
+#[derive(PartialEq, Clone, Debug)]
+pub enum Event {
+    KeyDown(u8),
+    KeyUp(u8),
+    Pause,
+    Advance,
+    Step,
+    Slower,
+    Faster,
+    Reset,
+    Turbo(bool), // Whether the turbo hotkey is held
+    Focused(bool),
+    Quit
+}
+
+// What the emulation thread tells the UI thread. Unlike frames these are queued, so none are missed
+#[derive(PartialEq, Clone, Debug)]
+pub enum Notice {
+    Message(String), // For the overlay
+    Stopped(StopReason),
+    Halted(Halt) // The thread stops after sending this
+}
+
+// The latest screen and what the overlay's status line needs
+#[derive(Clone, Debug)]
+pub struct Frame {
+    pub plane: Plane,
+    pub sequence: u64, // Goes up with each frame the chip runs, so renders of the same one can be told apart
+    pub instructions: u64, // Since the chip was created, so the UI can work out the rate
+    pub target_ips: Option<u128>,
+    pub chip_type: ChipType,
+    pub paused: bool
+}
+
+// Runs a Chip on its own thread at 60 frames a second, so a slow render can't hold the emulation
+// up. Input goes in as events, and frames come out through a triple buffer that always has the
+// latest one ready for the UI, however fast either side is going
+pub struct EmulationThread {
+    events: Sender<Event>,
+    notices: Receiver<Notice>,
+    frames: Reader<Frame>,
+    handle: Option<JoinHandle<()>>
+}
+
+impl EmulationThread {
+    // The chip should already have its ROM loaded. It stops instead of exiting when the ROM ends
+    pub fn spawn(mut chip: Chip, rom_path: Option<PathBuf>, rom: Vec<u8>, speed: Speed, turbo_factor: u32) -> Self {
+        chip.exit_on_halt = false;
+        let (events, event_receiver) = mpsc::channel();
+        let (notice_sender, notices) = mpsc::channel();
+        let (frame_writer, frames) = triple_buffer(frame(&chip, 0, false));
+
+        let core = Core {
+            chip,
+            rom_path,
+            rom,
+            speed,
+            turbo_factor,
+            events: event_receiver,
+            notices: notice_sender,
+            frames: frame_writer,
+            sequence: 0,
+            paused: false,
+            focused: true,
+            turbo: false
+        };
+        let handle = thread::Builder::new()
+            .name(String::from("emulation"))
+            .spawn(move || core.run())
+            .expect("Should be able to start the emulation thread");
+
+        EmulationThread { events, notices, frames, handle: Some(handle) }
+    }
+
+    // Events sent after the thread has stopped are dropped, since there's nothing left to act on them
+    pub fn send(&self, event: Event) {
+        let _ = self.events.send(event);
+    }
+
+    pub fn notices(&self) -> impl Iterator<Item = Notice> + '_ {
+        self.notices.try_iter()
+    }
+
+    pub fn frame(&mut self) -> &Frame {
+        self.frames.read()
+    }
+}
+
+impl Drop for EmulationThread {
+    fn drop(&mut self) {
+        self.send(Event::Quit);
+        if let Some(handle) = self.handle.take() {
+            let _ = handle.join();
+        }
+    }
+}
+
+fn frame(chip: &Chip, sequence: u64, paused: bool) -> Frame {
+    Frame {
+        plane: chip.display.plane,
+        sequence,
+        instructions: chip.instructions,
+        target_ips: (chip.timing == TimingMode::Ips).then_some(chip.target_ips),
+        chip_type: chip.chip_type.clone(),
+        paused
+    }
+}
+
+// The emulation thread's side
+struct Core {
+    chip: Chip,
+    rom_path: Option<PathBuf>,
+    rom: Vec<u8>,
+    speed: Speed,
+    turbo_factor: u32,
+    events: Receiver<Event>,
+    notices: Sender<Notice>,
+    frames: Writer<Frame>,
+    sequence: u64,
+    paused: bool,
+    focused: bool,
+    turbo: bool
+}
+
+impl Core {
+    fn run(mut self) {
+        let mut scheduler = Scheduler::new();
+        loop {
+            let mut advance = false;
+            let mut step = false;
+            loop {
+                let event = match self.events.try_recv() {
+                    Ok(event) => event,
+                    Err(TryRecvError::Empty) => break,
+                    Err(TryRecvError::Disconnected) => return
+                };
+                match event {
+                    Event::KeyDown(key) => self.chip.input.press_key(key),
+                    Event::KeyUp(key) => self.chip.input.release_key(key),
+                    Event::Pause => {
+                        self.paused = !self.paused;
+                        self.notify(String::from(if self.paused { "Paused" } else { "Resumed" }));
+                    },
+                    Event::Advance => advance = true,
+                    Event::Step => step = true,
+                    Event::Slower => {
+                        let message = change_speed(&mut self.chip, false);
+                        self.notify(message);
+                    },
+                    Event::Faster => {
+                        let message = change_speed(&mut self.chip, true);
+                        self.notify(message);
+                    },
+                    Event::Reset => {
+                        if let Some(message) = reload(&mut self.chip, self.rom_path.as_ref(), &mut self.rom) {
+                            self.notify(message);
+                        }
+                    },
+                    Event::Turbo(turbo) => self.turbo = turbo,
+                    Event::Focused(focused) => self.focused = focused,
+                    Event::Quit => return
+                }
+            }
+
+            // Pauses while another window has focus, like the single threaded loop
+            let running = !self.paused && self.focused;
+            if running {
+                self.run_frames(&scheduler);
+                if let Some(stop_reason) = &self.chip.stop_reason {
+                    self.paused = true;
+                    let _ = self.notices.send(Notice::Stopped(stop_reason.clone()));
+                }
+            } else if advance {
+                self.chip.run_frame();
+            } else if step {
+                self.chip.step();
+            }
+
+            if running || advance || step {
+                self.sequence += 1;
+            }
+            *self.frames.back() = frame(&self.chip, self.sequence, !running);
+            self.frames.publish();
+            if let Some(halt) = self.chip.halted.take() {
+                let _ = self.notices.send(Notice::Halted(halt));
+                return;
+            }
+            scheduler.wait_for_next_frame();
+        }
+    }
+
+    fn run_frames(&mut self, scheduler: &Scheduler) {
+        match self.speed {
+            Speed::Normal => {
+                let frames = if self.turbo { self.turbo_factor } else { 1 };
+                for _ in 0..frames {
+                    self.chip.run_frame();
+                    if self.chip.stop_reason.is_some() || self.chip.halted.is_some() {
+                        break;
+                    }
+                }
+            },
+            Speed::Uncapped => {
+                self.chip.run_frame();
+                while !scheduler.frame_due() && self.chip.stop_reason.is_none() && self.chip.halted.is_none() {
+                    self.chip.run_frame();
+                }
+            }
+        }
+    }
+
+    fn notify(&self, message: String) {
+        let _ = self.notices.send(Notice::Message(message));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+    use crate::display::DISPLAY_HEIGHT;
+    use super::*;
+
+    fn wait_for(emulation: &mut EmulationThread, done: impl Fn(&Frame) -> bool) -> Frame {
+        let deadline = Instant::now() + Duration::from_secs(5);
+        loop {
+            let frame = emulation.frame();
+            if done(frame) {
+                return frame.clone();
+            }
+            assert!(Instant::now() < deadline, "The emulation thread didn't get there in time");
+            thread::sleep(Duration::from_millis(1));
+        }
+    }
+
+    #[test]
+    fn frames_follow_the_keys_sent() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        // Loops until key 1 is down, then draws its glyph
+        chip.load_rom(&[0x60, 0x01, 0xE0, 0x9E, 0x12, 0x02, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x0A]).unwrap();
+        let mut emulation = EmulationThread::spawn(chip, None, vec![], Speed::Normal, 8);
+        assert_eq!(emulation.frame().plane, [0; DISPLAY_HEIGHT]);
+
+        emulation.send(Event::KeyDown(1));
+        let frame = wait_for(&mut emulation, |frame| frame.plane != [0; DISPLAY_HEIGHT]);
+        // The top row of the 1 glyph
+        assert_eq!(frame.plane[0], 0x20 << 56);
+        assert!(!frame.paused);
+    }
+
+    #[test]
+    fn halting_is_reported() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        chip.load_rom(&[0x00, 0x00]).unwrap();
+        let emulation = EmulationThread::spawn(chip, None, vec![], Speed::Normal, 8);
+
+        let notice = emulation.notices.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert_eq!(notice, Notice::Halted(Halt::Exited));
+    }
+
+    #[test]
+    fn the_sequence_only_moves_on_when_the_chip_runs() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        chip.load_rom(&[0x12, 0x00]).unwrap();
+        let mut emulation = EmulationThread::spawn(chip, None, vec![], Speed::Normal, 8);
+        wait_for(&mut emulation, |frame| frame.sequence > 0);
+
+        emulation.send(Event::Pause);
+        let paused = wait_for(&mut emulation, |frame| frame.paused);
+        thread::sleep(Duration::from_millis(100));
+        assert_eq!(emulation.frame().sequence, paused.sequence);
+
+        emulation.send(Event::Advance);
+        wait_for(&mut emulation, |frame| frame.sequence == paused.sequence + 1);
+    }
+}
@@ -0,0 +1,450 @@
+use std::fs;
+#[cfg(not(target_arch = "wasm32"))]
+use std::mem;
+use std::path::PathBuf;
+use macroquad::input::get_dropped_files;
+use macroquad::window::next_frame;
+use macroquad::DroppedFile;
+use crate::chip::Chip;
+use crate::debugger::Debugger;
+use crate::display::{DEBUGGER_PIXEL_DIMENSION, DisplayType, PIXEL_DIMENSION};
+#[cfg(not(target_arch = "wasm32"))]
+use crate::emulation_thread::{EmulationThread, Event, Notice};
+use crate::gdb::GdbStub;
+use crate::hotkeys::{Hotkey, Hotkeys};
+use crate::overlay::{RateCounter, Status};
+use crate::scheduler::{FRAMES_PER_SECOND, Scheduler, Speed};
+use crate::script::{Script, ScriptExit};
+#[cfg(target_arch = "wasm32")]
+use crate::sound::Beeper;
+use crate::summary::Halt;
+use crate::timing::TimingMode;
+
+const DROP_ROM_PROMPT: &str = "Drop a CHIP-8 ROM here to play it";
+
+// Runs the Chip a frame at a time, handling the hotkeys between frames
+pub struct Emulator {
+    pub chip: Chip,
+    rom_path: Option<PathBuf>, // Where reset reloads the ROM from, if it came from a file
+    rom: Vec<u8>, // Empty until a ROM is dropped on the window, when started without one
+    speed: Speed,
+    turbo_factor: u32,
+    hotkeys: Hotkeys,
+    debugger: Debugger,
+    gdb: Option<GdbStub>,
+    script: Option<Script>,
+    paused: bool
+}
+
+impl Emulator {
+    // The ROM should already be loaded into the chip
+    pub fn new(chip: Chip, rom_path: Option<PathBuf>, rom: Vec<u8>, speed: Speed, turbo_factor: u32) -> Self {
+        Emulator {
+            chip,
+            rom_path,
+            rom,
+            speed,
+            turbo_factor,
+            hotkeys: Hotkeys::default(),
+            debugger: Debugger::new(),
+            gdb: None,
+            script: None,
+            paused: false
+        }
+    }
+
+    // Starts paused, so a debugger can attach before the first instruction runs
+    pub fn attach_gdb(&mut self, gdb: GdbStub) {
+        self.gdb = Some(gdb);
+        self.paused = true;
+    }
+
+    pub fn set_script(&mut self, script: Script) {
+        self.script = Some(script);
+    }
+
+    pub async fn run(mut self) {
+        if let Some(script) = &mut self.script {
+            script.start(&mut self.chip);
+        }
+        self.check_script();
+
+        if self.rom.is_empty() {
+            self.chip.display.overlay.hud = vec![String::from(DROP_ROM_PROMPT)];
+        }
+        #[cfg(target_arch = "wasm32")]
+        let mut beeper = Beeper::new().await;
+
+        let mut scheduler = Scheduler::new();
+        let mut instructions_per_second = RateCounter::new();
+        let mut frames_per_second = RateCounter::new();
+        loop {
+            if *self.chip.display.display_type() == DisplayType::Macroquad {
+                self.load_dropped_rom();
+            }
+
+            if self.chip.input.update() {
+                self.chip.exit(0);
+            }
+
+            let host_keys = self.chip.input.host_keys();
+            let mut hotkeys = self.hotkeys.update(&host_keys);
+            if self.debugger.editing() {
+                hotkeys.clear();
+            }
+
+            let mut advance = false;
+            let mut step = false;
+            for hotkey in hotkeys {
+                match hotkey {
+                    Hotkey::Overlay => self.chip.display.overlay.visible = !self.chip.display.overlay.visible,
+                    Hotkey::Pause => {
+                        self.paused = !self.paused;
+                        let message = if self.paused { "Paused" } else { "Resumed" };
+                        self.chip.display.overlay.notify(String::from(message));
+                    },
+                    Hotkey::Debugger => self.toggle_debugger(),
+                    Hotkey::Advance => advance = true,
+                    Hotkey::Step => step = true,
+                    Hotkey::Slower => self.change_speed(false),
+                    Hotkey::Faster => self.change_speed(true),
+                    Hotkey::Reset => self.reset(),
+                    Hotkey::Turbo => (),
+                    Hotkey::Quit => self.quit()
+                }
+            }
+
+            if let Some(gdb) = &mut self.gdb {
+                if let Some(running) = gdb.poll(&mut self.chip) {
+                    self.paused = !running;
+                }
+            }
+
+            // Pauses while another window has focus, and carries on when it comes back
+            let running = !self.paused && self.chip.input.focused() && !self.rom.is_empty();
+            let instructions_before = self.chip.instructions;
+            if running {
+                if scheduler.frame_ready() {
+                    self.run_frames(&scheduler);
+                }
+                if let Some(stop_reason) = &self.chip.stop_reason {
+                    self.chip.display.end_frame(); // So what the frame drew before stopping shows
+                    self.paused = true;
+                    self.chip.display.overlay.notify(stop_reason.to_string());
+                    if !self.debugger.visible {
+                        self.toggle_debugger();
+                    }
+                }
+            } else if advance {
+                self.run_frame();
+            } else if step {
+                self.chip.step();
+                self.chip.display.end_frame();
+            }
+            self.check_script();
+
+            // Stopping at a breakpoint or with the pause hotkey is reported to the debugger
+            if let Some(gdb) = &mut self.gdb {
+                if self.paused {
+                    gdb.report_stop(&self.chip);
+                }
+            }
+
+            instructions_per_second.add(self.chip.instructions - instructions_before);
+            frames_per_second.add(1);
+            self.chip.display.overlay.set_status(Status {
+                ips: instructions_per_second.rate(),
+                target_ips: (self.chip.timing == TimingMode::Ips).then_some(self.chip.target_ips),
+                fps: frames_per_second.rate(),
+                chip_type: self.chip.chip_type.clone(),
+                paused: !running
+            });
+
+            #[cfg(target_arch = "wasm32")]
+            if let Some(beeper) = &mut beeper {
+                beeper.update(running && self.chip.sound_timer.get() > 0);
+            }
+
+            self.chip.display.print();
+            if *self.chip.display.display_type() == DisplayType::Macroquad {
+                if self.debugger.visible {
+                    self.debugger.update(&mut self.chip, !running);
+                }
+                next_frame().await;
+            }
+            scheduler.wait_for_next_frame();
+        }
+    }
+
+    // Like run, but with the chip on its own thread so rendering at the monitor's refresh rate
+    // can't slow it down. Scripts, GDB, the debugger and dropped ROMs need the chip between
+    // frames, so they're left to run
+    #[cfg(not(target_arch = "wasm32"))]
+    pub async fn run_threaded(mut self) {
+        // The chip moves to the emulation thread, leaving a headless one here that holds the
+        // display and keyboard, and gives the terminal back on exit
+        let headless = Chip::headless(self.chip.target_ips, self.chip.chip_type.clone());
+        let mut chip = mem::replace(&mut self.chip, headless);
+        mem::swap(&mut chip.display, &mut self.chip.display);
+        mem::swap(&mut chip.input, &mut self.chip.input);
+        let mut emulation = EmulationThread::spawn(chip, self.rom_path.clone(), self.rom.clone(), self.speed, self.turbo_factor);
+
+        let mut scheduler = Scheduler::new();
+        let mut instructions_per_second = RateCounter::new();
+        let mut frames_per_second = RateCounter::new();
+        let mut instructions = emulation.frame().instructions;
+        let mut sequence = emulation.frame().sequence;
+        let mut keys: Vec<u8> = vec![];
+        let mut turbo = false;
+        let mut focused = true;
+        loop {
+            if self.chip.input.update() {
+                self.chip.exit(0);
+            }
+
+            let host_keys = self.chip.input.host_keys();
+            for hotkey in self.hotkeys.update(&host_keys) {
+                match hotkey {
+                    Hotkey::Overlay => self.chip.display.overlay.visible = !self.chip.display.overlay.visible,
+                    Hotkey::Debugger => self.chip.display.overlay.notify(String::from("The debugger doesn't run with --threaded")),
+                    Hotkey::Pause => emulation.send(Event::Pause),
+                    Hotkey::Advance => emulation.send(Event::Advance),
+                    Hotkey::Step => emulation.send(Event::Step),
+                    Hotkey::Slower => emulation.send(Event::Slower),
+                    Hotkey::Faster => emulation.send(Event::Faster),
+                    Hotkey::Reset => emulation.send(Event::Reset),
+                    Hotkey::Turbo => (),
+                    Hotkey::Quit => self.quit()
+                }
+            }
+            if self.hotkeys.held(Hotkey::Turbo) != turbo {
+                turbo = !turbo;
+                emulation.send(Event::Turbo(turbo));
+            }
+            if self.chip.input.focused() != focused {
+                focused = !focused;
+                emulation.send(Event::Focused(focused));
+            }
+
+            // Only changes are sent, so a key held down is one event however long it's held
+            let pressed_keys = self.chip.input.pressed_keys();
+            for key in pressed_keys.iter().filter(|key| !keys.contains(key)) {
+                emulation.send(Event::KeyDown(*key));
+            }
+            for key in keys.iter().filter(|key| !pressed_keys.contains(key)) {
+                emulation.send(Event::KeyUp(*key));
+            }
+            keys = pressed_keys;
+
+            for notice in emulation.notices() {
+                match notice {
+                    Notice::Message(message) => self.chip.display.overlay.notify(message),
+                    Notice::Stopped(stop_reason) => self.chip.display.overlay.notify(stop_reason.to_string()),
+                    Notice::Halted(Halt::Error(message)) => self.chip.exit_with_message(&message),
+                    Notice::Halted(Halt::Exited) => self.chip.exit(1)
+                }
+            }
+
+            // The window usually draws faster than 60 frames a second, so the filter only moves on
+            // when there's a new frame, not for each time the same one is drawn
+            let frame = emulation.frame();
+            if frame.sequence != sequence {
+                sequence = frame.sequence;
+                self.chip.display.set_plane(frame.plane);
+                self.chip.display.end_frame();
+            }
+            instructions_per_second.add(frame.instructions - instructions);
+            instructions = frame.instructions;
+            frames_per_second.add(1);
+            self.chip.display.overlay.set_status(Status {
+                ips: instructions_per_second.rate(),
+                target_ips: frame.target_ips,
+                fps: frames_per_second.rate(),
+                chip_type: frame.chip_type.clone(),
+                paused: frame.paused
+            });
+
+            self.chip.display.print();
+            if *self.chip.display.display_type() == DisplayType::Macroquad {
+                next_frame().await;
+            } else {
+                scheduler.wait_for_next_frame();
+            }
+        }
+    }
+
+    fn run_frames(&mut self, scheduler: &Scheduler) {
+        match self.speed {
+            Speed::Normal => {
+                let frames = if self.hotkeys.held(Hotkey::Turbo) { self.turbo_factor } else { 1 };
+                for _ in 0..frames {
+                    self.run_frame();
+                    if self.chip.stop_reason.is_some() {
+                        break;
+                    }
+                }
+            },
+            Speed::Uncapped => {
+                self.run_frame();
+                while !scheduler.frame_due() && self.chip.stop_reason.is_none() {
+                    self.run_frame();
+                }
+            }
+        }
+    }
+
+    fn run_frame(&mut self) {
+        match &mut self.script {
+            Some(script) => script.run_frame(&mut self.chip),
+            None => self.chip.run_frame()
+        };
+    }
+
+    // Exits when the script stops the emulator or fails
+    fn check_script(&mut self) {
+        match self.script.as_ref().and_then(Script::exit) {
+            Some(ScriptExit::Stop(code)) => self.chip.exit(*code),
+            Some(ScriptExit::Error(error)) => self.chip.exit_with_message(error),
+            None => ()
+        }
+    }
+
+    // The debugger is drawn in the macroquad window, next to a smaller framebuffer
+    fn toggle_debugger(&mut self) {
+        if *self.chip.display.display_type() != DisplayType::Macroquad {
+            return;
+        }
+        self.debugger.visible = !self.debugger.visible;
+        self.chip.display.pixel_dimension = if self.debugger.visible { DEBUGGER_PIXEL_DIMENSION } else { PIXEL_DIMENSION };
+    }
+
+    fn change_speed(&mut self, faster: bool) {
+        let message = change_speed(&mut self.chip, faster);
+        self.chip.display.overlay.notify(message);
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    fn quit(&mut self) {
+        self.chip.exit(0);
+    }
+
+    // Pages can't close their own tab
+    #[cfg(target_arch = "wasm32")]
+    fn quit(&mut self) {}
+
+    fn reset(&mut self) {
+        if let Some(message) = reload(&mut self.chip, self.rom_path.as_ref(), &mut self.rom) {
+            self.chip.display.overlay.notify(message);
+        }
+    }
+
+    // Dropped ROMs replace the running one. They're kept in memory, so resetting doesn't reload them.
+    // Each drop is only handed over once, so dropping the same ROM again restarts it
+    fn load_dropped_rom(&mut self) {
+        let Some(DroppedFile { path, bytes: Some(rom) }) = get_dropped_files().into_iter().next() else { return };
+
+        let name = path
+            .and_then(|path| path.file_name().map(|file_name| file_name.to_string_lossy().into_owned()))
+            .unwrap_or_else(|| String::from("the ROM"));
+        self.chip.reset();
+        match self.chip.load_rom(&rom) {
+            Ok(_) => {
+                if self.rom.is_empty() {
+                    self.chip.display.overlay.hud.clear();
+                }
+                self.rom = rom;
+                self.rom_path = None;
+                self.chip.display.overlay.notify(format!("Loaded {}", name));
+            },
+            Err(error) => {
+                self.chip.reset();
+                if !self.rom.is_empty() {
+                    self.chip.load_rom(&self.rom).expect("The ROM loaded before, so it should load again");
+                }
+                self.chip.display.overlay.notify(format!("Can't load {}: {}", name, error));
+            }
+        }
+    }
+}
+
+// Steps the instructions per frame by about a tenth, so it's useful at any speed. Returns the
+// message for the overlay
+pub(crate) fn change_speed(chip: &mut Chip, faster: bool) -> String {
+    let instructions_per_frame = chip.instructions_per_frame();
+    let change = (instructions_per_frame / 10).max(1);
+    let instructions_per_frame = if faster {
+        instructions_per_frame + change
+    } else {
+        instructions_per_frame.saturating_sub(change).max(1)
+    };
+    chip.target_ips = instructions_per_frame * FRAMES_PER_SECOND as u128;
+    format!("Speed {} instructions per frame", instructions_per_frame)
+}
+
+// Resets the chip and reads the ROM again so changes to it are picked up, falling back to the old
+// one if it can't be loaded. Returns the message for the overlay, or nothing if there's no ROM yet
+pub(crate) fn reload(chip: &mut Chip, rom_path: Option<&PathBuf>, rom: &mut Vec<u8>) -> Option<String> {
+    if rom.is_empty() {
+        return None;
+    }
+
+    chip.reset();
+    if let Some(new_rom) = rom_path.and_then(|rom_path| fs::read(rom_path).ok()) {
+        if chip.load_rom(&new_rom).is_ok() {
+            *rom = new_rom;
+            return Some(String::from("Reset"));
+        }
+        chip.reset();
+    }
+    chip.load_rom(rom).expect("The ROM loaded before, so it should load again");
+    let message = if rom_path.is_some() { "Reset, but couldn't reload the ROM so it's the old one" } else { "Reset" };
+    Some(String::from(message))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip::ChipType;
+    use crate::display::DisplayType;
+    use std::env;
+
+    fn emulator(rom_path: PathBuf, rom: &[u8]) -> Emulator {
+        let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
+        chip.load_rom(rom).unwrap();
+        Emulator::new(chip, Some(rom_path), rom.to_vec(), Speed::Normal, 8)
+    }
+
+    #[test]
+    fn change_speed_steps_instructions_per_frame() {
+        let mut emulator = emulator(PathBuf::from("missing.ch8"), &[0x12, 0x00]);
+
+        emulator.change_speed(true);
+        assert_eq!(emulator.chip.instructions_per_frame(), 22);
+
+        emulator.chip.target_ips = 60;
+        emulator.change_speed(false);
+        assert_eq!(emulator.chip.instructions_per_frame(), 1);
+    }
+
+    #[test]
+    fn reset_reloads_rom_from_disk() {
+        let rom_path = env::temp_dir().join("chip8_reset_reloads_rom_from_disk.ch8");
+        fs::write(&rom_path, [0x60, 0x02, 0x12, 0x02]).unwrap();
+        let mut emulator = emulator(rom_path.clone(), &[0x60, 0x01, 0x12, 0x02]);
+        emulator.chip.run_frame();
+        assert_eq!(emulator.chip.registers[0], 1);
+
+        emulator.reset();
+        assert_eq!(emulator.chip.registers[0], 0);
+        emulator.chip.run_frame();
+        assert_eq!(emulator.chip.registers[0], 2);
+
+        // Keeps the ROM it has if the new one is broken
+        fs::write(&rom_path, []).unwrap();
+        emulator.reset();
+        emulator.chip.run_frame();
+        assert_eq!(emulator.chip.registers[0], 2);
+
+        fs::remove_file(rom_path).unwrap();
+    }
+}
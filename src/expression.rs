@@ -0,0 +1,325 @@
+use std::fmt;
+use crate::chip::Chip;
+
+// Conditions for breakpoints, like `v3 == 0x10 && i > 0x300`. They can use the registers
+// v0 to vf, i, pc, dt, st, sp (the stack level) and mem[address], with C's operators for
+// comparing, combining and adding. True is anything but 0, like in C.
+#[derive(Clone, Debug)]
+pub struct Expression {
+    source: String,
+    node: Node
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Number(i64),
+    Variable(Variable),
+    Memory(Box<Node>),
+    Not(Box<Node>),
+    Negate(Box<Node>),
+    Binary(BinaryOp, Box<Node>, Box<Node>)
+}
+
+#[derive(Clone, Copy, Debug)]
+enum Variable {
+    Register(usize),
+    I,
+    Pc,
+    DelayTimer,
+    SoundTimer,
+    StackLevel
+}
+
+#[derive(Clone, Copy, Debug)]
+enum BinaryOp {
+    Or,
+    And,
+    BitOr,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Add,
+    Subtract
+}
+
+impl BinaryOp {
+    fn from_token(token: &str) -> Option<Self> {
+        match token {
+            "||" => Some(BinaryOp::Or),
+            "&&" => Some(BinaryOp::And),
+            "|" => Some(BinaryOp::BitOr),
+            "&" => Some(BinaryOp::BitAnd),
+            "==" => Some(BinaryOp::Equal),
+            "!=" => Some(BinaryOp::NotEqual),
+            "<" => Some(BinaryOp::Less),
+            "<=" => Some(BinaryOp::LessOrEqual),
+            ">" => Some(BinaryOp::Greater),
+            ">=" => Some(BinaryOp::GreaterOrEqual),
+            "+" => Some(BinaryOp::Add),
+            "-" => Some(BinaryOp::Subtract),
+            _ => None
+        }
+    }
+
+    // Higher binds tighter, in the same order as C
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::BitOr => 3,
+            BinaryOp::BitAnd => 4,
+            BinaryOp::Equal | BinaryOp::NotEqual => 5,
+            BinaryOp::Less | BinaryOp::LessOrEqual | BinaryOp::Greater | BinaryOp::GreaterOrEqual => 6,
+            BinaryOp::Add | BinaryOp::Subtract => 7
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+enum Token {
+    Number(i64),
+    Name(String),
+    Symbol(&'static str)
+}
+
+const SYMBOLS: [&str; 17] = ["||", "&&", "==", "!=", "<=", ">=", "<", ">", "|", "&", "+", "-", "!", "(", ")", "[", "]"];
+
+fn tokenize(source: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = source.trim_start();
+
+    while !rest.is_empty() {
+        if let Some(symbol) = SYMBOLS.iter().find(|symbol| rest.starts_with(**symbol)) {
+            tokens.push(Token::Symbol(symbol));
+            rest = &rest[symbol.len()..];
+        } else if rest.starts_with(|character: char| character.is_ascii_alphanumeric()) {
+            let length = rest.find(|character: char| !character.is_ascii_alphanumeric() && character != '_').unwrap_or(rest.len());
+            let word = &rest[..length];
+            tokens.push(if word.starts_with(|character: char| character.is_ascii_digit()) {
+                Token::Number(parse_number(word)?)
+            } else {
+                Token::Name(word.to_lowercase())
+            });
+            rest = &rest[length..];
+        } else {
+            return Err(format!("Unexpected `{}`", rest.chars().next().unwrap_or_default()));
+        }
+        rest = rest.trim_start();
+    }
+
+    Ok(tokens)
+}
+
+fn parse_number(word: &str) -> Result<i64, String> {
+    let number = match word.strip_prefix("0x").or(word.strip_prefix("0X")) {
+        Some(hex) => i64::from_str_radix(hex, 16),
+        None => word.parse()
+    };
+    number.map_err(|_| format!("`{}` isn't a number", word))
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn expect(&mut self, symbol: &str) -> Result<(), String> {
+        match self.next() {
+            Some(Token::Symbol(found)) if found == symbol => Ok(()),
+            _ => Err(format!("Expected `{}`", symbol))
+        }
+    }
+
+    // Precedence climbing: parses operators that bind at least as tightly as `min_precedence`
+    fn binary(&mut self, min_precedence: u8) -> Result<Node, String> {
+        let mut left = self.unary()?;
+        while let Some(Token::Symbol(symbol)) = self.peek() {
+            let Some(op) = BinaryOp::from_token(symbol).filter(|op| op.precedence() >= min_precedence) else { break };
+            self.position += 1;
+            let right = self.binary(op.precedence() + 1)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        match self.peek() {
+            Some(Token::Symbol("!")) => {
+                self.position += 1;
+                Ok(Node::Not(Box::new(self.unary()?)))
+            },
+            Some(Token::Symbol("-")) => {
+                self.position += 1;
+                Ok(Node::Negate(Box::new(self.unary()?)))
+            },
+            _ => self.primary()
+        }
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        match self.next() {
+            Some(Token::Number(number)) => Ok(Node::Number(number)),
+            Some(Token::Symbol("(")) => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                Ok(node)
+            },
+            Some(Token::Name(name)) if name == "mem" => {
+                self.expect("[")?;
+                let address = self.binary(0)?;
+                self.expect("]")?;
+                Ok(Node::Memory(Box::new(address)))
+            },
+            Some(Token::Name(name)) => variable(&name).map(Node::Variable),
+            Some(Token::Symbol(symbol)) => Err(format!("Unexpected `{}`", symbol)),
+            None => Err(String::from("Unexpected end of expression"))
+        }
+    }
+}
+
+fn variable(name: &str) -> Result<Variable, String> {
+    match name {
+        "i" => Ok(Variable::I),
+        "pc" => Ok(Variable::Pc),
+        "dt" => Ok(Variable::DelayTimer),
+        "st" => Ok(Variable::SoundTimer),
+        "sp" => Ok(Variable::StackLevel),
+        _ => name
+            .strip_prefix('v')
+            .filter(|register| register.len() == 1)
+            .and_then(|register| usize::from_str_radix(register, 16).ok())
+            .map(Variable::Register)
+            .ok_or_else(|| format!("`{}` isn't a register, try v0 to vf, i, pc, dt, st or sp", name))
+    }
+}
+
+impl Expression {
+    pub fn parse(source: &str) -> Result<Self, String> {
+        let mut parser = Parser { tokens: tokenize(source)?, position: 0 };
+        let node = parser.binary(0)?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("Unexpected {:?} after the expression", token));
+        }
+        Ok(Expression { source: source.trim().to_owned(), node })
+    }
+
+    pub fn evaluate(&self, chip: &Chip) -> i64 {
+        evaluate(&self.node, chip)
+    }
+
+    pub fn is_true(&self, chip: &Chip) -> bool {
+        self.evaluate(chip) != 0
+    }
+}
+
+impl fmt::Display for Expression {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+fn evaluate(node: &Node, chip: &Chip) -> i64 {
+    match node {
+        Node::Number(number) => *number,
+        Node::Variable(variable) => match variable {
+            Variable::Register(register) => chip.registers[*register] as i64,
+            Variable::I => chip.i as i64,
+            Variable::Pc => chip.pc as i64,
+            Variable::DelayTimer => chip.delay_timer.get() as i64,
+            Variable::SoundTimer => chip.sound_timer.get() as i64,
+            Variable::StackLevel => chip.stack_level as i64
+        },
+        // Addresses outside of memory read as 0
+        Node::Memory(address) => usize::try_from(evaluate(address, chip))
+            .ok()
+            .and_then(|address| chip.memory.get(address))
+            .map_or(0, |byte| *byte as i64),
+        Node::Not(operand) => (evaluate(operand, chip) == 0) as i64,
+        Node::Negate(operand) => evaluate(operand, chip).wrapping_neg(),
+        Node::Binary(op, left, right) => {
+            let left = evaluate(left, chip);
+            // && and || don't evaluate their right side if the left decides the result
+            match op {
+                BinaryOp::Or if left != 0 => return 1,
+                BinaryOp::And if left == 0 => return 0,
+                _ => ()
+            }
+            let right = evaluate(right, chip);
+            match op {
+                BinaryOp::Or | BinaryOp::And => (right != 0) as i64,
+                BinaryOp::BitOr => left | right,
+                BinaryOp::BitAnd => left & right,
+                BinaryOp::Equal => (left == right) as i64,
+                BinaryOp::NotEqual => (left != right) as i64,
+                BinaryOp::Less => (left < right) as i64,
+                BinaryOp::LessOrEqual => (left <= right) as i64,
+                BinaryOp::Greater => (left > right) as i64,
+                BinaryOp::GreaterOrEqual => (left >= right) as i64,
+                BinaryOp::Add => left.wrapping_add(right),
+                BinaryOp::Subtract => left.wrapping_sub(right)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip::ChipType;
+    use crate::display::DisplayType;
+
+    fn chip() -> Chip {
+        let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
+        chip.registers[3] = 0x10;
+        chip.i = 0x320;
+        chip.memory[0x320] = 0xAB;
+        chip
+    }
+
+    fn evaluate(source: &str) -> i64 {
+        Expression::parse(source).unwrap().evaluate(&chip())
+    }
+
+    #[test]
+    fn evaluates_registers_and_comparisons() {
+        assert_eq!(evaluate("v3 == 0x10 && i > 0x300"), 1);
+        assert_eq!(evaluate("V3 == 16 && i > 0x400"), 0);
+        assert_eq!(evaluate("mem[i] == 0xab"), 1);
+        assert_eq!(evaluate("mem[i + 1]"), 0);
+        assert_eq!(evaluate("!(v0 || v3 == 0)"), 1);
+    }
+
+    #[test]
+    fn operators_follow_c_precedence() {
+        assert_eq!(evaluate("1 + 2 == 3"), 1);
+        assert_eq!(evaluate("0 || 1 && 0"), 0);
+        assert_eq!(evaluate("v3 & 0x30 == 0x10"), 0); // Like C, == binds tighter than &
+        assert_eq!(evaluate("5 - 3 - 1"), 1);
+        assert_eq!(evaluate("-v3 + 0x20"), 0x10);
+    }
+
+    #[test]
+    fn rejects_bad_expressions() {
+        assert!(Expression::parse("vg == 1").is_err());
+        assert!(Expression::parse("v0 ==").is_err());
+        assert!(Expression::parse("(v0 == 1").is_err());
+        assert!(Expression::parse("v0 1").is_err());
+        assert!(Expression::parse("0xzz").is_err());
+        assert!(Expression::parse("v0 = 1").is_err());
+    }
+}
@@ -0,0 +1,119 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+use crate::display::DISPLAY_SIZE;
+
+// Brightness a pixel has to fall below before the phosphor filter treats it as fully off
+const PHOSPHOR_CUTOFF: f32 = 0.01;
+
+#[derive(ValueEnum, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum FilterType {
+    None,
+    Phosphor,
+    Or
+}
+
+// Smooths the flicker of ROMs erasing and redrawing sprites, in what's drawn but not what's emulated
+#[derive(Clone)]
+pub struct Filter {
+    filter_type: FilterType,
+    decay: f32,
+    brightness: [f32; DISPLAY_SIZE],
+    previous_frame: [bool; DISPLAY_SIZE]
+}
+
+impl Filter {
+    pub fn new(filter_type: FilterType, decay: f32) -> Self {
+        Self {
+            filter_type,
+            decay,
+            brightness: [0.; DISPLAY_SIZE],
+            previous_frame: [false; DISPLAY_SIZE]
+        }
+    }
+
+    // Takes the next frame and returns how bright each pixel should be drawn, from 0 (off) to 1 (on)
+    pub fn apply(&mut self, frame: &[bool; DISPLAY_SIZE]) -> &[f32; DISPLAY_SIZE] {
+        for (pixel, is_on) in frame.iter().enumerate() {
+            self.brightness[pixel] = match self.filter_type {
+                FilterType::None => if *is_on { 1. } else { 0. },
+                FilterType::Or => if *is_on || self.previous_frame[pixel] { 1. } else { 0. },
+                FilterType::Phosphor => {
+                    // Lit pixels are fully bright, unlit pixels fade out by `decay` every frame
+                    let faded = self.brightness[pixel] * self.decay;
+                    if *is_on { 1. } else if faded < PHOSPHOR_CUTOFF { 0. } else { faded }
+                }
+            };
+        }
+
+        self.previous_frame = *frame;
+        &self.brightness
+    }
+
+    // What the last frame applied came out as
+    pub fn brightness(&self) -> &[f32; DISPLAY_SIZE] {
+        &self.brightness
+    }
+}
+
+// Used by clap to make sure the decay is a fraction of the previous frame's brightness
+pub fn parse_decay(decay: &str) -> Result<f32, String> {
+    let decay: f32 = decay.parse().map_err(|_| format!("`{}` isn't a number", decay))?;
+    check_decay(decay)
+}
+
+pub fn check_decay(decay: f32) -> Result<f32, String> {
+    if (0. ..1.).contains(&decay) {
+        Ok(decay)
+    } else {
+        Err(String::from("decay must be at least 0 and less than 1"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn no_filter_passes_frame_through() {
+        let mut filter = Filter::new(FilterType::None, 0.5);
+        let mut frame = [false; DISPLAY_SIZE];
+        frame[10] = true;
+
+        filter.apply(&frame);
+        let brightness = filter.apply(&[false; DISPLAY_SIZE]);
+
+        assert_eq!(brightness[10], 0.);
+    }
+
+    #[test]
+    fn or_filter_keeps_pixels_lit_in_either_of_the_last_two_frames() {
+        let mut filter = Filter::new(FilterType::Or, 0.5);
+        let mut frame = [false; DISPLAY_SIZE];
+        frame[10] = true;
+
+        filter.apply(&frame);
+        assert_eq!(filter.apply(&[false; DISPLAY_SIZE])[10], 1.);
+        assert_eq!(filter.apply(&[false; DISPLAY_SIZE])[10], 0.);
+    }
+
+    #[test]
+    fn phosphor_filter_fades_out_unlit_pixels() {
+        let mut filter = Filter::new(FilterType::Phosphor, 0.5);
+        let mut frame = [false; DISPLAY_SIZE];
+        frame[10] = true;
+
+        assert_eq!(filter.apply(&frame)[10], 1.);
+        assert_eq!(filter.apply(&[false; DISPLAY_SIZE])[10], 0.5);
+        assert_eq!(filter.apply(&[false; DISPLAY_SIZE])[10], 0.25);
+        assert_eq!(filter.apply(&frame)[10], 1.);
+    }
+
+    #[test]
+    fn decay_must_be_a_fraction() {
+        assert_eq!(parse_decay("0.75"), Ok(0.75));
+        assert!(parse_decay("1").is_err());
+        assert!(parse_decay("-0.1").is_err());
+        assert!(parse_decay("bright").is_err());
+    }
+}
@@ -2,7 +2,7 @@
 pub const FONT_SIZE: usize = 5;
 
 pub fn get_font() -> [u8; FONT_SIZE * 16] {
-    let font = [
+    [
         0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
         0x20, 0x60, 0x20, 0x20, 0x70, // 1
         0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
@@ -19,7 +19,5 @@ pub fn get_font() -> [u8; FONT_SIZE * 16] {
         0xE0, 0x90, 0x90, 0x90, 0xE0, // D
         0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
         0xF0, 0x80, 0xF0, 0x80, 0x80  // F
-    ];
-
-    font
+    ]
 }
@@ -0,0 +1,498 @@
+use std::io::{self, ErrorKind, Read, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::thread;
+use std::time::Duration;
+use crate::chip::Chip;
+use crate::watch::{AccessKind, StopReason, Watchpoint};
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const INTERRUPT: u8 = 0x03; // Sent outside of a packet when the debugger wants to stop the chip
+const REGISTERS: usize = 19; // V0 to VF, I, PC and SP
+const MAX_PACKET_SIZE: usize = 0x4000;
+
+// The registers GDB sees, in order. I and PC are 16 bits and big-endian, like CHIP-8 memory
+const TARGET_XML: &str = concat!(
+    r#"<?xml version="1.0"?><!DOCTYPE target SYSTEM "gdb-target.dtd"><target version="1.0">"#,
+    r#"<feature name="org.rustychip8.core">"#,
+    r#"<reg name="v0" bitsize="8" type="uint8"/><reg name="v1" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="v2" bitsize="8" type="uint8"/><reg name="v3" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="v4" bitsize="8" type="uint8"/><reg name="v5" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="v6" bitsize="8" type="uint8"/><reg name="v7" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="v8" bitsize="8" type="uint8"/><reg name="v9" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="va" bitsize="8" type="uint8"/><reg name="vb" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="vc" bitsize="8" type="uint8"/><reg name="vd" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="ve" bitsize="8" type="uint8"/><reg name="vf" bitsize="8" type="uint8"/>"#,
+    r#"<reg name="i" bitsize="16" type="data_ptr"/><reg name="pc" bitsize="16" type="code_ptr"/>"#,
+    r#"<reg name="sp" bitsize="8" type="uint8"/>"#,
+    r#"</feature></target>"#
+);
+
+// A GDB remote serial protocol stub on a local TCP port. It's polled once a frame, so the
+// emulator never waits on the debugger
+pub struct GdbStub {
+    listener: TcpListener,
+    client: Option<Client>
+}
+
+struct Client {
+    stream: TcpStream,
+    received: Vec<u8>,
+    acks: bool, // Until the debugger turns them off with QStartNoAckMode
+    running: bool // Continued, so the debugger is waiting to hear when the chip stops
+}
+
+enum Incoming {
+    Packet(String),
+    BadChecksum,
+    Interrupt
+}
+
+enum Response {
+    Reply(String),
+    Continue,
+    Detach,
+    Kill
+}
+
+impl GdbStub {
+    // Only listens on localhost, since the protocol has no authentication
+    pub fn listen(port: u16) -> io::Result<Self> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        listener.set_nonblocking(true)?;
+        Ok(GdbStub { listener, client: None })
+    }
+
+    pub fn address(&self) -> io::Result<SocketAddr> {
+        self.listener.local_addr()
+    }
+
+    // Accepts a debugger and handles everything it sent since the last frame. Returns whether
+    // the chip should be running when the debugger changes it: attaching and interrupting
+    // stop it, and continuing, detaching and disconnecting let it run
+    pub fn poll(&mut self, chip: &mut Chip) -> Option<bool> {
+        let Some(client) = &mut self.client else {
+            let (stream, _) = self.listener.accept().ok()?;
+            stream.set_nonblocking(true).ok()?;
+            self.client = Some(Client { stream, received: vec![], acks: true, running: false });
+            return Some(false);
+        };
+
+        if client.receive().is_err() {
+            self.client = None;
+            return Some(true);
+        }
+
+        let mut running = None;
+        while let Some(incoming) = client.next_incoming() {
+            let result = match incoming {
+                Incoming::BadChecksum => client.send_raw(b"-"),
+                Incoming::Interrupt => {
+                    running = Some(false);
+                    client.report_stop(chip, SIGINT)
+                },
+                Incoming::Packet(packet) => {
+                    let ack = if client.acks { client.send_raw(b"+") } else { Ok(()) };
+                    ack.and_then(|_| match respond(&packet, chip) {
+                        Response::Reply(reply) => {
+                            if packet == "QStartNoAckMode" {
+                                client.acks = false;
+                            }
+                            client.send(&reply)
+                        },
+                        Response::Continue => {
+                            client.running = true;
+                            running = Some(true);
+                            Ok(())
+                        },
+                        Response::Detach => {
+                            let _ = client.send("OK");
+                            Err(io::Error::from(ErrorKind::ConnectionAborted))
+                        },
+                        Response::Kill => chip.exit(0)
+                    })
+                }
+            };
+            if result.is_err() {
+                self.client = None;
+                return Some(true);
+            }
+        }
+        running
+    }
+
+    // Tells the debugger why the chip stopped, if it's waiting to hear
+    pub fn report_stop(&mut self, chip: &Chip) {
+        if let Some(client) = &mut self.client {
+            if client.report_stop(chip, SIGINT).is_err() {
+                self.client = None;
+            }
+        }
+    }
+}
+
+impl Client {
+    fn receive(&mut self) -> io::Result<()> {
+        let mut buffer = [0; 4096];
+        loop {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Err(io::Error::from(ErrorKind::UnexpectedEof)),
+                Ok(length) => self.received.extend_from_slice(&buffer[..length]),
+                Err(error) if error.kind() == ErrorKind::WouldBlock => return Ok(()),
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(error)
+            }
+        }
+    }
+
+    // Takes the next packet or interrupt off what's been received, skipping acks and noise
+    fn next_incoming(&mut self) -> Option<Incoming> {
+        loop {
+            match *self.received.first()? {
+                INTERRUPT => {
+                    self.received.remove(0);
+                    return Some(Incoming::Interrupt);
+                },
+                b'$' => {
+                    let end = self.received.iter().position(|byte| *byte == b'#')?;
+                    if self.received.len() < end + 3 {
+                        return None;
+                    }
+                    let packet: Vec<u8> = self.received.drain(..end + 3).collect();
+                    let data = &packet[1..end];
+                    let checksum = std::str::from_utf8(&packet[end + 1..])
+                        .ok()
+                        .and_then(|checksum| u8::from_str_radix(checksum, 16).ok());
+                    return Some(if checksum == Some(checksum_of(data)) {
+                        Incoming::Packet(String::from_utf8_lossy(data).into_owned())
+                    } else {
+                        Incoming::BadChecksum
+                    });
+                },
+                _ => {
+                    self.received.remove(0);
+                }
+            }
+        }
+    }
+
+    fn report_stop(&mut self, chip: &Chip, signal: u8) -> io::Result<()> {
+        if !self.running {
+            return Ok(());
+        }
+        self.running = false;
+        self.send(&stop_reply(chip, signal))
+    }
+
+    fn send(&mut self, data: &str) -> io::Result<()> {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        self.send_raw(packet.as_bytes())
+    }
+
+    // The stream doesn't block, so big replies can take a few tries
+    fn send_raw(&mut self, mut bytes: &[u8]) -> io::Result<()> {
+        while !bytes.is_empty() {
+            match self.stream.write(bytes) {
+                Ok(0) => return Err(io::Error::from(ErrorKind::WriteZero)),
+                Ok(length) => bytes = &bytes[length..],
+                Err(error) if error.kind() == ErrorKind::WouldBlock => thread::sleep(Duration::from_millis(1)),
+                Err(error) if error.kind() == ErrorKind::Interrupted => (),
+                Err(error) => return Err(error)
+            }
+        }
+        Ok(())
+    }
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum, byte| sum.wrapping_add(*byte))
+}
+
+fn stop_reply(chip: &Chip, signal: u8) -> String {
+    match &chip.stop_reason {
+        Some(StopReason::Watchpoint { address, kind }) => {
+            let watch = if *kind == AccessKind::Write { "watch" } else { "rwatch" };
+            format!("T{:02x}{}:{:x};", SIGTRAP, watch, address)
+        },
+        Some(_) => format!("S{:02x}", SIGTRAP),
+        None => format!("S{:02x}", signal)
+    }
+}
+
+fn respond(packet: &str, chip: &mut Chip) -> Response {
+    let reply = |result: Result<String, ()>| Response::Reply(result.unwrap_or_else(|_| String::from("E01")));
+    let (command, arguments) = packet.split_at(packet.chars().next().map_or(0, char::len_utf8));
+
+    match command {
+        "?" => Response::Reply(stop_reply(chip, SIGTRAP)),
+        "g" => Response::Reply((0..REGISTERS).map(|register| read_register(chip, register)).collect()),
+        "G" => reply(write_registers(chip, arguments)),
+        "p" => reply(parse_register(arguments).map(|register| read_register(chip, register))),
+        "P" => reply(arguments
+            .split_once('=')
+            .ok_or(())
+            .and_then(|(register, value)| write_register(chip, parse_register(register)?, value))),
+        "m" => reply(read_memory(chip, arguments)),
+        "M" => reply(write_memory(chip, arguments)),
+        "Z" => reply(set_breakpoint(chip, arguments, true)),
+        "z" => reply(set_breakpoint(chip, arguments, false)),
+        "c" => Response::Continue,
+        "s" => {
+            chip.step();
+            Response::Reply(stop_reply(chip, SIGTRAP))
+        },
+        "D" => Response::Detach,
+        "k" => Response::Kill,
+        "H" | "T" => Response::Reply(String::from("OK")),
+        _ => Response::Reply(query(packet))
+    }
+}
+
+// Queries GDB makes while connecting. Anything else gets an empty reply, meaning it isn't supported
+fn query(packet: &str) -> String {
+    if packet.starts_with("qSupported") {
+        return format!("PacketSize={:x};qXfer:features:read+;QStartNoAckMode+", MAX_PACKET_SIZE);
+    }
+    if let Some(range) = packet.strip_prefix("qXfer:features:read:target.xml:") {
+        let Some((offset, length)) = range.split_once(',') else { return String::from("E01") };
+        let (Ok(offset), Ok(length)) = (parse_hex(offset), parse_hex(length)) else { return String::from("E01") };
+        let start = offset.min(TARGET_XML.len());
+        let end = start.saturating_add(length).min(TARGET_XML.len());
+        let more = if end < TARGET_XML.len() { "m" } else { "l" };
+        return format!("{}{}", more, &TARGET_XML[start..end]);
+    }
+
+    match packet {
+        "QStartNoAckMode" => String::from("OK"),
+        "qAttached" => String::from("1"),
+        "qC" => String::from("QC1"),
+        "qfThreadInfo" => String::from("m1"),
+        "qsThreadInfo" => String::from("l"),
+        _ => String::new()
+    }
+}
+
+fn parse_hex(text: &str) -> Result<usize, ()> {
+    usize::from_str_radix(text, 16).map_err(|_| ())
+}
+
+fn parse_bytes(hex: &str) -> Result<Vec<u8>, ()> {
+    if !hex.len().is_multiple_of(2) || !hex.is_ascii() {
+        return Err(());
+    }
+    (0..hex.len()).step_by(2).map(|start| u8::from_str_radix(&hex[start..start + 2], 16).map_err(|_| ())).collect()
+}
+
+fn parse_register(text: &str) -> Result<usize, ()> {
+    parse_hex(text).and_then(|register| if register < REGISTERS { Ok(register) } else { Err(()) })
+}
+
+fn register_size(register: usize) -> usize {
+    if register == 16 || register == 17 { 2 } else { 1 }
+}
+
+fn read_register(chip: &Chip, register: usize) -> String {
+    match register {
+        0..=15 => format!("{:02x}", chip.registers[register]),
+        16 => format!("{:04x}", chip.i),
+        17 => format!("{:04x}", chip.pc),
+        _ => format!("{:02x}", chip.stack_level)
+    }
+}
+
+fn write_register(chip: &mut Chip, register: usize, hex: &str) -> Result<String, ()> {
+    if hex.len() != register_size(register) * 2 {
+        return Err(());
+    }
+    let value = parse_hex(hex)?;
+    match register {
+        0..=15 => chip.registers[register] = value as u8,
+        16 => chip.i = value,
+        17 if value < chip.memory.len() => chip.pc = value,
+        18 if value <= chip.stack.len() => chip.stack_level = value,
+        _ => return Err(())
+    }
+    Ok(String::from("OK"))
+}
+
+fn write_registers(chip: &mut Chip, hex: &str) -> Result<String, ()> {
+    let mut start = 0;
+    for register in 0..REGISTERS {
+        let end = start + register_size(register) * 2;
+        write_register(chip, register, hex.get(start..end).ok_or(())?)?;
+        start = end;
+    }
+    Ok(String::from("OK"))
+}
+
+// `addr,length` with both in hex
+fn parse_range(chip: &Chip, range: &str) -> Result<(usize, usize), ()> {
+    let (address, length) = range.split_once(',').ok_or(())?;
+    let (address, length) = (parse_hex(address)?, parse_hex(length)?);
+    if address >= chip.memory.len() {
+        return Err(());
+    }
+    Ok((address, length))
+}
+
+// Reads stop at the end of memory
+fn read_memory(chip: &Chip, range: &str) -> Result<String, ()> {
+    let (address, length) = parse_range(chip, range)?;
+    let end = address.saturating_add(length).min(chip.memory.len());
+    Ok(chip.memory[address..end].iter().map(|byte| format!("{:02x}", byte)).collect())
+}
+
+fn write_memory(chip: &mut Chip, arguments: &str) -> Result<String, ()> {
+    let (range, data) = arguments.split_once(':').ok_or(())?;
+    let (address, length) = parse_range(chip, range)?;
+    let bytes = parse_bytes(data)?;
+    if bytes.len() != length || address + length > chip.memory.len() {
+        return Err(());
+    }
+    chip.write_memory(address, &bytes);
+    Ok(String::from("OK"))
+}
+
+// `type,addr,kind`. Types 0 and 1 are breakpoints, and 2 to 4 watch writes, reads or both,
+// with kind being how many bytes to watch
+fn set_breakpoint(chip: &mut Chip, arguments: &str, insert: bool) -> Result<String, ()> {
+    let mut parts = arguments.split(',');
+    let (Some(breakpoint_type), Some(address), Some(kind)) = (parts.next(), parts.next(), parts.next()) else { return Err(()) };
+    let (address, kind) = (parse_hex(address)?, parse_hex(kind)?);
+    if address >= chip.memory.len() {
+        return Err(());
+    }
+
+    let (read, write) = match breakpoint_type {
+        "0" | "1" => {
+            if insert {
+                chip.breakpoints.insert(address, None);
+            } else {
+                chip.breakpoints.remove(&address);
+            }
+            return Ok(String::from("OK"));
+        },
+        "2" => (false, true),
+        "3" => (true, false),
+        "4" => (true, true),
+        _ => return Ok(String::new())
+    };
+    // The kind comes straight from the packet, so it can be anything
+    let end = address.checked_add(kind.max(1) - 1).ok_or(())?.min(chip.memory.len() - 1);
+    let watchpoint = Watchpoint { start: address, end, read, write };
+    if insert {
+        chip.watchpoints.push(watchpoint);
+    } else if let Some(index) = chip.watchpoints.iter().position(|watched| *watched == watchpoint) {
+        chip.watchpoints.remove(index);
+    }
+    Ok(String::from("OK"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chip::ChipType;
+    use crate::display::DisplayType;
+
+    fn chip() -> Chip {
+        let mut chip = Chip::new(1200, ChipType::CHIP8, DisplayType::Terminal);
+        chip.load_rom(&[0x60, 0x2A, 0xA3, 0x00, 0x12, 0x04]).unwrap();
+        chip
+    }
+
+    fn reply(packet: &str, chip: &mut Chip) -> String {
+        match respond(packet, chip) {
+            Response::Reply(reply) => reply,
+            _ => panic!("{} didn't get a reply", packet)
+        }
+    }
+
+    #[test]
+    fn reads_and_writes_registers_and_memory() {
+        let mut chip = chip();
+        assert_eq!(reply("s", &mut chip), "S05");
+        assert_eq!(reply("g", &mut chip), format!("2a{}00000202{}", "00".repeat(15), "00"));
+        assert_eq!(reply("p11", &mut chip), "0202");
+
+        assert_eq!(reply("P10=0300", &mut chip), "OK");
+        assert_eq!(chip.i, 0x300);
+        assert_eq!(reply("P11=1000", &mut chip), "E01");
+
+        assert_eq!(reply("m200,4", &mut chip), "602aa300");
+        assert_eq!(reply("mffe,10", &mut chip), "0000");
+        assert_eq!(reply("M300,2:beef", &mut chip), "OK");
+        assert_eq!(chip.memory[0x300..0x302], [0xBE, 0xEF]);
+        assert_eq!(reply("M300,2:be", &mut chip), "E01");
+    }
+
+    #[test]
+    fn breakpoints_and_watchpoints_are_set_on_the_chip() {
+        let mut chip = chip();
+        assert_eq!(reply("Z0,204,2", &mut chip), "OK");
+        assert!(chip.breakpoints.contains_key(&0x204));
+        assert_eq!(reply("z0,204,2", &mut chip), "OK");
+        assert!(chip.breakpoints.is_empty());
+
+        assert_eq!(reply("Z2,300,4", &mut chip), "OK");
+        assert_eq!(chip.watchpoints, vec![Watchpoint { start: 0x300, end: 0x303, read: false, write: true }]);
+        assert_eq!(reply("z2,300,4", &mut chip), "OK");
+        assert!(chip.watchpoints.is_empty());
+
+        assert_eq!(reply("Z2,300,ffffffffffffffff", &mut chip), "E01");
+        assert_eq!(reply("Z3,300,1000", &mut chip), "OK");
+        assert_eq!(chip.watchpoints, vec![Watchpoint { start: 0x300, end: 0xFFF, read: true, write: false }]);
+    }
+
+    #[test]
+    fn serves_target_description_in_chunks() {
+        let first = query("qXfer:features:read:target.xml:0,10");
+        assert_eq!(first, format!("m{}", &TARGET_XML[..0x10]));
+        let rest = query(&format!("qXfer:features:read:target.xml:10,{:x}", TARGET_XML.len()));
+        assert_eq!(rest, format!("l{}", &TARGET_XML[0x10..]));
+    }
+
+    fn packet(data: &str) -> String {
+        format!("${}#{:02x}", data, checksum_of(data.as_bytes()))
+    }
+
+    fn read(debugger: &mut TcpStream, expected: &str) {
+        let mut buffer = vec![0; expected.len()];
+        debugger.read_exact(&mut buffer).unwrap();
+        assert_eq!(String::from_utf8(buffer).unwrap(), expected);
+    }
+
+    #[test]
+    fn talks_to_a_debugger_over_tcp() {
+        let mut chip = chip();
+        chip.breakpoints.insert(0x204, None);
+        let mut stub = GdbStub::listen(0).unwrap();
+        let mut debugger = TcpStream::connect(stub.address().unwrap()).unwrap();
+        debugger.set_read_timeout(Some(Duration::from_secs(5))).unwrap();
+
+        // Attaching stops the chip
+        let mut attached = None;
+        while attached.is_none() {
+            attached = stub.poll(&mut chip);
+        }
+        assert_eq!(attached, Some(false));
+
+        debugger.write_all(packet("m200,2").as_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        stub.poll(&mut chip);
+        read(&mut debugger, &format!("+{}", packet("602a")));
+
+        debugger.write_all(format!("+{}", packet("c")).as_bytes()).unwrap();
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(stub.poll(&mut chip), Some(true));
+        read(&mut debugger, "+");
+
+        chip.run_frame();
+        stub.report_stop(&chip);
+        read(&mut debugger, &packet("S05"));
+
+        drop(debugger);
+        thread::sleep(Duration::from_millis(50));
+        assert_eq!(stub.poll(&mut chip), Some(true));
+        assert!(stub.client.is_none());
+    }
+}
@@ -0,0 +1,205 @@
+use crate::instruction::Instruction;
+use crate::watch::MemoryAccess;
+
+// A sprite DXYN drew, at the coordinates it was given before they wrap onto the screen
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct SpriteDraw {
+    pub x: u8,
+    pub y: u8,
+    pub height: u8,
+    pub collision: bool // Whether any pixel was turned off, which is what VF gets set to
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum TimerKind {
+    Delay,
+    Sound
+}
+
+type Callbacks<T> = Vec<Box<dyn FnMut(T) + Send>>;
+
+// Callbacks the chip makes as it runs, for tracing, coverage, profiling and the like. Addresses
+// are where the instruction doing something starts. Each kind of hook is only looked at when
+// some are registered, so a chip without hooks runs as fast as before
+#[derive(Default)]
+pub struct Hooks {
+    before_instruction: Callbacks<(usize, Instruction)>,
+    after_instruction: Callbacks<(usize, Instruction)>,
+    memory_access: Callbacks<MemoryAccess>,
+    call: Callbacks<(usize, usize)>,
+    return_: Callbacks<(usize, usize)>,
+    draw: Callbacks<SpriteDraw>,
+    timer_set: Callbacks<(TimerKind, u8)>,
+    frame_end: Callbacks<()>
+}
+
+fn fire<T: Copy>(callbacks: &mut Callbacks<T>, event: T) {
+    for callback in callbacks {
+        callback(event);
+    }
+}
+
+// Callbacks can't be copied, so a copy of a chip starts without any
+impl Clone for Hooks {
+    fn clone(&self) -> Self {
+        Hooks::default()
+    }
+}
+
+impl Hooks {
+    // Called with the instruction's address and what it decoded to
+    pub fn on_before_instruction(&mut self, hook: impl FnMut((usize, Instruction)) + Send + 'static) {
+        self.before_instruction.push(Box::new(hook));
+    }
+
+    pub fn on_after_instruction(&mut self, hook: impl FnMut((usize, Instruction)) + Send + 'static) {
+        self.after_instruction.push(Box::new(hook));
+    }
+
+    // Reads and writes through I, by DXYN, FX33, FX55 and FX65
+    pub fn on_memory_access(&mut self, hook: impl FnMut(MemoryAccess) + Send + 'static) {
+        self.memory_access.push(Box::new(hook));
+    }
+
+    // Called with the address of the 2NNN and the address it calls
+    pub fn on_call(&mut self, hook: impl FnMut((usize, usize)) + Send + 'static) {
+        self.call.push(Box::new(hook));
+    }
+
+    // Called with the address of the 00EE and the address it returns to
+    pub fn on_return(&mut self, hook: impl FnMut((usize, usize)) + Send + 'static) {
+        self.return_.push(Box::new(hook));
+    }
+
+    pub fn on_draw(&mut self, hook: impl FnMut(SpriteDraw) + Send + 'static) {
+        self.draw.push(Box::new(hook));
+    }
+
+    // FX15 and FX18, with the value they set
+    pub fn on_timer_set(&mut self, hook: impl FnMut((TimerKind, u8)) + Send + 'static) {
+        self.timer_set.push(Box::new(hook));
+    }
+
+    // After a frame's instructions have run and its timers have ticked. Frames a breakpoint or
+    // watchpoint stops early don't count, since they carry on later
+    pub fn on_frame_end(&mut self, mut hook: impl FnMut() + Send + 'static) {
+        self.frame_end.push(Box::new(move |()| hook()));
+    }
+
+    pub fn clear(&mut self) {
+        *self = Hooks::default();
+    }
+
+    #[inline]
+    pub(crate) fn before_instruction(&mut self, address: usize, instruction: Instruction) {
+        fire(&mut self.before_instruction, (address, instruction));
+    }
+
+    #[inline]
+    pub(crate) fn after_instruction(&mut self, address: usize, instruction: Instruction) {
+        fire(&mut self.after_instruction, (address, instruction));
+    }
+
+    #[inline]
+    pub(crate) fn memory_access(&mut self, access: MemoryAccess) {
+        fire(&mut self.memory_access, access);
+    }
+
+    #[inline]
+    pub(crate) fn call(&mut self, address: usize, target: usize) {
+        fire(&mut self.call, (address, target));
+    }
+
+    #[inline]
+    pub(crate) fn return_(&mut self, address: usize, target: usize) {
+        fire(&mut self.return_, (address, target));
+    }
+
+    #[inline]
+    pub(crate) fn draw(&mut self, draw: SpriteDraw) {
+        fire(&mut self.draw, draw);
+    }
+
+    #[inline]
+    pub(crate) fn timer_set(&mut self, timer: TimerKind, value: u8) {
+        fire(&mut self.timer_set, (timer, value));
+    }
+
+    #[inline]
+    pub(crate) fn frame_end(&mut self) {
+        fire(&mut self.frame_end, ());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use crate::chip::{Chip, ChipType};
+    use crate::watch::AccessKind;
+    use super::*;
+
+    #[derive(PartialEq, Debug)]
+    enum Event {
+        Before(usize, Instruction),
+        After(usize, Instruction),
+        Access(MemoryAccess),
+        Call(usize, usize),
+        Return(usize, usize),
+        Draw(SpriteDraw),
+        TimerSet(TimerKind, u8),
+        FrameEnd
+    }
+
+    fn log<T: 'static>(events: &Arc<Mutex<Vec<Event>>>, event: fn(T) -> Event) -> impl FnMut(T) + Send + 'static {
+        let events = events.clone();
+        move |value| events.lock().unwrap().push(event(value))
+    }
+
+    #[test]
+    fn hooks_see_what_the_chip_does() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        // Calls a subroutine that draws the 0 glyph twice, sets the sound timer and returns, then loops
+        chip.load_rom(&[0x22, 0x04, 0x12, 0x02, 0x61, 0x03, 0xA0, 0x50, 0xD0, 0x05, 0xD0, 0x05, 0xF1, 0x18, 0x00, 0xEE]).unwrap();
+        let events = Arc::new(Mutex::new(vec![]));
+        chip.hooks.on_memory_access(log(&events, Event::Access));
+        chip.hooks.on_call(log(&events, |(address, target)| Event::Call(address, target)));
+        chip.hooks.on_return(log(&events, |(address, target)| Event::Return(address, target)));
+        chip.hooks.on_draw(log(&events, Event::Draw));
+        chip.hooks.on_timer_set(log(&events, |(timer, value)| Event::TimerSet(timer, value)));
+        let mut frame_end = log(&events, |()| Event::FrameEnd);
+        chip.hooks.on_frame_end(move || frame_end(()));
+
+        chip.run_frame();
+
+        let font_read = || Event::Access(MemoryAccess { start: 0x50, length: 5, kind: AccessKind::Read });
+        let draw = |collision| Event::Draw(SpriteDraw { x: 0, y: 0, height: 5, collision });
+        assert_eq!(*events.lock().unwrap(), [
+            Event::Call(0x200, 0x204),
+            font_read(),
+            draw(false),
+            font_read(),
+            draw(true),
+            Event::TimerSet(TimerKind::Sound, 3),
+            Event::Return(0x20E, 0x202),
+            Event::FrameEnd
+        ]);
+    }
+
+    #[test]
+    fn instruction_hooks_come_either_side_of_it() {
+        let mut chip = Chip::headless(600, ChipType::CHIP8);
+        chip.load_rom(&[0x22, 0x02, 0x00, 0xEE]).unwrap();
+        let events = Arc::new(Mutex::new(vec![]));
+        chip.hooks.on_before_instruction(log(&events, |(address, instruction)| Event::Before(address, instruction)));
+        chip.hooks.on_call(log(&events, |(address, target)| Event::Call(address, target)));
+        chip.hooks.on_after_instruction(log(&events, |(address, instruction)| Event::After(address, instruction)));
+
+        chip.step();
+
+        assert_eq!(*events.lock().unwrap(), [
+            Event::Before(0x200, Instruction::Call(0x202)),
+            Event::Call(0x200, 0x202),
+            Event::After(0x200, Instruction::Call(0x202))
+        ]);
+    }
+}
@@ -0,0 +1,84 @@
+// Emulator controls, bound to host keys the keypad presets leave free
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Hotkey {
+    Overlay,  // Show or hide the status overlay
+    Debugger, // Show or hide the debugger panels
+    Pause,    // Pause or resume
+    Advance,  // Run one frame while paused
+    Step,     // Run one instruction while paused
+    Slower,   // Fewer instructions per frame
+    Faster,   // More instructions per frame
+    Reset,    // Reload the ROM and start it again
+    Turbo,    // Fast forward while held
+    Quit
+}
+
+pub const HOTKEYS: [(Hotkey, &str); 10] = [
+    (Hotkey::Overlay, "f1"),
+    (Hotkey::Debugger, "f2"),
+    (Hotkey::Pause, "f5"),
+    (Hotkey::Advance, "f6"),
+    (Hotkey::Slower, "f7"),
+    (Hotkey::Faster, "f8"),
+    (Hotkey::Reset, "f9"),
+    (Hotkey::Step, "f10"),
+    (Hotkey::Turbo, "tab"),
+    (Hotkey::Quit, "escape")
+];
+
+#[derive(Default)]
+pub struct Hotkeys {
+    down: Vec<Hotkey>
+}
+
+impl Hotkeys {
+    // Returns the hotkeys that went down since the last update, so holding one only triggers it once
+    pub fn update(&mut self, host_keys: &[String]) -> Vec<Hotkey> {
+        let down_now: Vec<Hotkey> = HOTKEYS
+            .iter()
+            .filter(|(_, host_key)| host_keys.iter().any(|key| key == host_key))
+            .map(|(hotkey, _)| *hotkey)
+            .collect();
+        let pressed = down_now.iter().filter(|hotkey| !self.down.contains(hotkey)).copied().collect();
+
+        self.down = down_now;
+        pressed
+    }
+
+    pub fn held(&self, hotkey: Hotkey) -> bool {
+        self.down.contains(&hotkey)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn keys(host_keys: &[&str]) -> Vec<String> {
+        host_keys.iter().map(|key| key.to_string()).collect()
+    }
+
+    #[test]
+    fn hotkey_is_pressed_once_while_held() {
+        let mut hotkeys = Hotkeys::default();
+
+        assert_eq!(hotkeys.update(&keys(&["f5", "w"])), vec![Hotkey::Pause]);
+        assert_eq!(hotkeys.update(&keys(&["f5"])), vec![]);
+        assert!(hotkeys.held(Hotkey::Pause));
+
+        assert_eq!(hotkeys.update(&keys(&[])), vec![]);
+        assert_eq!(hotkeys.update(&keys(&["f5"])), vec![Hotkey::Pause]);
+    }
+
+    #[test]
+    fn hotkeys_dont_clash_with_keypad_presets() {
+        use crate::keymap::{Keymap, KeymapPreset};
+
+        for preset in [KeymapPreset::Qwerty, KeymapPreset::Azerty, KeymapPreset::Dvorak, KeymapPreset::Colemak] {
+            let keymap = Keymap::from_preset(preset);
+            for (_, host_key) in HOTKEYS {
+                assert_eq!(keymap.chip_key(host_key), None);
+            }
+        }
+    }
+}
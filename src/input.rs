@@ -0,0 +1,570 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::cell::OnceCell;
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+#[cfg(not(target_arch = "wasm32"))]
+use crossterm::event::{self, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, KeyboardEnhancementFlags};
+#[cfg(not(target_arch = "wasm32"))]
+use crossterm::{execute, terminal};
+#[cfg(not(target_arch = "wasm32"))]
+use device_query::{DeviceQuery, DeviceState, Keycode};
+use macroquad::input::utils as input_utils;
+use macroquad::miniquad::EventHandler;
+use crate::display::DisplayType;
+use crate::keymap::Keymap;
+
+pub const DEFAULT_KEY_HOLD_MS: u64 = 200;
+
+// Where key presses come from. The terminal display reads them from stdin so it works without
+// a window system, otherwise the keyboard is read directly with device_query. Browsers only give
+// keys to the page, so they're read through macroquad there. A detached input only has the keys
+// pressed with press_key, for when something else owns the keyboard, like a libretro frontend.
+// device_query sees keys pressed in other windows too, so they're ignored while the window
+// doesn't have focus.
+enum Source {
+    #[cfg(not(target_arch = "wasm32"))]
+    DeviceQuery(WindowFocus),
+    #[cfg(not(target_arch = "wasm32"))]
+    Terminal(Box<TerminalInput>),
+    #[cfg(target_arch = "wasm32")]
+    Macroquad(WindowFocus),
+    Detached
+}
+
+// Whether the macroquad window has focus, from the events miniquad sends when it changes. It
+// starts listening on first use, since there's no window until macroquad starts
+struct WindowFocus {
+    subscriber: Option<usize>,
+    focused: bool
+}
+
+impl WindowFocus {
+    fn new() -> Self {
+        WindowFocus { subscriber: None, focused: true }
+    }
+
+    fn poll(&mut self) {
+        let subscriber = *self.subscriber.get_or_insert_with(input_utils::register_input_subscriber);
+        input_utils::repeat_all_miniquad_input(self, subscriber);
+    }
+}
+
+// miniquad reports focus changes as the window being minimized and restored
+impl EventHandler for WindowFocus {
+    fn update(&mut self) {}
+
+    fn draw(&mut self) {}
+
+    fn window_minimized_event(&mut self) {
+        self.focused = false;
+    }
+
+    fn window_restored_event(&mut self) {
+        self.focused = true;
+    }
+}
+
+// The keyboard device_query reads, kept per thread since its X11 connection can't move between
+// them. That leaves an Input free to, along with the Chip it's in
+#[cfg(not(target_arch = "wasm32"))]
+thread_local! {
+    static DEVICE_STATE: OnceCell<DeviceState> = const { OnceCell::new() };
+}
+
+pub struct Input {
+    source: Source,
+    keymap: Keymap,
+    virtual_keys: Vec<u8> // Held down by something other than the keyboard, like a script
+}
+
+// A copy is detached, since only one Input can own the keyboard or terminal. It keeps the keymap
+// and the keys held with press_key
+impl Clone for Input {
+    fn clone(&self) -> Self {
+        Self { source: Source::Detached, keymap: self.keymap.clone(), virtual_keys: self.virtual_keys.clone() }
+    }
+}
+
+impl Input {
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn new(display_type: &DisplayType) -> Self {
+        let source = match display_type {
+            // Both are set up on first use, so a Chip can be created without touching the keyboard
+            DisplayType::Macroquad => Source::DeviceQuery(WindowFocus::new()),
+            DisplayType::Terminal => {
+                let key_hold = Duration::from_millis(DEFAULT_KEY_HOLD_MS);
+                Source::Terminal(Box::new(TerminalInput::new(key_hold)))
+            }
+        };
+
+        Self { source, keymap: Keymap::default(), virtual_keys: vec![] }
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn new(_display_type: &DisplayType) -> Self {
+        Self { source: Source::Macroquad(WindowFocus::new()), keymap: Keymap::default(), virtual_keys: vec![] }
+    }
+
+    pub fn detached() -> Self {
+        Self { source: Source::Detached, keymap: Keymap::default(), virtual_keys: vec![] }
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn set_key_hold(&mut self, key_hold: Duration) {
+        if let Source::Terminal(terminal_input) = &mut self.source {
+            terminal_input.key_hold = key_hold;
+        }
+    }
+
+    pub fn set_keymap(&mut self, keymap: Keymap) {
+        self.keymap = keymap;
+    }
+
+    // Names of the host keys that are currently down, like "w" or "f5"
+    pub fn host_keys(&mut self) -> Vec<String> {
+        match &mut self.source {
+            #[cfg(not(target_arch = "wasm32"))]
+            Source::DeviceQuery(focus) if !focus.focused => vec![],
+            #[cfg(not(target_arch = "wasm32"))]
+            Source::DeviceQuery(_) => DEVICE_STATE.with(|device_state| device_state
+                .get_or_init(DeviceState::new)
+                .get_keys()
+                .iter()
+                .map(keycode_name)
+                .collect()),
+            #[cfg(not(target_arch = "wasm32"))]
+            Source::Terminal(terminal_input) => terminal_input.pressed_keys(),
+            #[cfg(target_arch = "wasm32")]
+            Source::Macroquad(_) => MACROQUAD_KEYS
+                .iter()
+                .filter(|(key_code, _)| macroquad::input::is_key_down(*key_code))
+                .map(|(_, name)| name.to_string())
+                .collect(),
+            Source::Detached => vec![]
+        }
+    }
+
+    // Hex values of the keypad keys that are currently down
+    pub fn pressed_keys(&mut self) -> Vec<u8> {
+        let host_keys = self.host_keys();
+
+        let mut chip_keys: Vec<u8> = host_keys.iter().filter_map(|host_key| self.keymap.chip_key(host_key)).collect();
+        chip_keys.extend(&self.virtual_keys);
+        chip_keys.sort();
+        chip_keys.dedup();
+        chip_keys
+    }
+
+    // Holds a keypad key down until it's released, whatever the keyboard is doing
+    pub fn press_key(&mut self, key: u8) {
+        if !self.virtual_keys.contains(&key) {
+            self.virtual_keys.push(key);
+        }
+    }
+
+    pub fn release_key(&mut self, key: u8) {
+        self.virtual_keys.retain(|pressed| *pressed != key);
+    }
+
+    // Reads any pending input, and returns true if the user asked to quit. Called once a frame,
+    // so the chip's key instructions read what it saw rather than polling themselves
+    pub fn update(&mut self) -> bool {
+        match &mut self.source {
+            #[cfg(not(target_arch = "wasm32"))]
+            Source::Terminal(terminal_input) => {
+                terminal_input.update();
+                terminal_input.quit_requested
+            }
+            #[cfg(not(target_arch = "wasm32"))]
+            Source::DeviceQuery(focus) => {
+                focus.poll();
+                false
+            }
+            #[cfg(target_arch = "wasm32")]
+            Source::Macroquad(focus) => {
+                focus.poll();
+                false
+            }
+            Source::Detached => false
+        }
+    }
+
+    // As of the last update. A terminal that doesn't report focus changes always has it
+    pub fn focused(&self) -> bool {
+        match &self.source {
+            #[cfg(not(target_arch = "wasm32"))]
+            Source::Terminal(terminal_input) => terminal_input.focused,
+            #[cfg(not(target_arch = "wasm32"))]
+            Source::DeviceQuery(focus) => focus.focused,
+            #[cfg(target_arch = "wasm32")]
+            Source::Macroquad(focus) => focus.focused,
+            Source::Detached => true
+        }
+    }
+
+    // Puts the terminal back into normal mode before exiting. Safe to call more than once
+    pub fn restore(&mut self) {
+        match &mut self.source {
+            #[cfg(not(target_arch = "wasm32"))]
+            Source::Terminal(terminal_input) => terminal_input.restore(),
+            _ => ()
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum KeyState {
+    Up,
+    HeldUntil(Instant),
+    Down
+}
+
+// Terminals usually only report key presses, never releases, so a press holds the key down for
+// `key_hold`. Key repeats keep extending the hold while the key stays down. Terminals that support
+// the kitty keyboard protocol do report releases, in which case keys are held until released.
+#[cfg(not(target_arch = "wasm32"))]
+pub struct TerminalInput {
+    key_hold: Duration,
+    keys: HashMap<String, KeyState>,
+    reports_releases: bool,
+    quit_requested: bool,
+    focused: bool,
+    active: bool
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TerminalInput {
+    pub fn new(key_hold: Duration) -> Self {
+        Self {
+            key_hold,
+            keys: HashMap::new(),
+            reports_releases: false,
+            quit_requested: false,
+            focused: true,
+            active: false
+        }
+    }
+
+    fn activate(&mut self) {
+        // If stdin isn't a terminal we can still read whatever it sends, just not in raw mode
+        let _ = terminal::enable_raw_mode();
+        let _ = execute!(std::io::stdout(), event::EnableFocusChange);
+        self.reports_releases = terminal::supports_keyboard_enhancement().unwrap_or(false);
+        if self.reports_releases {
+            let flags = KeyboardEnhancementFlags::REPORT_EVENT_TYPES;
+            let _ = execute!(std::io::stdout(), event::PushKeyboardEnhancementFlags(flags));
+        }
+        self.active = true;
+    }
+
+    fn update(&mut self) {
+        if !self.active {
+            self.activate();
+        }
+
+        while let Ok(true) = event::poll(Duration::ZERO) {
+            match event::read() {
+                Ok(Event::Key(key)) => self.handle_key(key, Instant::now()),
+                Ok(Event::FocusLost) => self.focused = false,
+                Ok(Event::FocusGained) => self.focused = true,
+                _ => ()
+            }
+        }
+    }
+
+    fn handle_key(&mut self, key: KeyEvent, now: Instant) {
+        // Raw mode means Ctrl+C no longer sends SIGINT, so we have to handle it ourselves
+        if key.code == KeyCode::Char('c') && key.modifiers.contains(KeyModifiers::CONTROL) {
+            self.quit_requested = true;
+            return;
+        }
+
+        let Some(host_key) = key_code_name(key.code) else { return };
+
+        let key_state = match key.kind {
+            KeyEventKind::Release => KeyState::Up,
+            _ if self.reports_releases => KeyState::Down,
+            _ => KeyState::HeldUntil(now + self.key_hold)
+        };
+        self.keys.insert(host_key, key_state);
+    }
+
+    // Only reads what update last polled, since this runs for every key instruction
+    fn pressed_keys(&self) -> Vec<String> {
+        self.keys_down_at(Instant::now())
+    }
+
+    fn keys_down_at(&self, now: Instant) -> Vec<String> {
+        let mut keys_down: Vec<String> = self.keys
+            .iter()
+            .filter(|(_, key_state)| match key_state {
+                KeyState::Up => false,
+                KeyState::HeldUntil(release_time) => *release_time > now,
+                KeyState::Down => true
+            })
+            .map(|(host_key, _)| host_key.clone())
+            .collect();
+        keys_down.sort();
+        keys_down
+    }
+
+    fn restore(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        if self.reports_releases {
+            let _ = execute!(std::io::stdout(), event::PopKeyboardEnhancementFlags);
+        }
+        let _ = execute!(std::io::stdout(), event::DisableFocusChange);
+        let _ = terminal::disable_raw_mode();
+        self.active = false;
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl Drop for TerminalInput {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+// Host key names are lowercase, using the character for keys that type one (like "w" or ";")
+// and the key's name otherwise (like "space" or "up")
+#[cfg(not(target_arch = "wasm32"))]
+fn keycode_name(keycode: &Keycode) -> String {
+    let name = match keycode {
+        Keycode::Key0 => "0",
+        Keycode::Key1 => "1",
+        Keycode::Key2 => "2",
+        Keycode::Key3 => "3",
+        Keycode::Key4 => "4",
+        Keycode::Key5 => "5",
+        Keycode::Key6 => "6",
+        Keycode::Key7 => "7",
+        Keycode::Key8 => "8",
+        Keycode::Key9 => "9",
+        Keycode::Grave => "`",
+        Keycode::Minus => "-",
+        Keycode::Equal => "=",
+        Keycode::LeftBracket => "[",
+        Keycode::RightBracket => "]",
+        Keycode::BackSlash => "\\",
+        Keycode::Semicolon => ";",
+        Keycode::Apostrophe => "'",
+        Keycode::Comma => ",",
+        Keycode::Dot => ".",
+        Keycode::Slash => "/",
+        _ => return keycode.to_string().to_lowercase()
+    };
+
+    name.to_string()
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn key_code_name(key_code: KeyCode) -> Option<String> {
+    let name = match key_code {
+        KeyCode::Char(' ') => String::from("space"),
+        KeyCode::Char(character) => character.to_lowercase().to_string(),
+        KeyCode::F(number) => format!("f{}", number),
+        KeyCode::Enter => String::from("enter"),
+        KeyCode::Tab => String::from("tab"),
+        KeyCode::Backspace => String::from("backspace"),
+        KeyCode::Esc => String::from("escape"),
+        KeyCode::Up => String::from("up"),
+        KeyCode::Down => String::from("down"),
+        KeyCode::Left => String::from("left"),
+        KeyCode::Right => String::from("right"),
+        KeyCode::Home => String::from("home"),
+        KeyCode::End => String::from("end"),
+        KeyCode::PageUp => String::from("pageup"),
+        KeyCode::PageDown => String::from("pagedown"),
+        KeyCode::Insert => String::from("insert"),
+        KeyCode::Delete => String::from("delete"),
+        _ => return None
+    };
+
+    Some(name)
+}
+
+// Host key names for the keys macroquad reports, matching the names device_query gives them
+#[cfg(target_arch = "wasm32")]
+const MACROQUAD_KEYS: [(macroquad::input::KeyCode, &str); 80] = {
+    use macroquad::input::KeyCode;
+    [
+        (KeyCode::A, "a"),
+        (KeyCode::B, "b"),
+        (KeyCode::C, "c"),
+        (KeyCode::D, "d"),
+        (KeyCode::E, "e"),
+        (KeyCode::F, "f"),
+        (KeyCode::G, "g"),
+        (KeyCode::H, "h"),
+        (KeyCode::I, "i"),
+        (KeyCode::J, "j"),
+        (KeyCode::K, "k"),
+        (KeyCode::L, "l"),
+        (KeyCode::M, "m"),
+        (KeyCode::N, "n"),
+        (KeyCode::O, "o"),
+        (KeyCode::P, "p"),
+        (KeyCode::Q, "q"),
+        (KeyCode::R, "r"),
+        (KeyCode::S, "s"),
+        (KeyCode::T, "t"),
+        (KeyCode::U, "u"),
+        (KeyCode::V, "v"),
+        (KeyCode::W, "w"),
+        (KeyCode::X, "x"),
+        (KeyCode::Y, "y"),
+        (KeyCode::Z, "z"),
+        (KeyCode::Key0, "0"),
+        (KeyCode::Key1, "1"),
+        (KeyCode::Key2, "2"),
+        (KeyCode::Key3, "3"),
+        (KeyCode::Key4, "4"),
+        (KeyCode::Key5, "5"),
+        (KeyCode::Key6, "6"),
+        (KeyCode::Key7, "7"),
+        (KeyCode::Key8, "8"),
+        (KeyCode::Key9, "9"),
+        (KeyCode::F1, "f1"),
+        (KeyCode::F2, "f2"),
+        (KeyCode::F3, "f3"),
+        (KeyCode::F4, "f4"),
+        (KeyCode::F5, "f5"),
+        (KeyCode::F6, "f6"),
+        (KeyCode::F7, "f7"),
+        (KeyCode::F8, "f8"),
+        (KeyCode::F9, "f9"),
+        (KeyCode::F10, "f10"),
+        (KeyCode::F11, "f11"),
+        (KeyCode::F12, "f12"),
+        (KeyCode::GraveAccent, "`"),
+        (KeyCode::Minus, "-"),
+        (KeyCode::Equal, "="),
+        (KeyCode::LeftBracket, "["),
+        (KeyCode::RightBracket, "]"),
+        (KeyCode::Backslash, "\\"),
+        (KeyCode::Semicolon, ";"),
+        (KeyCode::Apostrophe, "'"),
+        (KeyCode::Comma, ","),
+        (KeyCode::Period, "."),
+        (KeyCode::Slash, "/"),
+        (KeyCode::Space, "space"),
+        (KeyCode::Enter, "enter"),
+        (KeyCode::Tab, "tab"),
+        (KeyCode::Backspace, "backspace"),
+        (KeyCode::Escape, "escape"),
+        (KeyCode::Up, "up"),
+        (KeyCode::Down, "down"),
+        (KeyCode::Left, "left"),
+        (KeyCode::Right, "right"),
+        (KeyCode::Home, "home"),
+        (KeyCode::End, "end"),
+        (KeyCode::PageUp, "pageup"),
+        (KeyCode::PageDown, "pagedown"),
+        (KeyCode::Insert, "insert"),
+        (KeyCode::Delete, "delete"),
+        (KeyCode::LeftShift, "lshift"),
+        (KeyCode::RightShift, "rshift"),
+        (KeyCode::LeftControl, "lcontrol"),
+        (KeyCode::RightControl, "rcontrol"),
+        (KeyCode::LeftAlt, "lalt"),
+        (KeyCode::RightAlt, "ralt"),
+    ]
+};
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_event(character: char, kind: KeyEventKind) -> KeyEvent {
+        KeyEvent::new_with_kind(KeyCode::Char(character), KeyModifiers::NONE, kind)
+    }
+
+    #[test]
+    fn virtual_keys_are_held_until_released() {
+        // Detached, so the test doesn't read the real keyboard
+        let mut input = Input::detached();
+        input.press_key(0xA);
+        input.press_key(5);
+        input.press_key(5);
+        assert_eq!(input.pressed_keys(), vec![5, 0xA]);
+
+        input.release_key(5);
+        assert_eq!(input.pressed_keys(), vec![0xA]);
+    }
+
+    #[test]
+    fn terminal_key_press_is_held_for_key_hold() {
+        let mut input = TerminalInput::new(Duration::from_millis(100));
+        let now = Instant::now();
+
+        input.handle_key(key_event('W', KeyEventKind::Press), now);
+
+        assert_eq!(input.keys_down_at(now), vec!["w"]);
+        assert_eq!(input.keys_down_at(now + Duration::from_millis(99)), vec!["w"]);
+        assert_eq!(input.keys_down_at(now + Duration::from_millis(100)), Vec::<String>::new());
+    }
+
+    #[test]
+    fn terminal_key_is_held_until_released_if_terminal_reports_releases() {
+        let mut input = TerminalInput::new(Duration::from_millis(100));
+        input.reports_releases = true;
+        let now = Instant::now();
+
+        input.handle_key(key_event('v', KeyEventKind::Press), now);
+        assert_eq!(input.keys_down_at(now + Duration::from_secs(10)), vec!["v"]);
+
+        input.handle_key(key_event('v', KeyEventKind::Release), now);
+        assert_eq!(input.keys_down_at(now), Vec::<String>::new());
+    }
+
+    #[test]
+    fn reading_keys_leaves_the_terminal_alone_until_update() {
+        let mut input = Input::new(&DisplayType::Terminal);
+        assert!(input.pressed_keys().is_empty());
+        assert!(input.host_keys().is_empty());
+
+        let Source::Terminal(terminal_input) = &input.source else { panic!("Should read the terminal") };
+        assert!(!terminal_input.active);
+    }
+
+    #[test]
+    fn window_keys_are_ignored_while_it_doesnt_have_focus() {
+        let mut input = Input::new(&DisplayType::Macroquad);
+        assert!(input.focused());
+
+        let Source::DeviceQuery(focus) = &mut input.source else { panic!("Should read the keyboard") };
+        focus.window_minimized_event();
+        assert!(!input.focused());
+        // Without reading the keyboard, which the test machine may not have
+        assert!(input.host_keys().is_empty());
+
+        let Source::DeviceQuery(focus) = &mut input.source else { panic!("Should read the keyboard") };
+        focus.window_restored_event();
+        assert!(input.focused());
+    }
+
+    #[test]
+    fn ctrl_c_requests_quit() {
+        let mut input = TerminalInput::new(Duration::from_millis(100));
+
+        input.handle_key(KeyEvent::new(KeyCode::Char('c'), KeyModifiers::CONTROL), Instant::now());
+
+        assert!(input.quit_requested);
+        assert_eq!(input.keys_down_at(Instant::now()), Vec::<String>::new());
+    }
+
+    #[test]
+    fn host_keys_are_named_the_same_by_both_sources() {
+        assert_eq!(keycode_name(&Keycode::Key4), key_code_name(KeyCode::Char('4')).unwrap());
+        assert_eq!(keycode_name(&Keycode::W), key_code_name(KeyCode::Char('W')).unwrap());
+        assert_eq!(keycode_name(&Keycode::Semicolon), key_code_name(KeyCode::Char(';')).unwrap());
+        assert_eq!(keycode_name(&Keycode::Space), key_code_name(KeyCode::Char(' ')).unwrap());
+        assert_eq!(keycode_name(&Keycode::Up), key_code_name(KeyCode::Up).unwrap());
+        assert_eq!(keycode_name(&Keycode::Escape), key_code_name(KeyCode::Esc).unwrap());
+        assert_eq!(keycode_name(&Keycode::F5), key_code_name(KeyCode::F(5)).unwrap());
+    }
+}
@@ -0,0 +1,180 @@
+// Instructions decoded from their opcodes, so running one is a single match on its kind. X and Y
+// are register numbers, NN and NNN are the opcode's low byte and low 12 bits
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum Instruction {
+    Exit,                                // 0000
+    ClearScreen,                         // 00E0
+    Return,                              // 00EE
+    MachineCall,                         // 0NNN, which can't run outside a COSMAC VIP
+    Jump(u16),                           // 1NNN
+    Call(u16),                           // 2NNN
+    SkipIfEqual(u8, u8),                 // 3XNN
+    SkipIfNotEqual(u8, u8),              // 4XNN
+    SkipIfRegistersEqual(u8, u8),        // 5XY0
+    Load(u8, u8),                        // 6XNN
+    Add(u8, u8),                         // 7XNN
+    Copy(u8, u8),                        // 8XY0
+    Or(u8, u8),                          // 8XY1
+    And(u8, u8),                         // 8XY2
+    Xor(u8, u8),                         // 8XY3
+    AddRegisters(u8, u8),                // 8XY4
+    Subtract(u8, u8),                    // 8XY5
+    ShiftRight(u8, u8),                  // 8XY6
+    SubtractReversed(u8, u8),            // 8XY7
+    ShiftLeft(u8, u8),                   // 8XYE
+    SkipIfRegistersNotEqual(u8, u8),     // 9XY0
+    LoadI(u16),                          // ANNN
+    JumpWithOffset(u8, u16),             // BNNN
+    Random(u8, u8),                      // CXNN
+    Draw(u8, u8, u8),                    // DXYN
+    SkipIfKey(u8),                       // EX9E
+    SkipIfNotKey(u8),                    // EXA1
+    LoadDelayTimer(u8),                  // FX07
+    WaitForKey(u8),                      // FX0A
+    SetDelayTimer(u8),                   // FX15
+    SetSoundTimer(u8),                   // FX18
+    AddToI(u8),                          // FX1E
+    LoadFontCharacter(u8),               // FX29
+    StoreDecimal(u8),                    // FX33
+    StoreRegisters(u8),                  // FX55
+    LoadRegisters(u8),                   // FX65
+    Unknown                              // Does nothing, like data the ROM jumped into
+}
+
+pub fn nibbles(opcode: u16) -> [u8; 4] {
+    [(opcode >> 12) as u8, (opcode >> 8 & 0xF) as u8, (opcode >> 4 & 0xF) as u8, (opcode & 0xF) as u8]
+}
+
+impl Instruction {
+    pub fn decode(opcode: u16) -> Self {
+        let nn = (opcode & 0xFF) as u8;
+        let nnn = opcode & 0xFFF;
+
+        match nibbles(opcode) {
+            [0, 0, 0x0, 0x0] => Instruction::Exit,
+            [0, 0, 0xE, 0x0] => Instruction::ClearScreen,
+            [0, 0, 0xE, 0xE] => Instruction::Return,
+            [0, _, _, _] => Instruction::MachineCall,
+            [1, _, _, _] => Instruction::Jump(nnn),
+            [2, _, _, _] => Instruction::Call(nnn),
+            [3, x, _, _] => Instruction::SkipIfEqual(x, nn),
+            [4, x, _, _] => Instruction::SkipIfNotEqual(x, nn),
+            [5, x, y, 0] => Instruction::SkipIfRegistersEqual(x, y),
+            [6, x, _, _] => Instruction::Load(x, nn),
+            [7, x, _, _] => Instruction::Add(x, nn),
+            [8, x, y, 0x0] => Instruction::Copy(x, y),
+            [8, x, y, 0x1] => Instruction::Or(x, y),
+            [8, x, y, 0x2] => Instruction::And(x, y),
+            [8, x, y, 0x3] => Instruction::Xor(x, y),
+            [8, x, y, 0x4] => Instruction::AddRegisters(x, y),
+            [8, x, y, 0x5] => Instruction::Subtract(x, y),
+            [8, x, y, 0x6] => Instruction::ShiftRight(x, y),
+            [8, x, y, 0x7] => Instruction::SubtractReversed(x, y),
+            [8, x, y, 0xE] => Instruction::ShiftLeft(x, y),
+            [9, x, y, 0] => Instruction::SkipIfRegistersNotEqual(x, y),
+            [0xA, _, _, _] => Instruction::LoadI(nnn),
+            [0xB, x, _, _] => Instruction::JumpWithOffset(x, nnn),
+            [0xC, x, _, _] => Instruction::Random(x, nn),
+            [0xD, x, y, n] => Instruction::Draw(x, y, n),
+            [0xE, x, 0x9, 0xE] => Instruction::SkipIfKey(x),
+            [0xE, x, 0xA, 0x1] => Instruction::SkipIfNotKey(x),
+            [0xF, x, 0x0, 0x7] => Instruction::LoadDelayTimer(x),
+            [0xF, x, 0x0, 0xA] => Instruction::WaitForKey(x),
+            [0xF, x, 0x1, 0x5] => Instruction::SetDelayTimer(x),
+            [0xF, x, 0x1, 0x8] => Instruction::SetSoundTimer(x),
+            [0xF, x, 0x1, 0xE] => Instruction::AddToI(x),
+            [0xF, x, 0x2, 0x9] => Instruction::LoadFontCharacter(x),
+            [0xF, x, 0x3, 0x3] => Instruction::StoreDecimal(x),
+            [0xF, x, 0x5, 0x5] => Instruction::StoreRegisters(x),
+            [0xF, x, 0x6, 0x5] => Instruction::LoadRegisters(x),
+            _ => Instruction::Unknown
+        }
+    }
+}
+
+// Memory decoded ahead of time, one slot per address since jumps can land on odd ones. Slots are
+// decoded the first time they run, and cleared when the memory under them is written, so
+// self-modifying code still runs what it wrote
+#[derive(Clone)]
+pub struct DecodeCache {
+    slots: Vec<Option<Instruction>>
+}
+
+impl DecodeCache {
+    pub fn new(memory_size: usize) -> Self {
+        DecodeCache { slots: vec![None; memory_size] }
+    }
+
+    #[inline]
+    pub fn get(&mut self, memory: &[u8], address: usize) -> Instruction {
+        match self.slots[address] {
+            Some(instruction) => instruction,
+            None => {
+                // The last address runs into the first, like the chip's other reads past the end
+                let opcode = u16::from_be_bytes([memory[address], memory[(address + 1) % memory.len()]]);
+                let instruction = Instruction::decode(opcode);
+                self.slots[address] = Some(instruction);
+                instruction
+            }
+        }
+    }
+
+    // An instruction starting the byte before a write has its second byte changed too
+    pub fn invalidate(&mut self, address: usize, length: usize) {
+        let start = address.saturating_sub(1).min(self.slots.len());
+        let end = address.saturating_add(length).min(self.slots.len());
+        self.slots[start..end].fill(None);
+        if address == 0 && length > 0 {
+            if let Some(last) = self.slots.last_mut() {
+                *last = None;
+            }
+        }
+    }
+
+    pub fn clear(&mut self) {
+        self.slots.fill(None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn decodes_each_kind_of_instruction() {
+        assert_eq!(Instruction::decode(0x0000), Instruction::Exit);
+        assert_eq!(Instruction::decode(0x00E0), Instruction::ClearScreen);
+        assert_eq!(Instruction::decode(0x0123), Instruction::MachineCall);
+        assert_eq!(Instruction::decode(0x2ABC), Instruction::Call(0xABC));
+        assert_eq!(Instruction::decode(0x3A42), Instruction::SkipIfEqual(0xA, 0x42));
+        assert_eq!(Instruction::decode(0x5AB0), Instruction::SkipIfRegistersEqual(0xA, 0xB));
+        assert_eq!(Instruction::decode(0x5AB1), Instruction::Unknown);
+        assert_eq!(Instruction::decode(0x8AB6), Instruction::ShiftRight(0xA, 0xB));
+        assert_eq!(Instruction::decode(0x8AB8), Instruction::Unknown);
+        assert_eq!(Instruction::decode(0xB123), Instruction::JumpWithOffset(1, 0x123));
+        assert_eq!(Instruction::decode(0xD12F), Instruction::Draw(1, 2, 0xF));
+        assert_eq!(Instruction::decode(0xE3A1), Instruction::SkipIfNotKey(3));
+        assert_eq!(Instruction::decode(0xF765), Instruction::LoadRegisters(7));
+        assert_eq!(Instruction::decode(0xF7FF), Instruction::Unknown);
+    }
+
+    #[test]
+    fn writes_clear_the_instructions_they_touch() {
+        let mut memory = [0x12, 0x34, 0x60, 0x01, 0x70, 0x01];
+        let mut cache = DecodeCache::new(memory.len());
+        assert_eq!(cache.get(&memory, 1), Instruction::SkipIfEqual(4, 0x60));
+        assert_eq!(cache.get(&memory, 2), Instruction::Load(0, 1));
+        assert_eq!(cache.get(&memory, 4), Instruction::Add(0, 1));
+
+        memory[2] = 0x61;
+        memory[4] = 0x00;
+        assert_eq!(cache.get(&memory, 2), Instruction::Load(0, 1));
+        cache.invalidate(2, 1);
+        assert_eq!(cache.get(&memory, 1), Instruction::SkipIfEqual(4, 0x61));
+        assert_eq!(cache.get(&memory, 2), Instruction::Load(1, 1));
+        assert_eq!(cache.get(&memory, 4), Instruction::Add(0, 1));
+
+        cache.clear();
+        assert_eq!(cache.get(&memory, 4), Instruction::MachineCall);
+    }
+}
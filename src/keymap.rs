@@ -0,0 +1,261 @@
+use std::collections::HashMap;
+use std::fs;
+use std::path::Path;
+use clap::ValueEnum;
+use serde::Deserialize;
+use serde::de::{self, Deserializer};
+use crate::display::DisplayType;
+
+#[derive(ValueEnum, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum KeymapPreset {
+    Qwerty,
+    Azerty,
+    Dvorak,
+    Colemak
+}
+
+impl KeymapPreset {
+    // The window reads physical keys, named by what they type on QWERTY, so it's already right for
+    // every layout. Only the terminal reads the characters a layout types
+    pub fn for_display(self, display_type: &DisplayType) -> Self {
+        match display_type {
+            DisplayType::Terminal => self,
+            DisplayType::Macroquad => KeymapPreset::Qwerty
+        }
+    }
+
+    // Host keys for each CHIP-8 key, indexed by the CHIP-8 key. Every preset uses the same physical
+    // keys as the QWERTY layout's 1234/QWER/ASDF/ZXCV block
+    fn bindings(self) -> [&'static [&'static str]; 16] {
+        match self {
+            KeymapPreset::Qwerty => [
+                &["x"], &["1"], &["2"], &["3"],
+                &["q"], &["w"], &["e"], &["a"],
+                &["s"], &["d"], &["z"], &["c"],
+                &["4"], &["r"], &["f"], &["v"]
+            ],
+            // The number row needs shift on AZERTY, so the unshifted symbols work as well
+            KeymapPreset::Azerty => [
+                &["x"], &["1", "&"], &["2", "é"], &["3", "\""],
+                &["a"], &["z"], &["e"], &["q"],
+                &["s"], &["d"], &["w"], &["c"],
+                &["4", "'"], &["r"], &["f"], &["v"]
+            ],
+            KeymapPreset::Dvorak => [
+                &["q"], &["1"], &["2"], &["3"],
+                &["'"], &[","], &["."], &["a"],
+                &["o"], &["e"], &[";"], &["j"],
+                &["4"], &["p"], &["u"], &["k"]
+            ],
+            KeymapPreset::Colemak => [
+                &["x"], &["1"], &["2"], &["3"],
+                &["q"], &["w"], &["f"], &["a"],
+                &["r"], &["s"], &["z"], &["c"],
+                &["4"], &["p"], &["t"], &["v"]
+            ]
+        }
+    }
+}
+
+// Maps host key names (like "w", "space" or "up") to the 16 CHIP-8 keys. A CHIP-8 key can have
+// any number of host keys, but each host key only presses one CHIP-8 key.
+#[derive(PartialEq, Clone, Debug)]
+pub struct Keymap {
+    host_keys: HashMap<String, u8>
+}
+
+impl Keymap {
+    pub fn from_preset(preset: KeymapPreset) -> Self {
+        let mut keymap = Keymap { host_keys: HashMap::new() };
+        for (chip_key, host_keys) in preset.bindings().iter().enumerate() {
+            for host_key in host_keys.iter() {
+                keymap.host_keys.insert(host_key.to_string(), chip_key as u8);
+            }
+        }
+        keymap
+    }
+
+    // Replaces the host keys for a CHIP-8 key. The host keys are taken away from any other CHIP-8 key
+    pub fn bind(&mut self, chip_key: u8, host_keys: &[String]) {
+        self.host_keys.retain(|_, bound_key| *bound_key != chip_key);
+        for host_key in host_keys {
+            self.host_keys.insert(host_key.to_lowercase(), chip_key);
+        }
+    }
+
+    pub fn apply(&mut self, bindings: &KeyBindings) {
+        for (chip_key, host_keys) in &bindings.0 {
+            self.bind(*chip_key, host_keys);
+        }
+    }
+
+    pub fn chip_key(&self, host_key: &str) -> Option<u8> {
+        self.host_keys.get(host_key).copied()
+    }
+}
+
+impl Default for Keymap {
+    fn default() -> Self {
+        Keymap::from_preset(KeymapPreset::Qwerty)
+    }
+}
+
+// Host keys for some of the CHIP-8 keys, as written in a keymap file or with `--key`
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct KeyBindings(pub Vec<(u8, Vec<String>)>);
+
+impl<'de> Deserialize<'de> for KeyBindings {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let table: HashMap<String, Vec<String>> = HashMap::deserialize(deserializer)?;
+        let mut bindings = vec![];
+        for (chip_key, host_keys) in table {
+            let chip_key = parse_chip_key(&chip_key).map_err(de::Error::custom)?;
+            bindings.push((chip_key, host_keys));
+        }
+        // Keeps overrides predictable when two CHIP-8 keys list the same host key
+        bindings.sort();
+        Ok(KeyBindings(bindings))
+    }
+}
+
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct RomKeymap {
+    #[serde(default)]
+    pub keys: KeyBindings
+}
+
+// A keymap file, like:
+//
+//   preset = "azerty"
+//
+//   [keys]
+//   5 = ["w", "up"]
+//
+//   [roms."pong.ch8".keys]
+//   1 = ["w"]
+#[derive(Deserialize, Default, Debug)]
+#[serde(deny_unknown_fields)]
+pub struct KeymapFile {
+    pub preset: Option<KeymapPreset>,
+    #[serde(default)]
+    pub keys: KeyBindings,
+    #[serde(default)]
+    pub roms: HashMap<String, RomKeymap>
+}
+
+impl KeymapFile {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let contents = fs::read_to_string(path)
+            .map_err(|error| format!("Can't read keymap {}: {}", path.display(), error))?;
+        toml::from_str(&contents)
+            .map_err(|error| format!("Can't parse keymap {}: {}", path.display(), error))
+    }
+
+    // Builds the keymap for a ROM: the preset, then the file's keys, then the keys for that ROM's file name
+    pub fn keymap_for(&self, preset: Option<KeymapPreset>, display_type: &DisplayType, rom_name: &str) -> Keymap {
+        let preset = preset.or(self.preset).unwrap_or(KeymapPreset::Qwerty);
+        let mut keymap = Keymap::from_preset(preset.for_display(display_type));
+        keymap.apply(&self.keys);
+
+        let rom_file_name = Path::new(rom_name).file_name().and_then(|name| name.to_str()).unwrap_or(rom_name);
+        if let Some(rom_keymap) = self.roms.get(rom_file_name) {
+            keymap.apply(&rom_keymap.keys);
+        }
+        keymap
+    }
+}
+
+fn parse_chip_key(chip_key: &str) -> Result<u8, String> {
+    match u8::from_str_radix(chip_key.trim_start_matches("0x"), 16) {
+        Ok(chip_key) if chip_key < 16 => Ok(chip_key),
+        _ => Err(format!("`{}` isn't a CHIP-8 key, they go from 0 to F", chip_key))
+    }
+}
+
+// Used by clap to parse `--key 5=w,up`
+pub fn parse_key_binding(binding: &str) -> Result<(u8, Vec<String>), String> {
+    let (chip_key, host_keys) = binding
+        .split_once('=')
+        .ok_or_else(|| format!("`{}` should look like 5=w,up", binding))?;
+    let host_keys = host_keys.split(',').filter(|key| !key.is_empty()).map(String::from).collect();
+    Ok((parse_chip_key(chip_key)?, host_keys))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn qwerty_preset_matches_hex_keyboard_layout() {
+        let keymap = Keymap::default();
+
+        assert_eq!(keymap.chip_key("1"), Some(0x1));
+        assert_eq!(keymap.chip_key("4"), Some(0xC));
+        assert_eq!(keymap.chip_key("x"), Some(0x0));
+        assert_eq!(keymap.chip_key("v"), Some(0xF));
+        assert_eq!(keymap.chip_key("p"), None);
+    }
+
+    #[test]
+    fn binding_a_host_key_moves_it_to_the_new_chip_key() {
+        let mut keymap = Keymap::default();
+
+        keymap.bind(0x1, &[String::from("W"), String::from("up")]);
+
+        assert_eq!(keymap.chip_key("w"), Some(0x1));
+        assert_eq!(keymap.chip_key("up"), Some(0x1));
+        assert_eq!(keymap.chip_key("1"), None);
+    }
+
+    #[test]
+    fn keymap_file_applies_preset_then_keys_then_rom_keys() {
+        let keymap_file: KeymapFile = toml::from_str(r#"
+            preset = "dvorak"
+
+            [keys]
+            5 = ["up"]
+            6 = ["down"]
+
+            [roms."pong.ch8".keys]
+            6 = ["s"]
+        "#).unwrap();
+
+        let keymap = keymap_file.keymap_for(None, &DisplayType::Terminal, "roms/pong.ch8");
+        assert_eq!(keymap.chip_key("a"), Some(0x7));
+        assert_eq!(keymap.chip_key("up"), Some(0x5));
+        assert_eq!(keymap.chip_key("down"), None);
+        assert_eq!(keymap.chip_key("s"), Some(0x6));
+
+        let keymap = keymap_file.keymap_for(Some(KeymapPreset::Qwerty), &DisplayType::Terminal, "maze.ch8");
+        assert_eq!(keymap.chip_key("a"), Some(0x7));
+        assert_eq!(keymap.chip_key("down"), Some(0x6));
+    }
+
+    #[test]
+    fn presets_only_change_the_terminal_keys() {
+        let keymap_file = KeymapFile::default();
+
+        let terminal = keymap_file.keymap_for(Some(KeymapPreset::Azerty), &DisplayType::Terminal, "pong.ch8");
+        assert_eq!(terminal.chip_key("a"), Some(0x4));
+        assert_eq!(terminal.chip_key("q"), Some(0x7));
+
+        // The key AZERTY types "a" with is QWERTY's "q", which is what the window reports
+        let window = keymap_file.keymap_for(Some(KeymapPreset::Azerty), &DisplayType::Macroquad, "pong.ch8");
+        assert_eq!(window, Keymap::default());
+    }
+
+    #[test]
+    fn keymap_file_rejects_keys_above_f() {
+        let keymap_file: Result<KeymapFile, _> = toml::from_str("[keys]\n10 = [\"w\"]");
+        assert!(keymap_file.is_err());
+    }
+
+    #[test]
+    fn parses_key_binding_argument() {
+        assert_eq!(parse_key_binding("a=space,z"), Ok((0xA, vec![String::from("space"), String::from("z")])));
+        assert!(parse_key_binding("g=space").is_err());
+        assert!(parse_key_binding("space").is_err());
+    }
+}
@@ -0,0 +1,43 @@
+// The emulator itself, shared by the command line app, the browser build and the libretro core
+#![cfg_attr(target_arch = "wasm32", allow(dead_code, unused_imports))]
+
+pub mod bench;
+pub mod chip;
+pub mod clock;
+pub mod config;
+pub mod database;
+pub mod debugger;
+pub mod disassembler;
+pub mod font;
+pub mod display;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod emulation_thread;
+pub mod expression;
+pub mod emulator;
+pub mod filter;
+pub mod gdb;
+pub mod hooks;
+pub mod hotkeys;
+pub mod input;
+pub mod instruction;
+pub mod keymap;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod libretro;
+pub mod overlay;
+pub mod palette;
+pub mod quirks;
+pub mod rom;
+pub mod scheduler;
+pub mod script;
+pub mod snapshot;
+pub mod summary;
+#[cfg(target_arch = "wasm32")]
+mod sound;
+pub mod terminal;
+pub mod timer;
+pub mod timing;
+pub mod triple_buffer;
+pub mod watch;
+#[cfg(target_arch = "wasm32")]
+pub mod web;
+
@@ -0,0 +1,402 @@
+// The frontend is trusted to pass valid pointers, which the libretro API requires of it
+#![allow(clippy::missing_safety_doc)]
+
+use std::cell::RefCell;
+use std::ffi::{c_char, c_uint, c_void, CStr, CString};
+use std::{ptr, slice};
+use clap::ValueEnum;
+use libretro_sys::{
+    AudioSampleBatchFn, AudioSampleFn, EnvironmentFn, GameInfo, InputDescriptor, InputPollFn, InputStateFn, Message,
+    PixelFormat, Region, SystemAvInfo, SystemInfo, Variable, VideoRefreshFn, API_VERSION, DEVICE_JOYPAD, DEVICE_KEYBOARD,
+    ENVIRONMENT_GET_VARIABLE, ENVIRONMENT_GET_VARIABLE_UPDATE, ENVIRONMENT_SET_INPUT_DESCRIPTORS, ENVIRONMENT_SET_MESSAGE,
+    ENVIRONMENT_SET_PIXEL_FORMAT, ENVIRONMENT_SET_VARIABLES, MEMORY_SYSTEM_RAM
+};
+use libretro_sys::{
+    DEVICE_ID_JOYPAD_A, DEVICE_ID_JOYPAD_B, DEVICE_ID_JOYPAD_DOWN, DEVICE_ID_JOYPAD_L, DEVICE_ID_JOYPAD_L2,
+    DEVICE_ID_JOYPAD_L3, DEVICE_ID_JOYPAD_LEFT, DEVICE_ID_JOYPAD_R, DEVICE_ID_JOYPAD_R2, DEVICE_ID_JOYPAD_R3,
+    DEVICE_ID_JOYPAD_RIGHT, DEVICE_ID_JOYPAD_SELECT, DEVICE_ID_JOYPAD_START, DEVICE_ID_JOYPAD_UP, DEVICE_ID_JOYPAD_X,
+    DEVICE_ID_JOYPAD_Y
+};
+use crate::chip::{Chip, ChipType, STATE_SIZE};
+use crate::config::DEFAULT_TARGET_IPS;
+use crate::display::{DISPLAY_HEIGHT, DISPLAY_SIZE, DISPLAY_WIDTH};
+use crate::keymap::Keymap;
+use crate::palette::{Palette, PaletteType};
+use crate::scheduler::FRAMES_PER_SECOND;
+
+const SAMPLE_RATE: u32 = 44100;
+const SAMPLES_PER_FRAME: usize = (SAMPLE_RATE / FRAMES_PER_SECOND) as usize;
+const TONE_HZ: u32 = 441;
+const VOLUME: i16 = i16::MAX / 4;
+const MESSAGE_FRAMES: c_uint = 180;
+
+const CHIP_TYPE_OPTION: &CStr = c"chip8_chip_type";
+const SPEED_OPTION: &CStr = c"chip8_instructions_per_second";
+const PALETTE_OPTION: &CStr = c"chip8_palette";
+
+// Most games steer with 2, 4, 6 and 8 and act with 5, so those go on the d-pad and A. The other
+// keys fill the rest of the pad in order
+const JOYPAD_KEYS: [(c_uint, u8, &CStr); 16] = [
+    (DEVICE_ID_JOYPAD_UP, 0x2, c"2 (Up)"),
+    (DEVICE_ID_JOYPAD_LEFT, 0x4, c"4 (Left)"),
+    (DEVICE_ID_JOYPAD_RIGHT, 0x6, c"6 (Right)"),
+    (DEVICE_ID_JOYPAD_DOWN, 0x8, c"8 (Down)"),
+    (DEVICE_ID_JOYPAD_A, 0x5, c"5"),
+    (DEVICE_ID_JOYPAD_B, 0x0, c"0"),
+    (DEVICE_ID_JOYPAD_Y, 0x1, c"1"),
+    (DEVICE_ID_JOYPAD_X, 0x3, c"3"),
+    (DEVICE_ID_JOYPAD_L, 0x7, c"7"),
+    (DEVICE_ID_JOYPAD_R, 0x9, c"9"),
+    (DEVICE_ID_JOYPAD_L2, 0xA, c"A"),
+    (DEVICE_ID_JOYPAD_R2, 0xB, c"B"),
+    (DEVICE_ID_JOYPAD_SELECT, 0xC, c"C"),
+    (DEVICE_ID_JOYPAD_START, 0xD, c"D"),
+    (DEVICE_ID_JOYPAD_L3, 0xE, c"E"),
+    (DEVICE_ID_JOYPAD_R3, 0xF, c"F")
+];
+
+#[derive(Default)]
+struct Callbacks {
+    environment: Option<EnvironmentFn>,
+    video_refresh: Option<VideoRefreshFn>,
+    audio_sample_batch: Option<AudioSampleBatchFn>,
+    input_poll: Option<InputPollFn>,
+    input_state: Option<InputStateFn>
+}
+
+impl Callbacks {
+    fn environment(&self, command: c_uint, data: *mut c_void) -> bool {
+        self.environment.is_some_and(|environment| unsafe { environment(command, data) })
+    }
+
+    fn option(&self, key: &CStr) -> Option<String> {
+        let mut variable = Variable { key: key.as_ptr(), value: ptr::null() };
+        if !self.environment(ENVIRONMENT_GET_VARIABLE, &mut variable as *mut Variable as *mut c_void) || variable.value.is_null() {
+            return None;
+        }
+        Some(unsafe { CStr::from_ptr(variable.value) }.to_string_lossy().into_owned())
+    }
+
+    fn key_down(&self, device: c_uint, id: c_uint) -> bool {
+        self.input_state.is_some_and(|input_state| unsafe { input_state(0, device, 0, id) } != 0)
+    }
+}
+
+// A loaded game. The chip is told which keys are down each frame, rather than reading the keyboard
+struct Core {
+    chip: Chip,
+    rom: Vec<u8>,
+    palette: Palette,
+    keyboard: Vec<(c_uint, u8)>, // libretro key codes for the default keymap's letters and digits
+    frame: Vec<u32>,
+    samples: Vec<i16>,
+    sample_clock: u32 // Carries the tone's phase on from frame to frame
+}
+
+impl Core {
+    fn new(rom: Vec<u8>, callbacks: &Callbacks) -> Option<Self> {
+        let mut chip = Chip::headless(DEFAULT_TARGET_IPS, ChipType::CHIP8);
+        chip.load_rom(&rom).ok()?;
+
+        // Letters and digits have their ASCII codes in libretro
+        let keymap = Keymap::default();
+        let keyboard = (b'0'..=b'9')
+            .chain(b'a'..=b'z')
+            .filter_map(|code| keymap.chip_key(&(code as char).to_string()).map(|key| (code as c_uint, key)))
+            .collect();
+
+        let mut core = Core {
+            chip,
+            rom,
+            palette: Palette::default(),
+            keyboard,
+            frame: vec![0; DISPLAY_SIZE],
+            samples: vec![0; SAMPLES_PER_FRAME * 2],
+            sample_clock: 0
+        };
+        core.apply_options(callbacks);
+        Some(core)
+    }
+
+    fn apply_options(&mut self, callbacks: &Callbacks) {
+        if let Some(chip_type) = callbacks.option(CHIP_TYPE_OPTION).and_then(|value| ChipType::from_str(&value, true).ok()) {
+            self.chip.chip_type = chip_type;
+        }
+        if let Some(target_ips) = callbacks.option(SPEED_OPTION).and_then(|value| value.parse().ok()) {
+            self.chip.target_ips = target_ips;
+        }
+        if let Some(palette_type) = callbacks.option(PALETTE_OPTION).and_then(|value| PaletteType::from_str(&value, true).ok()) {
+            self.palette = Palette::from(palette_type);
+        }
+    }
+
+    fn reset(&mut self) {
+        self.chip.reset();
+        let _ = self.chip.load_rom(&self.rom);
+    }
+
+    fn run_frame(&mut self, callbacks: &Callbacks) {
+        let mut options_changed = false;
+        callbacks.environment(ENVIRONMENT_GET_VARIABLE_UPDATE, &mut options_changed as *mut bool as *mut c_void);
+        if options_changed {
+            self.apply_options(callbacks);
+        }
+
+        // Cheats and achievements can write memory through retro_get_memory_data between frames
+        self.chip.forget_decoded();
+
+        if let Some(input_poll) = callbacks.input_poll {
+            unsafe { input_poll() };
+        }
+        let mut keys_down = [false; 16];
+        for (id, key, _) in JOYPAD_KEYS {
+            keys_down[key as usize] |= callbacks.key_down(DEVICE_JOYPAD, id);
+        }
+        for (code, key) in &self.keyboard {
+            keys_down[*key as usize] |= callbacks.key_down(DEVICE_KEYBOARD, *code);
+        }
+        for (key, down) in keys_down.into_iter().enumerate() {
+            if down {
+                self.chip.input.press_key(key as u8);
+            } else {
+                self.chip.input.release_key(key as u8);
+            }
+        }
+
+        let summary = self.chip.run_frame();
+
+        // Only the frame the ROM halted on reports it, so the message is shown once
+        if let Some(halted) = summary.halted {
+            let message = CString::new(halted.to_string()).unwrap_or_default();
+            let mut message = Message { msg: message.as_ptr(), frames: MESSAGE_FRAMES };
+            callbacks.environment(ENVIRONMENT_SET_MESSAGE, &mut message as *mut Message as *mut c_void);
+        }
+
+        self.render(callbacks);
+        self.play_sound(callbacks);
+    }
+
+    // As XRGB8888, in the palette's colors
+    fn render(&mut self, callbacks: &Callbacks) {
+        let color = |[red, green, blue]: [u8; 3]| (red as u32) << 16 | (green as u32) << 8 | blue as u32;
+        let (foreground, background) = (color(self.palette.foreground), color(self.palette.background));
+        for (pixel, lit) in self.frame.iter_mut().zip(self.chip.display.pixels()) {
+            *pixel = if lit { foreground } else { background };
+        }
+
+        if let Some(video_refresh) = callbacks.video_refresh {
+            let pitch = DISPLAY_WIDTH * size_of::<u32>();
+            unsafe { video_refresh(self.frame.as_ptr() as *const c_void, DISPLAY_WIDTH as c_uint, DISPLAY_HEIGHT as c_uint, pitch) };
+        }
+    }
+
+    // A square wave while the sound timer runs, silence otherwise
+    fn play_sound(&mut self, callbacks: &Callbacks) {
+        let sounding = self.chip.sound_timer.get() > 0;
+        for frame in self.samples.chunks_mut(2) {
+            let high = (self.sample_clock * TONE_HZ * 2 / SAMPLE_RATE).is_multiple_of(2);
+            let sample = match (sounding, high) {
+                (false, _) => 0,
+                (true, true) => VOLUME,
+                (true, false) => -VOLUME
+            };
+            frame.fill(sample);
+            self.sample_clock = (self.sample_clock + 1) % SAMPLE_RATE;
+        }
+
+        if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+            unsafe { audio_sample_batch(self.samples.as_ptr(), SAMPLES_PER_FRAME) };
+        }
+    }
+}
+
+// Frontends call the core from a single thread
+thread_local! {
+    static CALLBACKS: RefCell<Callbacks> = RefCell::new(Callbacks::default());
+    static CORE: RefCell<Option<Core>> = const { RefCell::new(None) };
+}
+
+fn with_core<T>(default: T, run: impl FnOnce(&mut Core, &Callbacks) -> T) -> T {
+    CORE.with_borrow_mut(|core| match core {
+        Some(core) => CALLBACKS.with_borrow(|callbacks| run(core, callbacks)),
+        None => default
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    API_VERSION
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_environment(environment: EnvironmentFn) {
+    CALLBACKS.with_borrow_mut(|callbacks| {
+        callbacks.environment = Some(environment);
+
+        let mut options = [
+            Variable { key: CHIP_TYPE_OPTION.as_ptr(), value: c"Chip type; chip8|schip".as_ptr() },
+            Variable {
+                key: SPEED_OPTION.as_ptr(),
+                value: c"Instructions per second; 1200|600|900|1500|1800|2400|3000|6000|12000".as_ptr()
+            },
+            Variable { key: PALETTE_OPTION.as_ptr(), value: c"Palette; amber|green|white|gray".as_ptr() },
+            Variable { key: ptr::null(), value: ptr::null() }
+        ];
+        callbacks.environment(ENVIRONMENT_SET_VARIABLES, options.as_mut_ptr() as *mut c_void);
+    });
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(video_refresh: VideoRefreshFn) {
+    CALLBACKS.with_borrow_mut(|callbacks| callbacks.video_refresh = Some(video_refresh));
+}
+
+// Sound is sent a frame at a time through the batch callback instead
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_audio_sample: AudioSampleFn) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(audio_sample_batch: AudioSampleBatchFn) {
+    CALLBACKS.with_borrow_mut(|callbacks| callbacks.audio_sample_batch = Some(audio_sample_batch));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(input_poll: InputPollFn) {
+    CALLBACKS.with_borrow_mut(|callbacks| callbacks.input_poll = Some(input_poll));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(input_state: InputStateFn) {
+    CALLBACKS.with_borrow_mut(|callbacks| callbacks.input_state = Some(input_state));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    CORE.set(None);
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut SystemInfo) {
+    *info = SystemInfo {
+        library_name: c"RUSTEDCHIP-8".as_ptr(),
+        library_version: concat!(env!("CARGO_PKG_VERSION"), "\0").as_ptr() as *const c_char,
+        valid_extensions: c"ch8|c8|sc8".as_ptr(),
+        need_fullpath: false,
+        block_extract: false
+    };
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut SystemAvInfo) {
+    (*info).geometry.base_width = DISPLAY_WIDTH as c_uint;
+    (*info).geometry.base_height = DISPLAY_HEIGHT as c_uint;
+    (*info).geometry.max_width = DISPLAY_WIDTH as c_uint;
+    (*info).geometry.max_height = DISPLAY_HEIGHT as c_uint;
+    (*info).geometry.aspect_ratio = DISPLAY_WIDTH as f32 / DISPLAY_HEIGHT as f32;
+    (*info).timing.fps = FRAMES_PER_SECOND as f64;
+    (*info).timing.sample_rate = SAMPLE_RATE as f64;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    with_core((), |core, _| core.reset());
+}
+
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    with_core((), |core, callbacks| core.run_frame(callbacks));
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    STATE_SIZE
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    if size < STATE_SIZE {
+        return false;
+    }
+    with_core(false, |core, _| {
+        let state = core.chip.save_state();
+        ptr::copy_nonoverlapping(state.as_ptr(), data as *mut u8, state.len());
+        true
+    })
+}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let state = slice::from_raw_parts(data as *const u8, size);
+    with_core(false, |core, _| core.chip.load_state(state).is_ok())
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const GameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let rom = slice::from_raw_parts((*game).data as *const u8, (*game).size).to_vec();
+
+    CALLBACKS.with_borrow(|callbacks| {
+        let mut pixel_format = PixelFormat::ARGB8888;
+        if !callbacks.environment(ENVIRONMENT_SET_PIXEL_FORMAT, &mut pixel_format as *mut PixelFormat as *mut c_void) {
+            return false;
+        }
+
+        let mut descriptors: Vec<InputDescriptor> = JOYPAD_KEYS
+            .iter()
+            .map(|(id, _, description)| InputDescriptor { port: 0, device: DEVICE_JOYPAD, index: 0, id: *id, description: description.as_ptr() })
+            .collect();
+        descriptors.push(InputDescriptor { port: 0, device: 0, index: 0, id: 0, description: ptr::null() });
+        callbacks.environment(ENVIRONMENT_SET_INPUT_DESCRIPTORS, descriptors.as_mut_ptr() as *mut c_void);
+
+        let core = Core::new(rom, callbacks);
+        let loaded = core.is_some();
+        CORE.set(core);
+        loaded
+    })
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(_game_type: c_uint, _info: *const GameInfo, _num_info: usize) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    CORE.set(None);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    Region::NTSC.to_uint()
+}
+
+// Gives achievements and cheat finders the CHIP-8's memory
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(id: c_uint) -> *mut c_void {
+    if id != MEMORY_SYSTEM_RAM {
+        return ptr::null_mut();
+    }
+    with_core(ptr::null_mut(), |core, _| core.chip.memory.as_mut_ptr() as *mut c_void)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(id: c_uint) -> usize {
+    if id != MEMORY_SYSTEM_RAM {
+        return 0;
+    }
+    with_core(0, |core, _| core.chip.memory.len())
+}
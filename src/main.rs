@@ -1,39 +1,309 @@
-use std::fs;
-use clap::Parser;
+// Most of the command line app isn't used by the browser build
+#![cfg_attr(target_arch = "wasm32", allow(dead_code, unused_imports))]
 
-mod chip;
-mod font;
-mod display;
-mod timer;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process;
+use std::time::Duration;
+use clap::{Parser, Subcommand};
 
-use chip::{Chip, ChipType};
-use display::{DisplayType, window_conf};
+use chip8::bench::{self, parse_count};
+use chip8::chip::{Chip, ChipType};
+use chip8::config::{Config, DEFAULT_TARGET_IPS, Profile, Settings, rom_hash};
+use chip8::database::{Database, RomInfo, default_database_path};
+use chip8::display::{DisplayType, window_conf};
+use chip8::filter::{FilterType, parse_decay};
+use chip8::gdb::GdbStub;
+use chip8::keymap::{KeyBindings, KeymapPreset, parse_key_binding};
+use chip8::palette::{PaletteSetting, PaletteType};
+use chip8::emulator::Emulator;
+use chip8::expression::Expression;
+use chip8::scheduler::Speed;
+use chip8::script::Script;
+use chip8::terminal::TerminalMode;
+use chip8::timing::TimingMode;
+use chip8::watch::{Condition, Watchpoint, parse_breakpoint, parse_condition, parse_watchpoint};
 
+// Options left out fall back to the ROM's profile in the config file, then the config file's
+// defaults, then the default given here
 #[derive(Parser, Debug)]
 #[command(author = "Justin Carruthers", about = "Configurable CHIP-8 (and variants) emulator")]
+#[command(subcommand_negates_reqs = true, args_conflicts_with_subcommands = true)]
 struct Args {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[arg(required = true)]
+    rom_name: Option<String>,
+
+    /// Config file with default options and per-ROM profiles [default: ~/.config/chip8/config.toml]
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// CHIP-8 community database `programs.json`, used to set up known ROMs [default: ~/.config/chip8/programs.json]
+    #[arg(long)]
+    database: Option<PathBuf>,
+
+    /// Run in batches of a sixtieth of this each frame [default: 1200]
+    #[arg(short, long)]
+    target_instructions_per_second: Option<u64>,
+
+    /// `vip` times each instruction like the original COSMAC VIP interpreter, ignoring the target IPS [default: ips]
+    #[arg(long, value_enum)]
+    timing: Option<TimingMode>,
+
+    /// `uncapped` runs as fast as possible [default: normal]
+    #[arg(long, value_enum)]
+    speed: Option<Speed>,
+
+    /// How many frames to run per frame while holding Tab to fast forward [default: 8]
+    #[arg(long)]
+    turbo_factor: Option<u32>,
+
+    /// [default: chip8]
+    #[arg(short, long, value_enum)]
+    chip_type: Option<ChipType>,
+
+    /// [default: macroquad]
+    #[arg(short, long, value_enum)]
+    display_type: Option<DisplayType>,
+
+    /// Smooths sprite flicker in the macroquad display. Doesn't change the emulated screen [default: none]
+    #[arg(short, long, value_enum)]
+    filter: Option<FilterType>,
+
+    /// How much of a pixel's brightness is kept each frame after it turns off, with the phosphor filter [default: 0.6]
+    #[arg(long, value_parser = parse_decay)]
+    phosphor_decay: Option<f32>,
+
+    /// [default: amber]
+    #[arg(short, long, value_enum)]
+    palette: Option<PaletteType>,
+
+    /// Characters used to draw the terminal display. Auto picks the largest that fit in the terminal [default: auto]
+    #[arg(long, value_enum)]
+    terminal_mode: Option<TerminalMode>,
+
+    /// How long a key stays pressed in the terminal display, since most terminals don't report key releases [default: 200]
+    #[arg(long)]
+    key_hold_ms: Option<u64>,
+
+    /// TOML file mapping host keys to CHIP-8 keys, see the README
+    #[arg(long)]
+    keymap: Option<PathBuf>,
+
+    /// Keyboard layout to start the terminal's keymap from. Defaults to qwerty, or the keymap file's preset
+    #[arg(long, value_enum)]
+    keymap_preset: Option<KeymapPreset>,
+
+    /// Host keys for a CHIP-8 key, like `--key 5=w,up`. Can be passed more than once
+    #[arg(long, value_parser = parse_key_binding)]
+    key: Vec<(u8, Vec<String>)>,
+
+    /// Pause at an address, like `--break 2A4`, or only when a condition is true, like `--break "2A4 if v0 == 3"`
+    #[arg(long = "break", value_parser = parse_breakpoint)]
+    breakpoint: Vec<(usize, Option<Expression>)>,
+
+    /// Pause after memory is read or written, like `--watch 300-30F`. Add `:r` or `:w` to only watch reads or writes
+    #[arg(long, value_parser = parse_watchpoint)]
+    watch: Vec<Watchpoint>,
+
+    /// Pause when an expression becomes true, like `--break-when "v3 == 0x10 && i > 0x300"`
+    #[arg(long, value_parser = parse_condition)]
+    break_when: Vec<Condition>,
+
+    /// Rhai script to run alongside the ROM, see the README
+    #[arg(long)]
+    script: Option<PathBuf>,
+
+    /// Listen for a GDB remote debugger on this port on localhost. Starts paused until one attaches
+    #[arg(long)]
+    gdb: Option<u16>,
+
+    /// Run the emulation on its own thread, so rendering can't slow it down. Leaves out the debugger and dropped ROMs
+    #[arg(long, conflicts_with_all = ["script", "gdb"])]
+    threaded: bool,
+}
+
+#[derive(Subcommand, Debug)]
+enum Command {
+    /// Run a ROM headless and uncapped, and report how fast the interpreter is
+    Bench(BenchArgs)
+}
+
+#[derive(clap::Args, Debug)]
+struct BenchArgs {
     rom_name: String,
 
-    #[arg(short, long, default_value_t = 1200)]
-    target_instructions_per_second: u128,
+    /// How many instructions to run
+    #[arg(long, default_value = "50_000_000", value_parser = parse_count)]
+    cycles: u64,
 
-    #[arg(short, long, value_enum, default_value_t = ChipType::CHIP8)]
-    chip_type: ChipType,
+    /// Run in batches of a sixtieth of this each frame [default: 1200]
+    #[arg(short, long)]
+    target_instructions_per_second: Option<u64>,
 
-    #[arg(short, long, value_enum, default_value_t = DisplayType::Macroquad)]
-    display_type: DisplayType,
+    /// [default: chip8]
+    #[arg(short, long, value_enum)]
+    chip_type: Option<ChipType>,
 }
 
-#[macroquad::main(window_conf)]
-async fn main() {
+impl Args {
+    fn profile(&self) -> Profile {
+        Profile {
+            name: None,
+            target_instructions_per_second: self.target_instructions_per_second,
+            timing: self.timing,
+            speed: self.speed,
+            turbo_factor: self.turbo_factor,
+            chip_type: self.chip_type.clone(),
+            display_type: self.display_type.clone(),
+            filter: self.filter,
+            phosphor_decay: self.phosphor_decay,
+            palette: self.palette.map(PaletteSetting::Preset),
+            terminal_mode: self.terminal_mode,
+            key_hold_ms: self.key_hold_ms,
+            keymap: self.keymap.clone(),
+            keymap_preset: self.keymap_preset,
+            keys: KeyBindings(self.key.clone()),
+            quirks: Default::default()
+        }
+    }
+}
+
+fn exit_with_error(error: String) -> ! {
+    eprintln!("{}", error);
+    process::exit(1);
+}
+
+fn print_rom_info(rom_info: &RomInfo) {
+    if rom_info.authors.is_empty() {
+        println!("{}", rom_info.title);
+    } else {
+        println!("{} by {}", rom_info.title, rom_info.authors.join(", "));
+    }
+
+    if rom_info.platform.is_none() {
+        println!("This ROM needs a platform that isn't supported yet, using the default settings");
+    }
+
+    if !rom_info.key_hints.is_empty() {
+        let key_hints: Vec<String> = rom_info.key_hints
+            .iter()
+            .map(|(name, key)| format!("{} = {:X}", name, key))
+            .collect();
+        println!("Keys: {}", key_hints.join(", "));
+    }
+}
+
+fn run_bench(args: &BenchArgs) {
+    let rom = fs::read(&args.rom_name)
+        .unwrap_or_else(|error| exit_with_error(format!("Can't read ROM {}: {}", args.rom_name, error)));
+    let target_ips = args.target_instructions_per_second.map(u128::from).unwrap_or(DEFAULT_TARGET_IPS);
+    let mut chip = Chip::headless(target_ips, args.chip_type.clone().unwrap_or(ChipType::CHIP8));
+    if let Err(error) = chip.load_rom(&rom) {
+        exit_with_error(format!("Can't load ROM {}: {}", args.rom_name, error));
+    }
+
+    print!("{}", bench::run(&mut chip, args.cycles));
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+fn main() {
     let args = Args::parse();
+    if let Some(Command::Bench(bench_args)) = &args.command {
+        run_bench(bench_args);
+        return;
+    }
+    let rom_name = args.rom_name.clone().expect("clap requires a ROM unless there's a subcommand");
+
+    let rom = fs::read(&rom_name)
+        .unwrap_or_else(|error| exit_with_error(format!("Can't read ROM {}: {}", rom_name, error)));
+
+    let config = Config::load(args.config.as_deref()).unwrap_or_else(|error| exit_with_error(error));
+    let mut profile = config.defaults.clone();
+    let mut rom_title = Path::new(&rom_name)
+        .file_name()
+        .map(|file_name| file_name.to_string_lossy().into_owned())
+        .unwrap_or_else(|| rom_name.clone());
+
+    // Known ROMs are set up from the database, between the config's defaults and the ROM's own profile
+    let database_path = args.database.clone().or(config.database.clone());
+    let database = match database_path {
+        Some(path) => Some(Database::load(&path).unwrap_or_else(|error| exit_with_error(error))),
+        None => default_database_path()
+            .filter(|path| path.exists())
+            .map(|path| Database::load(&path).unwrap_or_else(|error| exit_with_error(error)))
+    };
+    match database {
+        Some(database) => match database.lookup(&rom_hash(&rom)) {
+            Some(rom_info) => {
+                print_rom_info(&rom_info);
+                rom_title = rom_info.title.clone();
+                profile.layer(&rom_info.profile);
+            },
+            None => println!("ROM isn't in the database, using the default settings")
+        },
+        None => println!("No ROM database found, using the default settings")
+    }
 
-    let rom = fs::read(&args.rom_name).expect("Cant read the rom");
-    let mut chip = Chip::new(args.target_instructions_per_second, args.chip_type, args.display_type);
+    if let Some(rom_profile) = config.rom_profile(&rom) {
+        if let Some(name) = &rom_profile.name {
+            println!("Using the config profile for {}", name);
+        }
+        profile.layer(rom_profile);
+    }
+    profile.layer(&args.profile());
+    let settings = Settings::resolve(profile, &rom_name).unwrap_or_else(|error| exit_with_error(error));
+
+    let mut chip = Chip::new(settings.target_ips, settings.chip_type, settings.display_type.clone());
+    chip.timing = settings.timing;
+    chip.quirk_overrides = settings.quirks;
+    chip.display.set_filter(settings.filter, settings.phosphor_decay);
+    chip.display.set_palette(settings.palette);
+    chip.display.set_terminal_mode(settings.terminal_mode);
+    chip.input.set_key_hold(Duration::from_millis(settings.key_hold_ms));
+    chip.input.set_keymap(settings.keymap);
+    chip.breakpoints.extend(args.breakpoint.iter().cloned());
+    chip.watchpoints = args.watch.clone();
+    chip.conditions = args.break_when.clone();
+
+    match chip.load_rom(&rom) {
+        Ok(warnings) => {
+            for warning in warnings {
+                eprintln!("Warning: {}", warning);
+            }
+        },
+        Err(error) => exit_with_error(format!("Can't load ROM {}: {}", rom_name, error))
+    }
 
-    chip.load_rom(&rom);
-    loop {
-        chip.step().await;
+    // The terminal display doesn't need a window, so it can run without a window system
+    let mut emulator = Emulator::new(chip, Some(PathBuf::from(&rom_name)), rom, settings.speed, settings.turbo_factor);
+    if let Some(path) = &args.script {
+        emulator.set_script(Script::load(path).unwrap_or_else(|error| exit_with_error(error)));
     }
+    if let Some(port) = args.gdb {
+        let gdb = GdbStub::listen(port).unwrap_or_else(|error| exit_with_error(format!("Can't listen for GDB on port {}: {}", port, error)));
+        if let Ok(address) = gdb.address() {
+            println!("Waiting for GDB on {}", address);
+        }
+        emulator.attach_gdb(gdb);
+    }
+    if settings.display_type == DisplayType::Macroquad {
+        if args.threaded {
+            macroquad::Window::from_config(window_conf(&rom_title), emulator.run_threaded());
+        } else {
+            macroquad::Window::from_config(window_conf(&rom_title), emulator.run());
+        }
+    } else if args.threaded {
+        futures::executor::block_on(emulator.run_threaded());
+    } else {
+        futures::executor::block_on(emulator.run());
+    }
+}
 
+// The browser build starts without a ROM, see web.rs
+#[cfg(target_arch = "wasm32")]
+fn main() {
+    macroquad::Window::from_config(window_conf("Web"), chip8::web::run());
 }
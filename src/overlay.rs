@@ -0,0 +1,145 @@
+use std::time::Duration;
+use crate::chip::ChipType;
+use crate::clock::Instant;
+
+const NOTIFICATION_DURATION: Duration = Duration::from_secs(2);
+
+// Counts events and works out how many happened per second, updated once a second
+pub struct RateCounter {
+    count: u64,
+    since: Instant,
+    rate: u64
+}
+
+impl RateCounter {
+    pub fn new() -> Self {
+        RateCounter { count: 0, since: Instant::now(), rate: 0 }
+    }
+
+    pub fn add(&mut self, count: u64) {
+        self.count += count;
+
+        let elapsed = self.since.elapsed();
+        if elapsed >= Duration::from_secs(1) {
+            self.rate = (self.count as f64 / elapsed.as_secs_f64()).round() as u64;
+            self.count = 0;
+            self.since = Instant::now();
+        }
+    }
+
+    pub fn rate(&self) -> u64 {
+        self.rate
+    }
+}
+
+impl Default for RateCounter {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub struct Status {
+    pub ips: u64,
+    pub target_ips: Option<u128>, // None with VIP timing, which doesn't have a target
+    pub fps: u64,
+    pub chip_type: ChipType,
+    pub paused: bool
+}
+
+// Text drawn over the macroquad display: a script's HUD, the emulator's status while it's toggled
+// on, and notifications for a couple of seconds after something happens
+#[derive(Default)]
+pub struct Overlay {
+    pub visible: bool,
+    pub hud: Vec<String>,
+    status: Option<Status>,
+    notification: Option<(String, Instant)>
+}
+
+// The status and notification are about whatever was running the original, so a copy starts
+// without them
+impl Clone for Overlay {
+    fn clone(&self) -> Self {
+        Overlay { visible: self.visible, hud: self.hud.clone(), status: None, notification: None }
+    }
+}
+
+impl Overlay {
+    pub fn set_status(&mut self, status: Status) {
+        self.status = Some(status);
+    }
+
+    pub fn notify(&mut self, message: String) {
+        self.notification = Some((message, Instant::now()));
+    }
+
+    pub fn lines(&self) -> Vec<String> {
+        self.lines_at(Instant::now())
+    }
+
+    fn lines_at(&self, now: Instant) -> Vec<String> {
+        let mut lines = self.hud.clone();
+
+        if let (true, Some(status)) = (self.visible, &self.status) {
+            lines.push(format!("{:?}", status.chip_type));
+            lines.push(match status.target_ips {
+                Some(target_ips) => format!("{} / {} instructions per second", status.ips, target_ips),
+                None => format!("{} instructions per second, VIP timing", status.ips)
+            });
+            lines.push(format!("{} frames per second", status.fps));
+            if status.paused {
+                lines.push(String::from("Paused"));
+            }
+        }
+
+        if let Some((message, shown_at)) = &self.notification {
+            if now.duration_since(*shown_at) < NOTIFICATION_DURATION {
+                lines.push(message.clone());
+            }
+        }
+
+        lines
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn status() -> Status {
+        Status { ips: 1190, target_ips: Some(1200), fps: 60, chip_type: ChipType::SCHIP, paused: true }
+    }
+
+    #[test]
+    fn status_is_only_shown_while_visible() {
+        let mut overlay = Overlay::default();
+        overlay.set_status(status());
+        assert!(overlay.lines().is_empty());
+
+        overlay.visible = true;
+        assert_eq!(overlay.lines(), vec![
+            "SCHIP",
+            "1190 / 1200 instructions per second",
+            "60 frames per second",
+            "Paused"
+        ]);
+    }
+
+    #[test]
+    fn notifications_expire() {
+        let mut overlay = Overlay::default();
+
+        overlay.notify(String::from("Speed 22 instructions per frame"));
+
+        assert_eq!(overlay.lines(), vec!["Speed 22 instructions per frame"]);
+        assert!(overlay.lines_at(Instant::now() + NOTIFICATION_DURATION).is_empty());
+    }
+
+    #[test]
+    fn hud_is_always_shown_first() {
+        let mut overlay = Overlay { hud: vec![String::from("Score 12")], ..Default::default() };
+        overlay.notify(String::from("Paused"));
+
+        assert_eq!(overlay.lines(), vec!["Score 12", "Paused"]);
+    }
+}
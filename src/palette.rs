@@ -0,0 +1,86 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+#[derive(ValueEnum, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum PaletteType {
+    Amber,
+    Green,
+    White,
+    Gray
+}
+
+// Colors as RGB bytes, so both the macroquad and terminal displays can use them
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Palette {
+    pub foreground: [u8; 3],
+    pub background: [u8; 3]
+}
+
+impl From<PaletteType> for Palette {
+    fn from(palette_type: PaletteType) -> Self {
+        match palette_type {
+            PaletteType::Amber => Palette { foreground: [255, 176, 0], background: [0, 0, 0] },
+            PaletteType::Green => Palette { foreground: [51, 255, 51], background: [0, 0, 0] },
+            PaletteType::White => Palette { foreground: [255, 255, 255], background: [0, 0, 0] },
+            PaletteType::Gray => Palette { foreground: [32, 32, 32], background: [200, 200, 200] }
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Self {
+        Palette::from(PaletteType::Amber)
+    }
+}
+
+// A palette in a config file, either a preset's name or custom colors like "#ffb000"
+#[derive(Deserialize, PartialEq, Clone, Debug)]
+#[serde(untagged)]
+pub enum PaletteSetting {
+    Preset(PaletteType),
+    Custom { foreground: String, background: String }
+}
+
+impl PaletteSetting {
+    pub fn palette(&self) -> Result<Palette, String> {
+        match self {
+            PaletteSetting::Preset(palette_type) => Ok(Palette::from(*palette_type)),
+            PaletteSetting::Custom { foreground, background } => Ok(Palette {
+                foreground: parse_color(foreground)?,
+                background: parse_color(background)?
+            })
+        }
+    }
+}
+
+fn parse_color(color: &str) -> Result<[u8; 3], String> {
+    let invalid_color = || format!("`{}` isn't a color, it should look like #ffb000", color);
+    let hex = color.strip_prefix('#').filter(|hex| hex.len() == 6 && hex.is_ascii()).ok_or_else(invalid_color)?;
+
+    let mut rgb = [0; 3];
+    for (index, channel) in rgb.iter_mut().enumerate() {
+        *channel = u8::from_str_radix(&hex[index * 2..index * 2 + 2], 16).map_err(|_| invalid_color())?;
+    }
+    Ok(rgb)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_palette_parses_hex_colors() {
+        let setting = PaletteSetting::Custom { foreground: String::from("#FFB000"), background: String::from("#102030") };
+        assert_eq!(setting.palette(), Ok(Palette { foreground: [255, 176, 0], background: [16, 32, 48] }));
+    }
+
+    #[test]
+    fn custom_palette_rejects_bad_colors() {
+        let setting = PaletteSetting::Custom { foreground: String::from("ffb000"), background: String::from("#000000") };
+        assert!(setting.palette().is_err());
+
+        let setting = PaletteSetting::Custom { foreground: String::from("#ffb000"), background: String::from("#00000g") };
+        assert!(setting.palette().is_err());
+    }
+}
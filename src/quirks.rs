@@ -0,0 +1,73 @@
+use serde::Deserialize;
+use crate::chip::ChipType;
+
+// Behaviours that differ between the CHIP-8 variants. Each chip type has its own defaults,
+// which can be overridden one at a time for ROMs that expect a mix.
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct Quirks {
+    pub vf_reset: bool, // 8XY1, 8XY2 and 8XY3 reset VF to 0
+    pub memory: bool,   // FX55 and FX65 leave I pointing past the last register
+    pub shifting: bool, // 8XY6 and 8XYE shift VX in place, instead of copying VY into VX first
+    pub jumping: bool   // BNNN jumps to XNN + VX, instead of NNN + V0
+}
+
+impl From<&ChipType> for Quirks {
+    fn from(chip_type: &ChipType) -> Self {
+        match chip_type {
+            ChipType::CHIP8 => Quirks { vf_reset: true, memory: true, shifting: false, jumping: false },
+            ChipType::SCHIP => Quirks { vf_reset: false, memory: false, shifting: true, jumping: true }
+        }
+    }
+}
+
+#[derive(Deserialize, PartialEq, Clone, Copy, Debug, Default)]
+#[serde(deny_unknown_fields)]
+pub struct QuirkOverrides {
+    pub vf_reset: Option<bool>,
+    pub memory: Option<bool>,
+    pub shifting: Option<bool>,
+    pub jumping: Option<bool>
+}
+
+impl QuirkOverrides {
+    pub fn apply(&self, quirks: Quirks) -> Quirks {
+        Quirks {
+            vf_reset: self.vf_reset.unwrap_or(quirks.vf_reset),
+            memory: self.memory.unwrap_or(quirks.memory),
+            shifting: self.shifting.unwrap_or(quirks.shifting),
+            jumping: self.jumping.unwrap_or(quirks.jumping)
+        }
+    }
+
+    // Overrides set in `other` win over the ones set here
+    pub fn layer(&mut self, other: &QuirkOverrides) {
+        self.vf_reset = other.vf_reset.or(self.vf_reset);
+        self.memory = other.memory.or(self.memory);
+        self.shifting = other.shifting.or(self.shifting);
+        self.jumping = other.jumping.or(self.jumping);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn overrides_only_change_quirks_they_set() {
+        let overrides = QuirkOverrides { jumping: Some(true), ..Default::default() };
+
+        let quirks = overrides.apply(Quirks::from(&ChipType::CHIP8));
+
+        assert_eq!(quirks, Quirks { vf_reset: true, memory: true, shifting: false, jumping: true });
+    }
+
+    #[test]
+    fn layered_overrides_win() {
+        let mut overrides = QuirkOverrides { vf_reset: Some(false), memory: Some(false), ..Default::default() };
+
+        overrides.layer(&QuirkOverrides { memory: Some(true), ..Default::default() });
+
+        assert_eq!(overrides.vf_reset, Some(false));
+        assert_eq!(overrides.memory, Some(true));
+    }
+}
@@ -0,0 +1,41 @@
+use std::fmt;
+use crate::chip::ChipType;
+
+#[derive(PartialEq, Debug)]
+pub enum RomError {
+    Empty,
+    TooLarge { size: usize, max_size: usize, chip_type: ChipType }
+}
+
+impl fmt::Display for RomError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomError::Empty => write!(f, "The ROM is empty"),
+            RomError::TooLarge { size, max_size, chip_type } => write!(
+                f,
+                "The ROM is {} bytes, but {:?} ROMs can be at most {} bytes",
+                size, chip_type, max_size
+            )
+        }
+    }
+}
+
+impl std::error::Error for RomError {}
+
+// Problems with a ROM that don't stop it from being loaded
+#[derive(PartialEq, Debug)]
+pub enum RomWarning {
+    OddLength(usize)
+}
+
+impl fmt::Display for RomWarning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RomWarning::OddLength(size) => write!(
+                f,
+                "The ROM is {} bytes, but instructions are 2 bytes each. It might be truncated, or not a CHIP-8 ROM",
+                size
+            )
+        }
+    }
+}
@@ -0,0 +1,103 @@
+use std::thread;
+use std::time::Duration;
+use clap::ValueEnum;
+use serde::Deserialize;
+use crate::clock::Instant;
+
+pub const FRAMES_PER_SECOND: u32 = 60;
+pub const DEFAULT_TURBO_FACTOR: u32 = 8;
+
+// `Normal` runs 60 frames a second, `Uncapped` runs frames as fast as the host can and only
+// stops to draw the display 60 times a second
+#[derive(ValueEnum, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum Speed {
+    Normal,
+    Uncapped
+}
+
+// Paces frames by sleeping until each one is due, instead of spinning between instructions
+pub struct Scheduler {
+    frame_duration: Duration,
+    next_frame: Instant
+}
+
+impl Scheduler {
+    pub fn new() -> Self {
+        let frame_duration = Duration::from_secs(1) / FRAMES_PER_SECOND;
+        Scheduler { frame_duration, next_frame: Instant::now() + frame_duration }
+    }
+
+    pub fn frame_due(&self) -> bool {
+        Instant::now() >= self.next_frame
+    }
+
+    // Natively the loop sleeps until the next frame is due, so every frame is ready
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn frame_ready(&mut self) -> bool {
+        true
+    }
+
+    // Browsers can't sleep, they run the loop at the monitor's refresh rate instead. Frames only
+    // run once they're due, so high refresh rate monitors don't speed games up
+    #[cfg(target_arch = "wasm32")]
+    pub fn frame_ready(&mut self) -> bool {
+        let ready = self.frame_due();
+        if ready {
+            self.advance(Instant::now());
+        }
+        ready
+    }
+
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn wait_for_next_frame(&mut self) {
+        let now = Instant::now();
+        if now < self.next_frame {
+            thread::sleep(self.next_frame - now);
+        }
+        self.advance(now);
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    pub fn wait_for_next_frame(&mut self) {}
+
+    fn advance(&mut self, now: Instant) {
+        if now < self.next_frame {
+            self.next_frame += self.frame_duration;
+        } else {
+            // Running behind, so start again from now rather than rushing frames out to catch up
+            self.next_frame = now + self.frame_duration;
+        }
+    }
+}
+
+impl Default for Scheduler {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn waits_until_next_frame_is_due() {
+        let mut scheduler = Scheduler::new();
+        let started = Instant::now();
+
+        scheduler.wait_for_next_frame();
+        scheduler.wait_for_next_frame();
+
+        assert!(started.elapsed() >= Duration::from_millis(33));
+    }
+
+    #[test]
+    fn doesnt_catch_up_after_falling_behind() {
+        let mut scheduler = Scheduler::new();
+        thread::sleep(Duration::from_millis(50));
+
+        scheduler.wait_for_next_frame();
+        assert!(!scheduler.frame_due());
+    }
+}
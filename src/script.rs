@@ -0,0 +1,259 @@
+use std::cell::{Cell, RefCell};
+use std::fs;
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+use rhai::{CallFnOptions, Dynamic, Engine, EvalAltResult, FuncArgs, Map, Scope, AST, INT};
+use crate::chip::{Chip, ChipType};
+use crate::display::{DISPLAY_HEIGHT, DISPLAY_WIDTH};
+use crate::summary::FrameSummary;
+
+type ScriptResult<T> = Result<T, Box<EvalAltResult>>;
+
+// Why a script wants the emulator to exit
+#[derive(PartialEq, Debug)]
+pub enum ScriptExit {
+    Stop(i32),
+    Error(String)
+}
+
+// A Rhai script attached to a run. The top level runs once when the emulator starts, then the
+// `on_start`, `on_frame` and `on_instruction` functions are called if the script has them. They
+// can keep state in `this`, a map that lasts the whole run.
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    this: Dynamic,
+    // The script's functions reach the emulator's chip through here. It's swapped in while the
+    // script runs, and holds a spare chip the rest of the time
+    chip: Rc<RefCell<Chip>>,
+    stop: Rc<Cell<Option<i32>>>,
+    exit: Option<ScriptExit>,
+    on_instruction: bool
+}
+
+impl Script {
+    pub fn load(path: &Path) -> Result<Self, String> {
+        let source = fs::read_to_string(path).map_err(|error| format!("Can't read script {}: {}", path.display(), error))?;
+        Self::compile(&source).map_err(|error| format!("Can't load script {}: {}", path.display(), error))
+    }
+
+    fn compile(source: &str) -> Result<Self, String> {
+        let chip = Rc::new(RefCell::new(Chip::headless(0, ChipType::CHIP8)));
+        let stop = Rc::new(Cell::new(None));
+        let mut engine = Engine::new();
+        register_functions(&mut engine, &chip, &stop);
+
+        let ast = engine.compile(source).map_err(|error| error.to_string())?;
+        let on_instruction = ast.iter_functions().any(|function| function.name == "on_instruction");
+        Ok(Script { engine, ast, this: Dynamic::from_map(Map::new()), chip, stop, exit: None, on_instruction })
+    }
+
+    // Set once the script stops the emulator or fails, after which it isn't called again
+    pub fn exit(&self) -> Option<&ScriptExit> {
+        self.exit.as_ref()
+    }
+
+    pub fn start(&mut self, chip: &mut Chip) {
+        self.with_chip(chip, |script| {
+            script.engine.run_ast(&script.ast)?;
+            script.call("on_start", ())
+        });
+    }
+
+    // Runs a frame, calling the script's hooks for each instruction and when the frame ends. The
+    // chip is moved in once for the whole frame and only borrowed to run each instruction, so the
+    // hooks can reach it in between
+    pub fn run_frame(&mut self, chip: &mut Chip) -> FrameSummary {
+        mem::swap(chip, &mut self.chip.borrow_mut());
+        let summary = if self.on_instruction {
+            let mut frame = self.chip.borrow_mut().start_frame();
+            while self.chip.borrow_mut().next_instruction(&mut frame) {
+                let (pc, opcode) = {
+                    let chip = self.chip.borrow();
+                    (chip.pc as INT, (chip.memory[chip.pc] as INT) << 8 | chip.memory.get(chip.pc + 1).copied().unwrap_or(0) as INT)
+                };
+                self.run_hook(|script| script.call("on_instruction", (pc, opcode)));
+                self.chip.borrow_mut().run_instruction(&mut frame);
+            }
+            self.chip.borrow_mut().finish_frame(frame)
+        } else {
+            self.chip.borrow_mut().run_frame()
+        };
+        self.run_hook(|script| script.call("on_frame", ()));
+        mem::swap(chip, &mut self.chip.borrow_mut());
+        summary
+    }
+
+    fn with_chip(&mut self, chip: &mut Chip, run: impl FnOnce(&mut Self) -> ScriptResult<()>) {
+        mem::swap(chip, &mut self.chip.borrow_mut());
+        self.run_hook(run);
+        mem::swap(chip, &mut self.chip.borrow_mut());
+    }
+
+    // Runs part of the script with the chip already swapped in
+    fn run_hook(&mut self, run: impl FnOnce(&mut Self) -> ScriptResult<()>) {
+        if self.exit.is_some() {
+            return;
+        }
+
+        let result = run(self);
+        if let Err(error) = result {
+            self.exit = Some(ScriptExit::Error(format!("Script error: {}", error)));
+        } else if let Some(code) = self.stop.get() {
+            self.exit = Some(ScriptExit::Stop(code));
+        }
+    }
+
+    // Hooks the script doesn't have are skipped
+    fn call(&mut self, name: &str, args: impl FuncArgs) -> ScriptResult<()> {
+        if !self.ast.iter_functions().any(|function| function.name == name) {
+            return Ok(());
+        }
+        let options = CallFnOptions::new().eval_ast(false).bind_this_ptr(&mut self.this);
+        self.engine.call_fn_with_options::<Dynamic>(options, &mut Scope::new(), &self.ast, name, args).map(|_| ())
+    }
+}
+
+fn check(value: INT, limit: usize, what: &str) -> ScriptResult<usize> {
+    usize::try_from(value)
+        .ok()
+        .filter(|value| *value < limit)
+        .ok_or_else(|| format!("{} isn't a valid {}", value, what).into())
+}
+
+fn register_functions(engine: &mut Engine, chip: &Rc<RefCell<Chip>>, stop: &Rc<Cell<Option<i32>>>) {
+    let shared = chip.clone();
+    engine.register_fn("peek", move |address: INT| -> ScriptResult<INT> {
+        let chip = shared.borrow();
+        Ok(chip.memory[check(address, chip.memory.len(), "address")?] as INT)
+    });
+    let shared = chip.clone();
+    engine.register_fn("poke", move |address: INT, value: INT| -> ScriptResult<()> {
+        let mut chip = shared.borrow_mut();
+        let address = check(address, chip.memory.len(), "address")?;
+        chip.write_memory(address, &[value as u8]);
+        Ok(())
+    });
+
+    let shared = chip.clone();
+    engine.register_fn("v", move |register: INT| -> ScriptResult<INT> {
+        Ok(shared.borrow().registers[check(register, 16, "register")?] as INT)
+    });
+    let shared = chip.clone();
+    engine.register_fn("set_v", move |register: INT, value: INT| -> ScriptResult<()> {
+        shared.borrow_mut().registers[check(register, 16, "register")?] = value as u8;
+        Ok(())
+    });
+    let shared = chip.clone();
+    engine.register_fn("i", move || shared.borrow().i as INT);
+    let shared = chip.clone();
+    engine.register_fn("set_i", move |value: INT| shared.borrow_mut().i = value as u16 as usize);
+    let shared = chip.clone();
+    engine.register_fn("pc", move || shared.borrow().pc as INT);
+    let shared = chip.clone();
+    engine.register_fn("set_pc", move |value: INT| -> ScriptResult<()> {
+        let mut chip = shared.borrow_mut();
+        chip.pc = check(value, chip.memory.len(), "address")?;
+        Ok(())
+    });
+    let shared = chip.clone();
+    engine.register_fn("delay_timer", move || shared.borrow().delay_timer.get() as INT);
+    let shared = chip.clone();
+    engine.register_fn("sound_timer", move || shared.borrow().sound_timer.get() as INT);
+    let shared = chip.clone();
+    engine.register_fn("instructions", move || shared.borrow().instructions as INT);
+
+    let shared = chip.clone();
+    engine.register_fn("pixel", move |x: INT, y: INT| -> ScriptResult<bool> {
+        let (x, y) = (check(x, DISPLAY_WIDTH, "x")?, check(y, DISPLAY_HEIGHT, "y")?);
+        Ok(shared.borrow().display.get_pixel(x + y * DISPLAY_WIDTH))
+    });
+    let shared = chip.clone();
+    engine.register_fn("press", move |key: INT| -> ScriptResult<()> {
+        shared.borrow_mut().input.press_key(check(key, 16, "key")? as u8);
+        Ok(())
+    });
+    let shared = chip.clone();
+    engine.register_fn("release", move |key: INT| -> ScriptResult<()> {
+        shared.borrow_mut().input.release_key(check(key, 16, "key")? as u8);
+        Ok(())
+    });
+    let shared = chip.clone();
+    engine.register_fn("screenshot", move |path: &str| -> ScriptResult<()> {
+        shared.borrow().display.screenshot(Path::new(path)).map_err(|error| error.into())
+    });
+    let shared = chip.clone();
+    engine.register_fn("hud", move |text: &str| {
+        shared.borrow_mut().display.overlay.hud = text.lines().map(String::from).collect();
+    });
+
+    let shared = stop.clone();
+    engine.register_fn("stop", move || shared.set(Some(0)));
+    let shared = stop.clone();
+    engine.register_fn("stop", move |code: INT| shared.set(Some(code as i32)));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::display::DisplayType;
+
+    fn chip() -> Chip {
+        let mut chip = Chip::new(600, ChipType::CHIP8, DisplayType::Terminal);
+        // Counts up in V0 forever
+        chip.load_rom(&[0x70, 0x01, 0x12, 0x00]).unwrap();
+        chip
+    }
+
+    #[test]
+    fn hooks_read_and_write_the_chip() {
+        let mut chip = chip();
+        let mut script = Script::compile(r#"
+            poke(0x300, 7);
+            fn on_start() { this.frames = 0; this.instructions = 0; }
+            fn on_instruction(pc, opcode) { if opcode == 0x7001 { this.instructions += 1; } }
+            fn on_frame() {
+                this.frames += 1;
+                set_v(1, this.instructions);
+                hud("Frame " + this.frames);
+                if this.frames == 2 { stop(3); }
+            }
+        "#).unwrap();
+
+        script.start(&mut chip);
+        assert_eq!(chip.memory[0x300], 7);
+
+        script.run_frame(&mut chip);
+        assert_eq!(chip.registers[1], 5);
+        assert_eq!(chip.display.overlay.hud, vec!["Frame 1"]);
+        assert_eq!(script.exit(), None);
+
+        script.run_frame(&mut chip);
+        assert_eq!(chip.registers[0], 10);
+        assert_eq!(script.exit(), Some(&ScriptExit::Stop(3)));
+    }
+
+    #[test]
+    fn errors_stop_the_script() {
+        let mut chip = chip();
+        let mut script = Script::compile("fn on_frame() { peek(0x1000) }").unwrap();
+
+        script.run_frame(&mut chip);
+        let Some(ScriptExit::Error(error)) = script.exit() else { panic!("the script should have failed") };
+        assert!(error.contains("4096 isn't a valid address"), "{}", error);
+        assert!(Script::compile("fn on_frame( {").is_err());
+    }
+
+    #[test]
+    fn the_frame_finishes_after_an_instruction_hook_fails() {
+        let mut chip = chip();
+        chip.delay_timer.set(5);
+        let mut script = Script::compile("fn on_instruction(pc, opcode) { if v(0) == 3 { set_v(16, 0); } }").unwrap();
+
+        script.run_frame(&mut chip);
+        assert!(matches!(script.exit(), Some(ScriptExit::Error(_))));
+        assert_eq!(chip.registers[0], 5);
+        assert_eq!(chip.delay_timer.get(), 4);
+    }
+}
@@ -0,0 +1,25 @@
+use crate::display::Plane;
+use crate::summary::Halt;
+use crate::timing::VipClock;
+
+// Everything a running ROM can change, taken with Chip::snapshot and put back with Chip::restore.
+// Unlike save_state it isn't serialized, so it's cheap enough to take every frame. It includes the
+// random number generator, so running the same keys from the same snapshot always ends the same way
+#[derive(PartialEq, Clone, Debug)]
+pub struct Snapshot {
+    pub(crate) memory: [u8; 4096],
+    pub(crate) registers: [u8; 16],
+    pub(crate) stack: [usize; 32],
+    pub(crate) stack_level: usize,
+    pub(crate) plane: Plane,
+    pub(crate) delay_timer: u8,
+    pub(crate) sound_timer: u8,
+    pub(crate) keys_down_while_waiting: Option<Vec<u8>>,
+    pub(crate) i: usize,
+    pub(crate) pc: usize,
+    pub(crate) instructions: u64,
+    pub(crate) vip_clock: VipClock,
+    pub(crate) random: u64,
+    pub(crate) halted: Option<Halt>
+}
+
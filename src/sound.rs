@@ -0,0 +1,53 @@
+use macroquad::audio::{self, PlaySoundParams, Sound};
+
+const SAMPLE_RATE: u32 = 44100;
+const TONE_HZ: u32 = 441; // Divides the sample rate, so a second of it loops without clicking
+const VOLUME: f32 = 0.25;
+
+// Plays a square wave tone while the sound timer is running
+pub struct Beeper {
+    sound: Sound,
+    playing: bool
+}
+
+impl Beeper {
+    pub async fn new() -> Option<Self> {
+        let sound = audio::load_sound_from_bytes(&square_wave_wav()).await.ok()?;
+        Some(Beeper { sound, playing: false })
+    }
+
+    pub fn update(&mut self, sounding: bool) {
+        if sounding && !self.playing {
+            audio::play_sound(&self.sound, PlaySoundParams { looped: true, volume: VOLUME });
+        } else if !sounding && self.playing {
+            audio::stop_sound(&self.sound);
+        }
+        self.playing = sounding;
+    }
+}
+
+// A second of the tone as a 16-bit mono WAV file
+fn square_wave_wav() -> Vec<u8> {
+    let samples: Vec<i16> = (0..SAMPLE_RATE)
+        .map(|sample| if (sample * TONE_HZ * 2 / SAMPLE_RATE).is_multiple_of(2) { i16::MAX / 2 } else { i16::MIN / 2 })
+        .collect();
+    let data_size = samples.len() as u32 * 2;
+
+    let mut wav = Vec::with_capacity(44 + data_size as usize);
+    wav.extend_from_slice(b"RIFF");
+    wav.extend_from_slice(&(36 + data_size).to_le_bytes());
+    wav.extend_from_slice(b"WAVEfmt ");
+    wav.extend_from_slice(&16u32.to_le_bytes()); // Format chunk size
+    wav.extend_from_slice(&1u16.to_le_bytes()); // PCM
+    wav.extend_from_slice(&1u16.to_le_bytes()); // Mono
+    wav.extend_from_slice(&SAMPLE_RATE.to_le_bytes());
+    wav.extend_from_slice(&(SAMPLE_RATE * 2).to_le_bytes()); // Bytes per second
+    wav.extend_from_slice(&2u16.to_le_bytes()); // Bytes per sample
+    wav.extend_from_slice(&16u16.to_le_bytes()); // Bits per sample
+    wav.extend_from_slice(b"data");
+    wav.extend_from_slice(&data_size.to_le_bytes());
+    for sample in samples {
+        wav.extend_from_slice(&sample.to_le_bytes());
+    }
+    wav
+}
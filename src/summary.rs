@@ -0,0 +1,76 @@
+use std::fmt;
+use crate::watch::StopReason;
+
+// Why the ROM stopped for good
+#[derive(PartialEq, Clone, Debug)]
+pub enum Halt {
+    Exited, // With 0000
+    Error(String)
+}
+
+impl fmt::Display for Halt {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Halt::Exited => write!(f, "The ROM exited"),
+            Halt::Error(message) => write!(f, "{}", message)
+        }
+    }
+}
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum SoundChange {
+    Started,
+    Stopped
+}
+
+impl SoundChange {
+    pub fn between(sounding_before: bool, sounding_after: bool) -> Option<Self> {
+        match (sounding_before, sounding_after) {
+            (false, true) => Some(SoundChange::Started),
+            (true, false) => Some(SoundChange::Stopped),
+            _ => None
+        }
+    }
+}
+
+// What one instruction did that a frontend might react to
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct StepResult {
+    pub screen_changed: bool,
+    pub sound: Option<SoundChange>,
+    pub waiting_for_key: bool, // FX0A is waiting, and runs again on the next step
+    pub halted: Option<Halt> // Only on the step that halted, since nothing runs after it
+}
+
+// What a frame did. Sound is compared from the start of the frame to after its timers ticked
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct FrameSummary {
+    pub instructions: u64,
+    pub screen_changed: bool,
+    pub sound: Option<SoundChange>,
+    pub waiting_for_key: bool,
+    pub halted: Option<Halt>,
+    pub stop_reason: Option<StopReason> // A breakpoint or watch stopped the frame early
+}
+
+impl FrameSummary {
+    pub fn add(&mut self, step: StepResult) {
+        self.instructions += 1;
+        self.screen_changed |= step.screen_changed;
+        self.waiting_for_key = step.waiting_for_key;
+        self.halted = self.halted.take().or(step.halted);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sound_changes_only_when_it_starts_or_stops() {
+        assert_eq!(SoundChange::between(false, true), Some(SoundChange::Started));
+        assert_eq!(SoundChange::between(true, false), Some(SoundChange::Stopped));
+        assert_eq!(SoundChange::between(true, true), None);
+        assert_eq!(SoundChange::between(false, false), None);
+    }
+}
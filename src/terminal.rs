@@ -0,0 +1,289 @@
+#[cfg(not(target_arch = "wasm32"))]
+use std::io::{self, Write};
+use clap::ValueEnum;
+use serde::Deserialize;
+#[cfg(not(target_arch = "wasm32"))]
+use crossterm::{cursor, queue, style, terminal};
+use crate::display::{DISPLAY_HEIGHT, DISPLAY_SIZE, DISPLAY_WIDTH};
+use crate::palette::Palette;
+
+// Used when the terminal can't tell us how big it is
+const FALLBACK_TERMINAL_SIZE: (u16, u16) = (80, 24);
+
+#[derive(ValueEnum, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "kebab-case")]
+pub enum TerminalMode {
+    Auto,
+    Block,
+    HalfBlock,
+    Braille
+}
+
+// Characters used to draw the screen, once `TerminalMode::Auto` has been resolved
+#[derive(PartialEq, Clone, Copy, Debug)]
+enum Glyphs {
+    Block,     // Two columns per pixel, so pixels come out roughly square
+    HalfBlock, // Two pixels stacked in each character
+    Braille    // 2x4 pixels in each character
+}
+
+impl Glyphs {
+    // Picks the largest glyphs that let the whole screen fit in the terminal
+    fn for_terminal(mode: TerminalMode, (columns, rows): (u16, u16)) -> Self {
+        match mode {
+            TerminalMode::Block => Glyphs::Block,
+            TerminalMode::HalfBlock => Glyphs::HalfBlock,
+            TerminalMode::Braille => Glyphs::Braille,
+            TerminalMode::Auto => [Glyphs::Block, Glyphs::HalfBlock]
+                .into_iter()
+                .find(|glyphs| {
+                    let (width, height) = glyphs.grid_size();
+                    width * glyphs.cell_width() <= columns as usize && height <= rows as usize
+                })
+                .unwrap_or(Glyphs::Braille)
+        }
+    }
+
+    // How many pixels wide and tall each character is
+    fn pixels_per_cell(self) -> (usize, usize) {
+        match self {
+            Glyphs::Block => (1, 1),
+            Glyphs::HalfBlock => (1, 2),
+            Glyphs::Braille => (2, 4)
+        }
+    }
+
+    // How many terminal columns each cell takes up
+    fn cell_width(self) -> usize {
+        if self == Glyphs::Block { 2 } else { 1 }
+    }
+
+    // How many cells wide and tall the whole screen is
+    fn grid_size(self) -> (usize, usize) {
+        let (pixels_wide, pixels_tall) = self.pixels_per_cell();
+        (DISPLAY_WIDTH / pixels_wide, DISPLAY_HEIGHT / pixels_tall)
+    }
+
+    fn cell(self, frame: &[bool; DISPLAY_SIZE], column: usize, row: usize) -> char {
+        let (pixels_wide, pixels_tall) = self.pixels_per_cell();
+        let pixel = |x: usize, y: usize| frame[DISPLAY_WIDTH * (row * pixels_tall + y) + column * pixels_wide + x];
+
+        match self {
+            Glyphs::Block => if pixel(0, 0) { '█' } else { ' ' },
+            Glyphs::HalfBlock => match (pixel(0, 0), pixel(0, 1)) {
+                (false, false) => ' ',
+                (true, false) => '▀',
+                (false, true) => '▄',
+                (true, true) => '█'
+            },
+            Glyphs::Braille => {
+                // Braille dots are numbered down the left column first, with the bottom row added last
+                const DOTS: [[u32; 2]; 4] = [[0x01, 0x08], [0x02, 0x10], [0x04, 0x20], [0x40, 0x80]];
+                let mut dots = 0;
+                for (y, row_dots) in DOTS.iter().enumerate() {
+                    for (x, dot) in row_dots.iter().enumerate() {
+                        if pixel(x, y) {
+                            dots |= dot;
+                        }
+                    }
+                }
+                char::from_u32(0x2800 + dots).unwrap_or(' ')
+            }
+        }
+    }
+
+    fn cells(self, frame: &[bool; DISPLAY_SIZE]) -> Vec<char> {
+        let (width, height) = self.grid_size();
+        let mut cells = Vec::with_capacity(width * height);
+        for row in 0..height {
+            for column in 0..width {
+                cells.push(self.cell(frame, column, row));
+            }
+        }
+        cells
+    }
+}
+
+// Draws the screen with unicode characters, only rewriting the characters that changed since the last
+// frame. Takes over the terminal with an alternate screen on the first render, and gives it back on drop.
+pub struct TerminalRenderer {
+    mode: TerminalMode,
+    palette: Palette,
+    terminal_size: Option<(u16, u16)>,
+    glyphs: Glyphs,
+    origin: (u16, u16),
+    cells: Vec<char>,
+    active: bool
+}
+
+impl TerminalRenderer {
+    pub fn new(mode: TerminalMode, palette: Palette) -> Self {
+        Self {
+            mode,
+            palette,
+            terminal_size: None,
+            glyphs: Glyphs::Braille,
+            origin: (0, 0),
+            cells: vec![],
+            active: false
+        }
+    }
+
+    // Another renderer with the same settings that hasn't taken the terminal over yet
+    pub fn detached(&self) -> Self {
+        Self::new(self.mode, self.palette)
+    }
+
+    // Changing the mode or palette means everything gets redrawn on the next render
+    pub fn set_mode(&mut self, mode: TerminalMode) {
+        self.mode = mode;
+        self.terminal_size = None;
+    }
+
+    pub fn set_palette(&mut self, palette: Palette) {
+        self.palette = palette;
+        self.terminal_size = None;
+    }
+}
+
+#[cfg(not(target_arch = "wasm32"))]
+impl TerminalRenderer {
+    pub fn render(&mut self, frame: &[bool; DISPLAY_SIZE]) {
+        // Rendering is best effort, there's nowhere better to report a broken stdout
+        let _ = self.try_render(frame);
+    }
+
+    fn try_render(&mut self, frame: &[bool; DISPLAY_SIZE]) -> io::Result<()> {
+        let mut stdout = io::stdout().lock();
+
+        if !self.active {
+            queue!(stdout, terminal::EnterAlternateScreen, cursor::Hide)?;
+            self.active = true;
+        }
+
+        let terminal_size = terminal::size().unwrap_or(FALLBACK_TERMINAL_SIZE);
+        if self.terminal_size != Some(terminal_size) {
+            self.resize(&mut stdout, terminal_size)?;
+        }
+
+        let (width, height) = self.glyphs.grid_size();
+        let (columns, rows) = terminal_size;
+        let cells = self.glyphs.cells(frame);
+        for row in 0..height {
+            let y = self.origin.1 as usize + row;
+            if y >= rows as usize {
+                break;
+            }
+
+            // Only move the cursor when skipping over unchanged cells, printing moves it along for us
+            let mut cursor_in_place = false;
+            for column in 0..width {
+                let index = row * width + column;
+                let x = self.origin.0 as usize + column * self.glyphs.cell_width();
+                if self.cells.get(index) == Some(&cells[index]) || x + self.glyphs.cell_width() > columns as usize {
+                    cursor_in_place = false;
+                    continue;
+                }
+
+                if !cursor_in_place {
+                    queue!(stdout, cursor::MoveTo(x as u16, y as u16))?;
+                    cursor_in_place = true;
+                }
+                for _ in 0..self.glyphs.cell_width() {
+                    queue!(stdout, style::Print(cells[index]))?;
+                }
+            }
+        }
+
+        self.cells = cells;
+        stdout.flush()
+    }
+
+    // Works out the glyphs and position for a new terminal size, then wipes the terminal to redraw everything
+    fn resize(&mut self, stdout: &mut impl Write, terminal_size: (u16, u16)) -> io::Result<()> {
+        let (columns, rows) = terminal_size;
+        self.glyphs = Glyphs::for_terminal(self.mode, terminal_size);
+
+        let (width, height) = self.glyphs.grid_size();
+        let width = (width * self.glyphs.cell_width()) as u16;
+        self.origin = (columns.saturating_sub(width) / 2, rows.saturating_sub(height as u16) / 2);
+        self.terminal_size = Some(terminal_size);
+        self.cells.clear();
+
+        let [fg_red, fg_green, fg_blue] = self.palette.foreground;
+        let [bg_red, bg_green, bg_blue] = self.palette.background;
+        queue!(
+            stdout,
+            style::SetForegroundColor(style::Color::Rgb { r: fg_red, g: fg_green, b: fg_blue }),
+            style::SetBackgroundColor(style::Color::Rgb { r: bg_red, g: bg_green, b: bg_blue }),
+            terminal::Clear(terminal::ClearType::All)
+        )
+    }
+
+    // Puts the terminal back the way we found it. Safe to call more than once
+    pub fn restore(&mut self) {
+        if !self.active {
+            return;
+        }
+
+        let mut stdout = io::stdout().lock();
+        let _ = queue!(stdout, style::ResetColor, cursor::Show, terminal::LeaveAlternateScreen);
+        let _ = stdout.flush();
+        self.active = false;
+        self.terminal_size = None;
+    }
+}
+
+// There's no terminal in the browser
+#[cfg(target_arch = "wasm32")]
+impl TerminalRenderer {
+    pub fn render(&mut self, _frame: &[bool; DISPLAY_SIZE]) {}
+
+    pub fn restore(&mut self) {}
+}
+
+impl Drop for TerminalRenderer {
+    fn drop(&mut self) {
+        self.restore();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn auto_mode_picks_largest_glyphs_that_fit() {
+        assert_eq!(Glyphs::for_terminal(TerminalMode::Auto, (128, 32)), Glyphs::Block);
+        assert_eq!(Glyphs::for_terminal(TerminalMode::Auto, (80, 24)), Glyphs::HalfBlock);
+        assert_eq!(Glyphs::for_terminal(TerminalMode::Auto, (40, 10)), Glyphs::Braille);
+        assert_eq!(Glyphs::for_terminal(TerminalMode::HalfBlock, (40, 10)), Glyphs::HalfBlock);
+    }
+
+    #[test]
+    fn half_blocks_stack_two_pixels() {
+        let mut frame = [false; DISPLAY_SIZE];
+        frame[0] = true; // (0, 0)
+        frame[DISPLAY_WIDTH + 1] = true; // (1, 1)
+        frame[2] = true; // (2, 0)
+        frame[DISPLAY_WIDTH + 2] = true; // (2, 1)
+
+        let cells = Glyphs::HalfBlock.cells(&frame);
+
+        assert_eq!(cells.len(), DISPLAY_WIDTH * DISPLAY_HEIGHT / 2);
+        assert_eq!(&cells[0..4], &['▀', '▄', '█', ' ']);
+    }
+
+    #[test]
+    fn braille_packs_eight_pixels() {
+        let mut frame = [false; DISPLAY_SIZE];
+        frame[0] = true; // (0, 0) is dot 1
+        frame[DISPLAY_WIDTH * 3 + 1] = true; // (1, 3) is dot 8
+
+        let cells = Glyphs::Braille.cells(&frame);
+
+        assert_eq!(cells.len(), DISPLAY_WIDTH * DISPLAY_HEIGHT / 8);
+        assert_eq!(cells[0], '\u{2881}');
+        assert_eq!(cells[1], '\u{2800}');
+    }
+}
@@ -0,0 +1,134 @@
+use clap::ValueEnum;
+use serde::Deserialize;
+
+// How long each instruction takes. `Ips` gives every instruction the same time to hit
+// target_instructions_per_second, `Vip` charges what it cost the original COSMAC VIP interpreter.
+#[derive(ValueEnum, Deserialize, PartialEq, Clone, Copy, Debug)]
+#[serde(rename_all = "lowercase")]
+pub enum TimingMode {
+    Ips,
+    Vip
+}
+
+// The VIP's 1802 ran at 1.76064 MHz with 8 clock pulses per machine cycle, so there are 3668
+// machine cycles between the display interrupts that start each 60 Hz frame
+pub const CYCLES_PER_FRAME: u64 = 3668;
+// Fetching and decoding, which every instruction pays before its own cost
+const FETCH_CYCLES: u64 = 40;
+
+// Approximate machine cycles each instruction took in the VIP interpreter, from disassemblies of it.
+// Costs that depend on a branch use the registers from before the instruction ran.
+pub fn instruction_cycles(nibbles: [u8; 4], registers: &[u8; 16], i: usize) -> u64 {
+    let vx = registers[nibbles[1] as usize];
+    let vy = registers[nibbles[2] as usize];
+    let nn = nibbles[2] << 4 | nibbles[3];
+    let skip = |taken: bool| if taken { 14 } else { 10 };
+
+    let cycles = match nibbles {
+        [0, 0, 0xE, 0x0] => 24 + 3078, // Clears the 256 byte display buffer
+        [0, 0, 0xE, 0xE] => 10,
+        [0, _, _, _] => 0,
+        [1, _, _, _] => 12,
+        [2, _, _, _] => 26,
+        [3, _, _, _] => skip(vx == nn),
+        [4, _, _, _] => skip(vx != nn),
+        [5, _, _, _] => skip(vx == vy) + 4,
+        [6, _, _, _] => 6,
+        [7, _, _, _] => 10,
+        [8, _, _, 0] => 12,
+        [8, _, _, _] => 44,
+        [9, _, _, _] => skip(vx != vy) + 4,
+        [0xA, _, _, _] => 12,
+        [0xB, _, _, _] => 22,
+        [0xC, _, _, _] => 36,
+        [0xD, _, _, n] => draw_cycles(vx, n),
+        [0xE, _, _, _] => 14,
+        [0xF, _, 0x1, 0xE] if (i & 0xFF) + vx as usize > 0xFF => 16 + 4, // Carries into the high byte of I
+        [0xF, _, 0x1, 0xE] => 16,
+        [0xF, _, 0x2, 0x9] => 16,
+        // Converting to decimal takes longer for bigger numbers
+        [0xF, _, 0x3, 0x3] => 80 + 16 * (vx / 100 + vx / 10 % 10 + vx % 10) as u64,
+        [0xF, x, 0x5, 0x5] | [0xF, x, 0x6, 0x5] => 14 + 14 * (x as u64 + 1),
+        [0xF, _, _, _] => 10,
+        _ => 0
+    };
+
+    FETCH_CYCLES + cycles
+}
+
+// Sprites are drawn a byte at a time, so one that isn't lined up with a byte touches two per row
+fn draw_cycles(x: u8, height: u8) -> u64 {
+    let row_cycles = if x.is_multiple_of(8) { 26 } else { 46 };
+    26 + row_cycles * height as u64
+}
+
+// Counts the machine cycles used in the current frame
+#[derive(PartialEq, Clone, Debug, Default)]
+pub struct VipClock {
+    pub cycles: u64,
+    vblank: bool
+}
+
+impl VipClock {
+    // Cycles an instruction ran over the end of the last frame come out of this one
+    pub fn start_frame(&mut self) {
+        self.cycles = self.cycles.saturating_sub(CYCLES_PER_FRAME);
+        self.vblank = true;
+    }
+
+    pub fn charge(&mut self, cycles: u64) {
+        self.cycles += cycles;
+        self.vblank = false;
+    }
+
+    // True until the first instruction after the display interrupt has run
+    pub fn in_vblank(&self) -> bool {
+        self.vblank
+    }
+
+    pub fn frame_done(&self) -> bool {
+        self.cycles >= CYCLES_PER_FRAME
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn skips_cost_more_when_taken() {
+        let mut registers = [0; 16];
+        registers[1] = 0x42;
+
+        assert_eq!(instruction_cycles([3, 1, 4, 2], &registers, 0), FETCH_CYCLES + 14);
+        assert_eq!(instruction_cycles([3, 1, 4, 3], &registers, 0), FETCH_CYCLES + 10);
+    }
+
+    #[test]
+    fn unaligned_sprites_take_longer_to_draw() {
+        let mut registers = [0; 16];
+        registers[1] = 8;
+        registers[2] = 9;
+
+        let aligned = instruction_cycles([0xD, 1, 0, 5], &registers, 0);
+        let unaligned = instruction_cycles([0xD, 2, 0, 5], &registers, 0);
+
+        assert!(unaligned > aligned);
+        assert_eq!(instruction_cycles([0xD, 1, 0, 10], &registers, 0) - aligned, 5 * 26);
+    }
+
+    #[test]
+    fn cycles_past_the_end_of_a_frame_carry_over() {
+        let mut clock = VipClock::default();
+        clock.start_frame();
+        assert!(clock.in_vblank());
+
+        clock.charge(CYCLES_PER_FRAME + 100);
+        assert!(clock.frame_done());
+        assert!(!clock.in_vblank());
+
+        clock.start_frame();
+        assert_eq!(clock.cycles, 100);
+        assert!(clock.in_vblank());
+    }
+}
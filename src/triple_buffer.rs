@@ -0,0 +1,96 @@
+use std::mem;
+use std::sync::{Arc, Mutex};
+
+// Hands the latest value from one thread to another without either waiting on the other. The
+// writer fills the back buffer and the reader looks at the front one, and they only lock to swap
+// theirs with the one in the middle. The reader skips values it was too slow to see
+pub fn triple_buffer<T: Clone>(initial: T) -> (Writer<T>, Reader<T>) {
+    let middle = Arc::new(Mutex::new(Middle { value: initial.clone(), fresh: false }));
+    let writer = Writer { back: initial.clone(), middle: middle.clone() };
+    let reader = Reader { front: initial, middle };
+    (writer, reader)
+}
+
+struct Middle<T> {
+    value: T,
+    fresh: bool // Published since the reader last swapped
+}
+
+pub struct Writer<T> {
+    back: T,
+    middle: Arc<Mutex<Middle<T>>>
+}
+
+impl<T> Writer<T> {
+    // Holds whatever the reader swapped out last, so it should be written over in full
+    pub fn back(&mut self) -> &mut T {
+        &mut self.back
+    }
+
+    pub fn publish(&mut self) {
+        let mut middle = self.middle.lock().unwrap();
+        mem::swap(&mut self.back, &mut middle.value);
+        middle.fresh = true;
+    }
+}
+
+pub struct Reader<T> {
+    front: T,
+    middle: Arc<Mutex<Middle<T>>>
+}
+
+impl<T> Reader<T> {
+    // The latest published value, or the last one read if nothing new has been published
+    pub fn read(&mut self) -> &T {
+        let mut middle = self.middle.lock().unwrap();
+        if middle.fresh {
+            mem::swap(&mut self.front, &mut middle.value);
+            middle.fresh = false;
+        }
+        &self.front
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+    use super::*;
+
+    #[test]
+    fn reader_sees_the_latest_value() {
+        let (mut writer, mut reader) = triple_buffer(0);
+        assert_eq!(*reader.read(), 0);
+
+        *writer.back() = 1;
+        writer.publish();
+        *writer.back() = 2;
+        writer.publish();
+        assert_eq!(*reader.read(), 2);
+        assert_eq!(*reader.read(), 2);
+
+        *writer.back() = 3;
+        assert_eq!(*reader.read(), 2);
+        writer.publish();
+        assert_eq!(*reader.read(), 3);
+    }
+
+    #[test]
+    fn values_cross_threads_whole() {
+        let (mut writer, mut reader) = triple_buffer([0; 64]);
+        let writing = thread::spawn(move || {
+            for value in 1..=1000 {
+                *writer.back() = [value; 64];
+                writer.publish();
+            }
+        });
+
+        let mut last = 0;
+        while last < 1000 {
+            let values = reader.read();
+            assert!(values.iter().all(|value| *value == values[0]));
+            assert!(values[0] >= last);
+            last = values[0];
+        }
+        writing.join().unwrap();
+    }
+}
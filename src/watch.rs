@@ -0,0 +1,157 @@
+use std::fmt;
+use crate::expression::Expression;
+
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub enum AccessKind {
+    Read,
+    Write
+}
+
+// Memory an instruction read or wrote through I, recorded while there are watchpoints
+#[derive(PartialEq, Clone, Copy, Debug)]
+pub struct MemoryAccess {
+    pub start: usize,
+    pub length: usize,
+    pub kind: AccessKind
+}
+
+// Stops after an instruction reads or writes any address from `start` to `end`
+#[derive(PartialEq, Clone, Debug)]
+pub struct Watchpoint {
+    pub start: usize,
+    pub end: usize,
+    pub read: bool,
+    pub write: bool
+}
+
+impl Watchpoint {
+    // The first watched address the access touched
+    pub fn hit(&self, access: &MemoryAccess) -> Option<usize> {
+        let kind_watched = match access.kind {
+            AccessKind::Read => self.read,
+            AccessKind::Write => self.write
+        };
+        let first = access.start.max(self.start);
+        let last = (access.start + access.length).saturating_sub(1).min(self.end);
+        (kind_watched && access.length > 0 && first <= last).then_some(first)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let kinds = match (self.read, self.write) {
+            (true, false) => "r",
+            (false, true) => "w",
+            _ => "rw"
+        };
+        if self.start == self.end {
+            write!(f, "{:03X}:{}", self.start, kinds)
+        } else {
+            write!(f, "{:03X}-{:03X}:{}", self.start, self.end, kinds)
+        }
+    }
+}
+
+// Stops after an instruction makes the expression true. It has to turn false again before it
+// can stop again, so carrying on doesn't stop straight away
+#[derive(Clone, Debug)]
+pub struct Condition {
+    pub expression: Expression,
+    pub was_true: bool
+}
+
+impl Condition {
+    pub fn new(expression: Expression) -> Self {
+        Condition { expression, was_true: false }
+    }
+}
+
+#[derive(PartialEq, Clone, Debug)]
+pub enum StopReason {
+    Breakpoint(usize),
+    Watchpoint { address: usize, kind: AccessKind },
+    Condition(String)
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StopReason::Breakpoint(address) => write!(f, "Breakpoint at {:03X}", address),
+            StopReason::Watchpoint { address, kind: AccessKind::Read } => write!(f, "Read from {:03X}", address),
+            StopReason::Watchpoint { address, kind: AccessKind::Write } => write!(f, "Wrote to {:03X}", address),
+            StopReason::Condition(expression) => write!(f, "{} is true", expression)
+        }
+    }
+}
+
+fn parse_address(address: &str) -> Result<usize, String> {
+    let hex = address.trim().trim_start_matches("0x");
+    usize::from_str_radix(hex, 16)
+        .ok()
+        .filter(|address| *address < 4096)
+        .ok_or_else(|| format!("`{}` isn't an address, it should be hex from 000 to FFF", address.trim()))
+}
+
+// Breakpoints from the command line, like `2A4` or `2A4 if v0 == 3`
+pub fn parse_breakpoint(breakpoint: &str) -> Result<(usize, Option<Expression>), String> {
+    match breakpoint.split_once(" if ") {
+        Some((address, condition)) => Ok((parse_address(address)?, Some(Expression::parse(condition)?))),
+        None => Ok((parse_address(breakpoint)?, None))
+    }
+}
+
+// Watchpoints from the command line, like `300`, `300-30F` or `300-30F:w`. They watch reads
+// and writes unless `:r` or `:w` is given
+pub fn parse_watchpoint(watchpoint: &str) -> Result<Watchpoint, String> {
+    let (range, kinds) = watchpoint.split_once(':').unwrap_or((watchpoint, "rw"));
+    let (start, end) = match range.split_once('-') {
+        Some((start, end)) => (parse_address(start)?, parse_address(end)?),
+        None => (parse_address(range)?, parse_address(range)?)
+    };
+    if end < start {
+        return Err(format!("`{}` ends before it starts", range));
+    }
+
+    let (read, write) = match kinds {
+        "r" => (true, false),
+        "w" => (false, true),
+        "rw" | "wr" => (true, true),
+        _ => return Err(format!("`{}` should be r, w or rw", kinds))
+    };
+    Ok(Watchpoint { start, end, read, write })
+}
+
+pub fn parse_condition(condition: &str) -> Result<Condition, String> {
+    Ok(Condition::new(Expression::parse(condition)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn watchpoint_is_hit_by_overlapping_access_of_watched_kind() {
+        let watchpoint = parse_watchpoint("300-30F:w").unwrap();
+        let write = |start, length| MemoryAccess { start, length, kind: AccessKind::Write };
+
+        assert_eq!(watchpoint.hit(&write(0x2FE, 3)), Some(0x300));
+        assert_eq!(watchpoint.hit(&write(0x30F, 1)), Some(0x30F));
+        assert_eq!(watchpoint.hit(&write(0x2FD, 3)), None);
+        assert_eq!(watchpoint.hit(&write(0x310, 3)), None);
+        assert_eq!(watchpoint.hit(&MemoryAccess { start: 0x300, length: 1, kind: AccessKind::Read }), None);
+    }
+
+    #[test]
+    fn parses_watchpoints_and_breakpoints() {
+        assert_eq!(parse_watchpoint("0x2a0").unwrap(), Watchpoint { start: 0x2A0, end: 0x2A0, read: true, write: true });
+        assert_eq!(parse_watchpoint("300-30F:r").unwrap().to_string(), "300-30F:r");
+        assert!(parse_watchpoint("30F-300").is_err());
+        assert!(parse_watchpoint("1000").is_err());
+        assert!(parse_watchpoint("300:x").is_err());
+
+        let (address, condition) = parse_breakpoint("2A4 if v0 == 3").unwrap();
+        assert_eq!(address, 0x2A4);
+        assert_eq!(condition.unwrap().to_string(), "v0 == 3");
+        assert!(parse_breakpoint("2A4 if").is_err());
+    }
+}
@@ -0,0 +1,14 @@
+use macroquad::miniquad::date;
+use crate::chip::{Chip, ChipType};
+use crate::config::DEFAULT_TARGET_IPS;
+use crate::display::DisplayType;
+use crate::emulator::Emulator;
+use crate::scheduler::{DEFAULT_TURBO_FACTOR, Speed};
+
+// There's no command line or file system in the browser, so the emulator starts with the default
+// settings and waits for a ROM to be dropped on the page or picked with its file picker
+pub async fn run() {
+    macroquad::rand::srand(date::now() as u64);
+    let chip = Chip::new(DEFAULT_TARGET_IPS, ChipType::CHIP8, DisplayType::Macroquad);
+    Emulator::new(chip, None, vec![], Speed::Normal, DEFAULT_TURBO_FACTOR).run().await;
+}
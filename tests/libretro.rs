@@ -0,0 +1,266 @@
+// Runs the libretro core through a tiny frontend that loads the shared library, like RetroArch
+// would. Each test thread gets its own frontend and core, since both keep their state per thread
+use std::cell::RefCell;
+use std::ffi::{c_uint, c_void, CStr, CString};
+use std::{fs, ptr, slice};
+use libloading::Library;
+use libretro_sys::{
+    GameInfo, InputDescriptor, Message, SystemInfo, Variable, DEVICE_ID_JOYPAD_A, DEVICE_JOYPAD,
+    ENVIRONMENT_GET_VARIABLE, ENVIRONMENT_GET_VARIABLE_UPDATE, ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+    ENVIRONMENT_SET_MESSAGE, ENVIRONMENT_SET_PIXEL_FORMAT, ENVIRONMENT_SET_VARIABLES, MEMORY_SYSTEM_RAM
+};
+
+const AMBER: u32 = 0xFFB000;
+const GREEN: u32 = 0x33FF33;
+
+#[derive(Default)]
+struct Frontend {
+    pixel_format: Option<c_uint>,
+    options: Vec<(CString, CString)>,
+    input_descriptors: usize,
+    messages: Vec<String>,
+    buttons: Vec<c_uint>,
+    frame: Vec<u32>,
+    audio_frames: usize,
+    loudest_sample: i16
+}
+
+thread_local! {
+    static FRONTEND: RefCell<Frontend> = RefCell::new(Frontend::default());
+}
+
+unsafe extern "C" fn environment(command: c_uint, data: *mut c_void) -> bool {
+    FRONTEND.with_borrow_mut(|frontend| match command {
+        ENVIRONMENT_SET_PIXEL_FORMAT => {
+            frontend.pixel_format = Some(*(data as *const c_uint));
+            true
+        },
+        // Options start on the first of their values, like "Palette; amber|green"
+        ENVIRONMENT_SET_VARIABLES => {
+            let mut variable = data as *const Variable;
+            while !(*variable).key.is_null() {
+                let key = CStr::from_ptr((*variable).key).to_owned();
+                let value = CStr::from_ptr((*variable).value).to_str().unwrap();
+                let default = value.split_once("; ").unwrap().1.split('|').next().unwrap();
+                frontend.options.push((key, CString::new(default).unwrap()));
+                variable = variable.add(1);
+            }
+            true
+        },
+        ENVIRONMENT_GET_VARIABLE => {
+            let variable = data as *mut Variable;
+            let key = CStr::from_ptr((*variable).key);
+            match frontend.options.iter().find(|(option, _)| option.as_c_str() == key) {
+                Some((_, value)) => {
+                    (*variable).value = value.as_ptr();
+                    true
+                },
+                None => false
+            }
+        },
+        ENVIRONMENT_GET_VARIABLE_UPDATE => {
+            *(data as *mut bool) = false;
+            true
+        },
+        ENVIRONMENT_SET_INPUT_DESCRIPTORS => {
+            let descriptors = data as *const InputDescriptor;
+            while !(*descriptors.add(frontend.input_descriptors)).description.is_null() {
+                frontend.input_descriptors += 1;
+            }
+            true
+        },
+        ENVIRONMENT_SET_MESSAGE => {
+            let message = &*(data as *const Message);
+            frontend.messages.push(CStr::from_ptr(message.msg).to_string_lossy().into_owned());
+            true
+        },
+        _ => false
+    })
+}
+
+unsafe extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    assert_eq!(pitch, width as usize * 4);
+    let pixels = slice::from_raw_parts(data as *const u32, (width * height) as usize);
+    FRONTEND.with_borrow_mut(|frontend| frontend.frame = pixels.to_vec());
+}
+
+unsafe extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+unsafe extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = slice::from_raw_parts(data, frames * 2);
+    FRONTEND.with_borrow_mut(|frontend| {
+        frontend.audio_frames += frames;
+        frontend.loudest_sample = samples.iter().map(|sample| sample.abs()).fold(frontend.loudest_sample, i16::max);
+    });
+    frames
+}
+
+unsafe extern "C" fn input_poll() {}
+
+unsafe extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    FRONTEND.with_borrow(|frontend| (port == 0 && device == DEVICE_JOYPAD && frontend.buttons.contains(&id)) as i16)
+}
+
+struct Core {
+    library: Library
+}
+
+impl Core {
+    // Cargo builds the core into the deps directory the test runs from
+    fn load() -> Self {
+        let deps = std::env::current_exe().unwrap().parent().unwrap().to_path_buf();
+        let path = deps.join(libloading::library_filename("chip8"));
+        let library = unsafe { Library::new(&path) }.unwrap_or_else(|error| panic!("Can't load {}: {}", path.display(), error));
+        let core = Core { library };
+
+        unsafe {
+            core.function::<unsafe extern "C" fn(libretro_sys::EnvironmentFn)>(b"retro_set_environment")(environment);
+            core.function::<unsafe extern "C" fn(libretro_sys::VideoRefreshFn)>(b"retro_set_video_refresh")(video_refresh);
+            core.function::<unsafe extern "C" fn(libretro_sys::AudioSampleFn)>(b"retro_set_audio_sample")(audio_sample);
+            core.function::<unsafe extern "C" fn(libretro_sys::AudioSampleBatchFn)>(b"retro_set_audio_sample_batch")(audio_sample_batch);
+            core.function::<unsafe extern "C" fn(libretro_sys::InputPollFn)>(b"retro_set_input_poll")(input_poll);
+            core.function::<unsafe extern "C" fn(libretro_sys::InputStateFn)>(b"retro_set_input_state")(input_state);
+            core.function::<unsafe extern "C" fn()>(b"retro_init")();
+        }
+        core
+    }
+
+    unsafe fn function<T: Copy>(&self, name: &[u8]) -> T {
+        *self.library.get::<T>(name).unwrap()
+    }
+
+    fn load_game(&self, rom: &[u8]) -> bool {
+        let game = GameInfo { path: ptr::null(), data: rom.as_ptr() as *const c_void, size: rom.len(), meta: ptr::null() };
+        unsafe { self.function::<unsafe extern "C" fn(*const GameInfo) -> bool>(b"retro_load_game")(&game) }
+    }
+
+    fn run(&self, frames: usize) {
+        for _ in 0..frames {
+            unsafe { self.function::<unsafe extern "C" fn()>(b"retro_run")() };
+        }
+    }
+
+    fn memory(&self) -> Vec<u8> {
+        unsafe {
+            let data = self.function::<unsafe extern "C" fn(c_uint) -> *mut c_void>(b"retro_get_memory_data")(MEMORY_SYSTEM_RAM);
+            let size = self.function::<unsafe extern "C" fn(c_uint) -> usize>(b"retro_get_memory_size")(MEMORY_SYSTEM_RAM);
+            slice::from_raw_parts(data as *const u8, size).to_vec()
+        }
+    }
+
+    fn serialize(&self) -> Vec<u8> {
+        unsafe {
+            let size = self.function::<unsafe extern "C" fn() -> usize>(b"retro_serialize_size")();
+            let mut state = vec![0; size];
+            assert!(self.function::<unsafe extern "C" fn(*mut c_void, usize) -> bool>(b"retro_serialize")(state.as_mut_ptr() as *mut c_void, size));
+            state
+        }
+    }
+
+    fn unserialize(&self, state: &[u8]) -> bool {
+        unsafe { self.function::<unsafe extern "C" fn(*const c_void, usize) -> bool>(b"retro_unserialize")(state.as_ptr() as *const c_void, state.len()) }
+    }
+}
+
+impl Drop for Core {
+    fn drop(&mut self) {
+        unsafe {
+            self.function::<unsafe extern "C" fn()>(b"retro_unload_game")();
+            self.function::<unsafe extern "C" fn()>(b"retro_deinit")();
+        }
+    }
+}
+
+fn frame() -> Vec<u32> {
+    FRONTEND.with_borrow(|frontend| frontend.frame.clone())
+}
+
+#[test]
+fn runs_a_rom_and_draws_its_frames() {
+    let core = Core::load();
+    let mut info = unsafe { std::mem::zeroed::<SystemInfo>() };
+    unsafe { core.function::<unsafe extern "C" fn(*mut SystemInfo)>(b"retro_get_system_info")(&mut info) };
+    assert_eq!(unsafe { CStr::from_ptr(info.library_name) }, c"RUSTEDCHIP-8");
+
+    let rom = fs::read("test_roms/ibm_logo.ch8").unwrap();
+    assert!(core.load_game(&rom));
+    core.run(10);
+
+    FRONTEND.with_borrow(|frontend| {
+        assert_eq!(frontend.pixel_format, Some(1)); // XRGB8888
+        assert_eq!(frontend.input_descriptors, 16);
+        assert_eq!(frontend.frame.len(), 64 * 32);
+        assert_eq!(frontend.audio_frames, 735 * 10);
+    });
+    let frame = frame();
+    assert!(frame.contains(&AMBER));
+    assert!(frame.contains(&0));
+    assert_eq!(core.memory()[0x200..0x200 + rom.len()], rom[..]);
+}
+
+#[test]
+fn options_are_read_when_the_game_loads() {
+    let core = Core::load();
+    FRONTEND.with_borrow_mut(|frontend| {
+        let palette = frontend.options.iter_mut().find(|(key, _)| key.as_c_str() == c"chip8_palette").unwrap();
+        palette.1 = CString::new("green").unwrap();
+    });
+
+    assert!(core.load_game(&fs::read("test_roms/ibm_logo.ch8").unwrap()));
+    core.run(10);
+    assert!(frame().contains(&GREEN));
+}
+
+#[test]
+fn joypad_buttons_press_keys() {
+    let core = Core::load();
+    // Waits for a key, then stores it at 0x300
+    assert!(core.load_game(&[0xF0, 0x0A, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x06]));
+
+    FRONTEND.with_borrow_mut(|frontend| frontend.buttons = vec![DEVICE_ID_JOYPAD_A]);
+    core.run(2);
+    assert_eq!(core.memory()[0x300], 0);
+
+    // FX0A carries on once the key is released
+    FRONTEND.with_borrow_mut(|frontend| frontend.buttons.clear());
+    core.run(2);
+    assert_eq!(core.memory()[0x300], 5);
+}
+
+#[test]
+fn save_states_restore_the_game() {
+    let core = Core::load();
+    // Counts up in V0 and stores it at 0x300
+    assert!(core.load_game(&[0x70, 0x01, 0xA3, 0x00, 0xF0, 0x55, 0x12, 0x00]));
+    core.run(1);
+    let state = core.serialize();
+    let saved = core.memory()[0x300];
+
+    core.run(5);
+    assert_ne!(core.memory()[0x300], saved);
+    assert!(core.unserialize(&state));
+    assert_eq!(core.memory()[0x300], saved);
+    assert!(!core.unserialize(&state[1..]));
+}
+
+#[test]
+fn beeps_while_the_sound_timer_runs() {
+    let core = Core::load();
+    // Sets the sound timer, then loops
+    assert!(core.load_game(&[0x60, 0x02, 0xF0, 0x18, 0x12, 0x04]));
+    core.run(1);
+    assert!(FRONTEND.with_borrow(|frontend| frontend.loudest_sample) > 0);
+
+    // Timers tick at the end of each frame, so the second frame ends silent
+    FRONTEND.with_borrow_mut(|frontend| frontend.loudest_sample = 0);
+    core.run(1);
+    assert_eq!(FRONTEND.with_borrow(|frontend| frontend.loudest_sample), 0);
+}
+
+#[test]
+fn ending_rom_shows_a_message_instead_of_exiting() {
+    let core = Core::load();
+    assert!(core.load_game(&[0x00, 0x00]));
+    core.run(3);
+    assert_eq!(FRONTEND.with_borrow(|frontend| frontend.messages.clone()), vec!["The ROM exited"]);
+}